@@ -1,8 +1,8 @@
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{Sample, SampleFormat, StreamConfig, InputCallbackInfo};
 use anyhow::{Result, anyhow};
-use tokio::sync::mpsc;
 use crate::models::AudioChunk;
+use crate::pipeline::{self, PipelineReceiver, PipelineSender, QueueConfig};
 use std::sync::{Arc, Mutex};
 
 pub struct AudioCapture {
@@ -30,7 +30,7 @@ impl AudioCapture {
         })
     }
 
-    pub fn start_recording(&mut self) -> Result<mpsc::UnboundedReceiver<AudioChunk>> {
+    pub fn start_recording(&mut self, queue: QueueConfig) -> Result<PipelineReceiver<AudioChunk>> {
         println!("AudioCapture: Starting REAL microphone capture...");
 
         let host = cpal::default_host();
@@ -43,7 +43,7 @@ impl AudioCapture {
 
         println!("AudioCapture: Using device with format: {:?}", config);
 
-        let (tx, rx) = mpsc::unbounded_channel();
+        let (tx, rx) = pipeline::channel(queue);
         let is_recording = Arc::clone(&self.is_recording);
         *is_recording.lock().unwrap() = true;
 
@@ -74,7 +74,7 @@ impl AudioCapture {
         &self,
         device: &cpal::Device,
         config: &StreamConfig,
-        tx: mpsc::UnboundedSender<AudioChunk>,
+        tx: PipelineSender<AudioChunk>,
         is_recording: Arc<Mutex<bool>>,
     ) -> Result<cpal::Stream>
     where
@@ -89,36 +89,37 @@ impl AudioCapture {
         // Buffer per accumulare audio (100ms di audio)
         let chunk_size = (sample_rate as usize) / 10; // 100ms chunks
         let mut buffer = Vec::with_capacity(chunk_size);
-        let mut start_time = std::time::Instant::now();
+        let mut samples_sent: u64 = 0;
+        let mut tx = Some(tx);
 
         let stream = device.build_input_stream(
             config,
             move |data: &[T], _info: &InputCallbackInfo| {
                 if !*is_recording.lock().unwrap() {
+                    // Flush the partial chunk and drop the sender so the queue closes
+                    if let Some(sender) = tx.take() {
+                        if !buffer.is_empty() {
+                            let _ = sender.send_blocking(Self::take_chunk(&mut buffer, &mut samples_sent, sample_rate));
+                        }
+                        println!("AudioCapture: Recording stopped, audio queue closed");
+                    }
                     return;
                 }
 
+                let Some(sender) = tx.as_ref() else { return };
+
                 // Convert samples to f32 and accumulate
                 for &sample in data {
                     let sample_f32: f32 = f32::from_sample(sample);
                     buffer.push(sample_f32);
 
                     if buffer.len() >= chunk_size {
-                        let timestamp = start_time.elapsed().as_secs_f64();
+                        let chunk = Self::take_chunk(&mut buffer, &mut samples_sent, sample_rate);
 
-                        let chunk = AudioChunk {
-                            data: buffer.clone(),
-                            sample_rate,
-                            timestamp,
-                        };
-
-                        if let Err(_) = tx.send(chunk) {
-                            println!("AudioCapture: Failed to send audio chunk, receiver dropped");
+                        if let Err(e) = sender.send_blocking(chunk) {
+                            println!("AudioCapture: Failed to send audio chunk: {}", e);
                             return;
                         }
-
-                        buffer.clear();
-                        start_time = std::time::Instant::now();
                     }
                 }
             },
@@ -131,6 +132,21 @@ impl AudioCapture {
         Ok(stream)
     }
 
+    /// Empties `buffer` into a chunk stamped with its offset from the start of
+    /// the recording, so gaps left by dropped chunks stay visible downstream.
+    fn take_chunk(buffer: &mut Vec<f32>, samples_sent: &mut u64, sample_rate: u32) -> AudioChunk {
+        let timestamp = *samples_sent as f64 / sample_rate as f64;
+        *samples_sent += buffer.len() as u64;
+
+        AudioChunk {
+            data: std::mem::take(buffer),
+            sample_rate,
+            timestamp,
+        }
+    }
+
+    /// Stops capture. The stream callback flushes the partially filled chunk
+    /// and closes the audio queue on its next invocation.
     pub fn stop_recording(&mut self) -> Result<()> {
         *self.is_recording.lock().unwrap() = false;
         Ok(())
@@ -0,0 +1,716 @@
+use anyhow::{Result, anyhow, bail};
+use std::collections::HashSet;
+use crate::models::{label_key, MeetingNotes, Transcription, TranscriptionEdit, TranscriptionSegment, TokenUsage, WordConfidence};
+use crate::transcription::analysis::{analysis_segments, join_text};
+
+/// Longest title accepted from an edit or from title generation.
+pub const MAX_TITLE_CHARS: usize = 120;
+
+/// Applies `edits` in order, then updates what is derived from the edited
+/// fields: the plain text, chapter contents and confidence. When any edit
+/// is invalid the transcription is left unchanged.
+pub fn apply(transcription: &mut Transcription, edits: &[TranscriptionEdit]) -> Result<()> {
+    let mut edited = transcription.clone();
+    let mut text_changed = false;
+    let mut bounds_changed = false;
+
+    for (i, edit) in edits.iter().enumerate() {
+        apply_edit(&mut edited, edit).map_err(|e| anyhow!("Edit {}: {}", i + 1, e))?;
+        match edit {
+            TranscriptionEdit::SegmentText { .. } => text_changed = true,
+            TranscriptionEdit::ChapterBounds { .. } => bounds_changed = true,
+            _ => {}
+        }
+    }
+
+    if text_changed {
+        edited.raw_text = join_text(&edited.segments);
+    }
+    if text_changed || bounds_changed {
+        refresh_chapters(&mut edited, bounds_changed)?;
+        edited.refresh_chapter_confidence();
+    }
+
+    *transcription = edited;
+    Ok(())
+}
+
+fn apply_edit(transcription: &mut Transcription, edit: &TranscriptionEdit) -> Result<()> {
+    match edit {
+        TranscriptionEdit::Title { title } => {
+            transcription.title = validate_title(title)?;
+        }
+        TranscriptionEdit::SegmentText { segment_id, text } => {
+            let text = text.trim();
+            if text.is_empty() {
+                bail!("segment text cannot be empty");
+            }
+            let segment = segment_mut(transcription, segment_id)?;
+            segment.text = text.to_string();
+            segment.confidence = 1.0;
+            segment.words = text
+                .split_whitespace()
+                .map(|word| WordConfidence { text: word.to_string(), confidence: 1.0 })
+                .collect();
+        }
+        TranscriptionEdit::Speaker { segment_id, speaker } => {
+            let speaker = speaker.as_deref().map(str::trim).filter(|s| !s.is_empty());
+            segment_mut(transcription, segment_id)?.speaker = speaker.map(str::to_string);
+        }
+        TranscriptionEdit::ChapterTitle { chapter_id, title } => {
+            let title = validate_title(title)?;
+            transcription
+                .chapters
+                .iter_mut()
+                .find(|c| &c.id == chapter_id)
+                .ok_or_else(|| anyhow!("chapter {} not found", chapter_id))?
+                .title = title;
+        }
+        TranscriptionEdit::ChapterBounds { chapter_id, start_time, end_time } => {
+            set_chapter_bounds(transcription, chapter_id, *start_time, *end_time)?;
+        }
+        TranscriptionEdit::Tags { tags } => {
+            transcription.tags = labels(tags)?;
+        }
+        TranscriptionEdit::Folder { folder } => {
+            transcription.folder = optional_label(folder.as_deref())?;
+        }
+        TranscriptionEdit::Participants { participants } => {
+            transcription.participants = labels(participants)?;
+        }
+        TranscriptionEdit::Location { location } => {
+            transcription.location = optional_label(location.as_deref())?;
+        }
+        TranscriptionEdit::Metadata { key, value } => {
+            let key = key.trim();
+            if key.is_empty() {
+                bail!("metadata key cannot be empty");
+            }
+            match value {
+                Some(value) => {
+                    transcription.metadata.insert(key.to_string(), value.clone());
+                }
+                None => {
+                    transcription.metadata.remove(key);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Trimmed single-line labels without blanks or duplicates that differ
+/// only in case, in the order given.
+pub fn labels(values: &[String]) -> Result<Vec<String>> {
+    let mut labels: Vec<String> = Vec::new();
+    for value in values {
+        if let Some(label) = optional_label(Some(value))? {
+            if !labels.iter().any(|l| label_key(l) == label_key(&label)) {
+                labels.push(label);
+            }
+        }
+    }
+    Ok(labels)
+}
+
+fn optional_label(value: Option<&str>) -> Result<Option<String>> {
+    let label = value.map(|v| v.split_whitespace().collect::<Vec<_>>().join(" ")).unwrap_or_default();
+    if label.chars().count() > MAX_TITLE_CHARS {
+        bail!("labels cannot be longer than {} characters", MAX_TITLE_CHARS);
+    }
+    Ok((!label.is_empty()).then_some(label))
+}
+
+/// Trimmed title on a single line.
+pub fn validate_title(title: &str) -> Result<String> {
+    let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+    if title.is_empty() {
+        bail!("title cannot be empty");
+    }
+    if title.chars().count() > MAX_TITLE_CHARS {
+        bail!("title is longer than {} characters", MAX_TITLE_CHARS);
+    }
+    Ok(title)
+}
+
+/// Title made of the first words of `text`, for when none can be generated.
+pub fn title_from_text(text: &str) -> Option<String> {
+    let mut title = String::new();
+    for word in text.split_whitespace() {
+        if title.chars().count() + word.chars().count() + 1 > MAX_TITLE_CHARS / 2 {
+            if title.is_empty() {
+                title = word.chars().take(MAX_TITLE_CHARS / 2).collect();
+            }
+            title.push('…');
+            break;
+        }
+        if !title.is_empty() {
+            title.push(' ');
+        }
+        title.push_str(word);
+    }
+    (!title.is_empty()).then_some(title)
+}
+
+fn segment_mut<'a>(transcription: &'a mut Transcription, id: &str) -> Result<&'a mut TranscriptionSegment> {
+    transcription
+        .segments
+        .iter_mut()
+        .find(|s| s.id == id)
+        .ok_or_else(|| anyhow!("segment {} not found", id))
+}
+
+/// End of the recording in seconds. `duration` is rounded down, so the
+/// last segment may end slightly after it.
+fn recording_end(transcription: &Transcription) -> f64 {
+    transcription
+        .segments
+        .iter()
+        .map(|s| s.end_time)
+        .fold(transcription.duration as f64, f64::max)
+}
+
+fn set_chapter_bounds(transcription: &mut Transcription, id: &str, start_time: f64, end_time: f64) -> Result<()> {
+    let recording_end = recording_end(transcription);
+
+    if !start_time.is_finite() || !end_time.is_finite() || start_time < 0.0 {
+        bail!("chapter bounds must be non-negative times");
+    }
+    if end_time <= start_time {
+        bail!("a chapter must end after it starts");
+    }
+    if end_time > recording_end {
+        bail!("a chapter cannot end after the recording ({:.1}s)", recording_end);
+    }
+
+    let chapters = &mut transcription.chapters;
+    let index = chapters
+        .iter()
+        .position(|c| c.id == id)
+        .ok_or_else(|| anyhow!("chapter {} not found", id))?;
+
+    if index > 0 {
+        let previous = &mut chapters[index - 1];
+        if start_time <= previous.start_time {
+            bail!("a chapter cannot start before the previous chapter \"{}\"", previous.title);
+        }
+        previous.end_time = start_time;
+    }
+    if let Some(next) = chapters.get_mut(index + 1) {
+        if end_time >= next.end_time {
+            bail!("a chapter cannot end after the next chapter \"{}\"", next.title);
+        }
+        next.start_time = end_time;
+    }
+
+    chapters[index].start_time = start_time;
+    chapters[index].end_time = end_time;
+    Ok(())
+}
+
+/// Brings chapters built from segments in line with the segments: with
+/// `reassign`, membership follows the chapter and subsection times;
+/// content is always rebuilt. Chapters analysed from plain text keep
+/// their content.
+fn refresh_chapters(transcription: &mut Transcription, reassign: bool) -> Result<()> {
+    let segments = &transcription.segments;
+    let count = transcription.chapters.len();
+
+    for (i, chapter) in transcription.chapters.iter_mut().enumerate() {
+        if chapter.segment_ids.is_empty() {
+            continue;
+        }
+
+        if reassign {
+            let last = i + 1 == count;
+            chapter.segment_ids = segment_ids_between(segments, chapter.start_time, chapter.end_time, last);
+            if chapter.segment_ids.is_empty() {
+                bail!("chapter \"{}\" would contain no segments", chapter.title);
+            }
+
+            let (start, end) = (chapter.start_time, chapter.end_time);
+            chapter.subsections.retain(|s| s.start_time < end && s.end_time > start);
+            let starts: Vec<f64> = chapter.subsections.iter().map(|s| s.start_time.max(start)).collect();
+            let subsection_count = chapter.subsections.len();
+
+            for (j, subsection) in chapter.subsections.iter_mut().enumerate() {
+                // Subsections stay contiguous and the first one starts with the chapter
+                subsection.start_time = if j == 0 { start } else { starts[j] };
+                subsection.end_time = starts.get(j + 1).copied().unwrap_or(end);
+                subsection.segment_ids = segment_ids_between(
+                    segments,
+                    subsection.start_time,
+                    subsection.end_time,
+                    last && j + 1 == subsection_count,
+                );
+            }
+            chapter.subsections.retain(|s| !s.segment_ids.is_empty());
+        }
+
+        chapter.content = text_of(segments, &chapter.segment_ids);
+        for subsection in chapter.subsections.iter_mut() {
+            subsection.content = text_of(segments, &subsection.segment_ids);
+        }
+    }
+
+    Ok(())
+}
+
+/// Segments starting in `[start, end)`, or `[start, end]` for the last range.
+fn segment_ids_between(segments: &[TranscriptionSegment], start: f64, end: f64, inclusive: bool) -> Vec<String> {
+    segments
+        .iter()
+        .filter(|s| s.start_time >= start && (s.start_time < end || (inclusive && s.start_time <= end)))
+        .map(|s| s.id.clone())
+        .collect()
+}
+
+fn text_of(segments: &[TranscriptionSegment], ids: &[String]) -> String {
+    let ids: HashSet<&str> = ids.iter().map(|id| id.as_str()).collect();
+    segments
+        .iter()
+        .filter(|s| ids.contains(s.id.as_str()))
+        .map(|s| s.text.trim())
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Splits a transcription at the first segment starting at or after `at`
+/// seconds. The first part keeps the id, title, revisions and token usage;
+/// the second is a new transcription.
+pub fn split(transcription: &Transcription, at: f64) -> Result<(Transcription, Transcription)> {
+    require_segments(transcription)?;
+    let end = recording_end(transcription);
+    if !(at > 0.0 && at < end) {
+        bail!("the split point must be inside the recording (0s to {:.1}s)", end);
+    }
+    let at = next_segment_start(transcription, at)
+        .ok_or_else(|| anyhow!("no segment starts after {:.1}s", at))?;
+
+    let first = extract(transcription, 0.0, at)?;
+    let mut second = extract(transcription, at, end)?;
+    second.id = uuid::Uuid::new_v4().to_string();
+    second.title = format!("{} (2)", transcription.title);
+    second.created_at = transcription.created_at + chrono::Duration::milliseconds((at * 1000.0) as i64);
+    second.usage = TokenUsage::default();
+
+    Ok((first, second))
+}
+
+/// Keeps only the segments starting between `start_time` and `end_time`,
+/// with times re-based so the first of them starts at zero.
+pub fn trim(transcription: &Transcription, start_time: f64, end_time: f64) -> Result<Transcription> {
+    require_segments(transcription)?;
+    let end = recording_end(transcription);
+    if !(start_time >= 0.0 && end_time > start_time) {
+        bail!("the trimmed range must start at or after 0s and end after it starts");
+    }
+    if start_time == 0.0 && end_time >= end {
+        bail!("the range covers the whole recording, nothing to trim");
+    }
+    let start = next_segment_start(transcription, start_time)
+        .filter(|&start| start < end_time)
+        .ok_or_else(|| anyhow!("no segments start between {:.1}s and {:.1}s", start_time, end_time))?;
+
+    extract(transcription, start, end_time.min(end))
+}
+
+fn next_segment_start(transcription: &Transcription, at: f64) -> Option<f64> {
+    transcription.segments.iter().map(|s| s.start_time).find(|&start| start >= at)
+}
+
+/// Joins transcriptions in the given order into the first one. Times of
+/// each part are moved after the end of the previous parts; token usage,
+/// tags and participants are combined and the rest comes from the first.
+/// When some parts have segments, parts without them get segments cut
+/// from their text, so that editing a segment later keeps all the text.
+pub fn merge(transcriptions: &[Transcription]) -> Result<Transcription> {
+    let Some((first, rest)) = transcriptions.split_first() else {
+        bail!("nothing to merge");
+    };
+    if rest.is_empty() {
+        bail!("at least two transcriptions are needed to merge");
+    }
+
+    let timed = transcriptions.iter().any(|t| !t.segments.is_empty());
+    let mut merged = first.clone();
+    merged.segments = part_segments(first, timed);
+    let mut offset = recording_end(first);
+    let mut texts = vec![first.raw_text.trim().to_string()];
+
+    for part in rest {
+        merged.segments.extend(part_segments(part, timed).into_iter().map(|mut segment| {
+            segment.start_time += offset;
+            segment.end_time += offset;
+            segment
+        }));
+        merged.chapters.extend(part.chapters.iter().cloned().map(|mut chapter| {
+            chapter.start_time += offset;
+            chapter.end_time += offset;
+            for subsection in chapter.subsections.iter_mut() {
+                subsection.start_time += offset;
+                subsection.end_time += offset;
+            }
+            chapter
+        }));
+        merged.usage.add(&part.usage);
+        merged.tags.extend(part.tags.iter().cloned());
+        merged.participants.extend(part.participants.iter().cloned());
+        texts.push(part.raw_text.trim().to_string());
+        offset += recording_end(part);
+    }
+
+    merged.raw_text = texts.into_iter().filter(|t| !t.is_empty()).collect::<Vec<_>>().join("\n\n");
+    merged.duration = offset.round() as u64;
+    merged.tags = labels(&merged.tags)?;
+    merged.participants = labels(&merged.participants)?;
+    merged.notes = MeetingNotes::default();
+    Ok(merged)
+}
+
+/// Segments of a part being merged; with `timed`, a part without any are
+/// cut from its text, with ids that stay unique in the merged result.
+fn part_segments(part: &Transcription, timed: bool) -> Vec<TranscriptionSegment> {
+    if !timed || !part.segments.is_empty() {
+        return part.segments.clone();
+    }
+    analysis_segments(part)
+        .into_iter()
+        .map(|mut segment| {
+            segment.id = format!("{}-{}", part.id, segment.id);
+            segment
+        })
+        .collect()
+}
+
+/// Splitting and trimming go by segment times, which plain text lacks.
+fn require_segments(transcription: &Transcription) -> Result<()> {
+    if transcription.segments.is_empty() {
+        bail!("only transcriptions with timed segments can be split or trimmed");
+    }
+    Ok(())
+}
+
+/// The part of `source` starting in `[start, end)`, re-based to start at
+/// zero. Chapters and subsections are clipped to the range; meeting notes
+/// no longer apply and are cleared.
+fn extract(source: &Transcription, start: f64, end: f64) -> Result<Transcription> {
+    let mut part = source.clone();
+    part.segments = source
+        .segments
+        .iter()
+        .filter(|s| s.start_time >= start && s.start_time < end)
+        .cloned()
+        .map(|mut segment| {
+            segment.start_time -= start;
+            segment.end_time -= start;
+            segment
+        })
+        .collect();
+    if part.segments.is_empty() {
+        bail!("no segments start between {:.1}s and {:.1}s", start, end);
+    }
+
+    let kept: HashSet<String> = part.segments.iter().map(|s| s.id.clone()).collect();
+    part.chapters = source
+        .chapters
+        .iter()
+        .filter(|c| c.start_time < end && c.end_time > start)
+        .cloned()
+        .filter_map(|mut chapter| {
+            let from_segments = !chapter.segment_ids.is_empty();
+            chapter.segment_ids.retain(|id| kept.contains(id));
+            if from_segments && chapter.segment_ids.is_empty() {
+                return None;
+            }
+            chapter.start_time = chapter.start_time.max(start) - start;
+            chapter.end_time = chapter.end_time.min(end) - start;
+
+            chapter.subsections.retain_mut(|subsection| {
+                if subsection.start_time >= end || subsection.end_time <= start {
+                    return false;
+                }
+                // Text-based subsections have no segments to lose
+                let from_segments = !subsection.segment_ids.is_empty();
+                subsection.segment_ids.retain(|id| kept.contains(id));
+                subsection.start_time = subsection.start_time.max(start) - start;
+                subsection.end_time = subsection.end_time.min(end) - start;
+                !from_segments || !subsection.segment_ids.is_empty()
+            });
+            if let Some(first) = chapter.subsections.first_mut() {
+                first.start_time = chapter.start_time;
+            }
+
+            if from_segments {
+                chapter.content = text_of(&part.segments, &chapter.segment_ids);
+                for subsection in chapter.subsections.iter_mut() {
+                    subsection.content = text_of(&part.segments, &subsection.segment_ids);
+                }
+            }
+            Some(chapter)
+        })
+        .collect();
+
+    part.raw_text = join_text(&part.segments);
+    part.duration = (end - start).round() as u64;
+    part.notes = MeetingNotes::default();
+    part.refresh_chapter_confidence();
+    Ok(part)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Four 10 s segments with one chapter analysed from plain text.
+    fn text_analysed() -> Transcription {
+        let segments: Vec<_> = (0..4)
+            .map(|i| {
+                json!({
+                    "id": format!("seg-{}", i),
+                    "text": format!("Part {}.", i),
+                    "start_time": i as f64 * 10.0,
+                    "end_time": (i + 1) as f64 * 10.0,
+                    "confidence": 0.9,
+                    "words": []
+                })
+            })
+            .collect();
+        let subsection = |title: &str, start: f64, end: f64| {
+            json!({
+                "id": title, "title": title, "content": title,
+                "start_time": start, "end_time": end, "confidence": 0.9
+            })
+        };
+
+        serde_json::from_value(json!({
+            "schema_version": 2,
+            "id": "t1",
+            "title": "Meeting",
+            "created_at": "2024-05-01T10:00:00Z",
+            "duration": 40,
+            "chapters": [{
+                "id": "c1", "title": "All", "start_time": 0.0, "end_time": 40.0,
+                "content": "Part 0. Part 1. Part 2. Part 3.", "confidence": 0.9,
+                "subsections": [subsection("Opening", 0.0, 20.0), subsection("Closing", 20.0, 40.0)]
+            }],
+            "raw_text": "Part 0. Part 1. Part 2. Part 3.",
+            "status": "Completed",
+            "segments": segments
+        }))
+        .unwrap()
+    }
+
+    /// The same segments with two chapters analysed from them.
+    fn segment_analysed() -> Transcription {
+        let mut transcription = text_analysed();
+        let chapter = |id: &str, start: f64, end: f64, segments: &[usize]| {
+            let ids: Vec<String> = segments.iter().map(|i| format!("seg-{}", i)).collect();
+            json!({
+                "id": id, "title": id, "start_time": start, "end_time": end,
+                "content": "", "confidence": 0.9, "segment_ids": ids,
+                "subsections": [{
+                    "id": format!("{}-s", id), "title": id, "content": "",
+                    "start_time": start, "end_time": end, "confidence": 0.9, "segment_ids": ids
+                }]
+            })
+        };
+        transcription.chapters =
+            serde_json::from_value(json!([chapter("c1", 0.0, 20.0, &[0, 1]), chapter("c2", 20.0, 40.0, &[2, 3])]))
+                .unwrap();
+        transcription
+    }
+
+    fn bounds(chapter_id: &str, start_time: f64, end_time: f64) -> TranscriptionEdit {
+        TranscriptionEdit::ChapterBounds { chapter_id: chapter_id.to_string(), start_time, end_time }
+    }
+
+    fn chapters(transcription: &Transcription) -> Vec<(f64, f64, Vec<&str>)> {
+        transcription
+            .chapters
+            .iter()
+            .map(|c| (c.start_time, c.end_time, c.segment_ids.iter().map(String::as_str).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn segment_edits_update_text_and_chapters() {
+        let mut transcription = segment_analysed();
+        apply(&mut transcription, &[
+            TranscriptionEdit::SegmentText { segment_id: "seg-1".to_string(), text: "  Fixed  text ".to_string() },
+            TranscriptionEdit::Speaker { segment_id: "seg-1".to_string(), speaker: Some(" Anna ".to_string()) },
+            TranscriptionEdit::Title { title: " Weekly\n sync ".to_string() },
+        ])
+        .unwrap();
+
+        assert_eq!(transcription.title, "Weekly sync");
+        assert_eq!(transcription.raw_text, "Part 0. Fixed  text Part 2. Part 3.");
+        let segment = &transcription.segments[1];
+        assert_eq!((segment.confidence, segment.speaker.as_deref()), (1.0, Some("Anna")));
+        assert_eq!(segment.words.iter().map(|w| w.text.as_str()).collect::<Vec<_>>(), vec!["Fixed", "text"]);
+        assert_eq!(transcription.chapters[0].content, "Part 0. Fixed  text");
+        assert_eq!(transcription.chapters[0].subsections[0].content, "Part 0. Fixed  text");
+    }
+
+    #[test]
+    fn invalid_edits_leave_the_transcription_unchanged() {
+        let original = segment_analysed();
+        let mut transcription = original.clone();
+
+        let error = apply(&mut transcription, &[
+            TranscriptionEdit::Title { title: "Renamed".to_string() },
+            TranscriptionEdit::SegmentText { segment_id: "missing".to_string(), text: "Text".to_string() },
+        ])
+        .unwrap_err();
+
+        assert_eq!(error.to_string(), "Edit 2: segment missing not found");
+        assert_eq!(serde_json::to_value(&transcription).unwrap(), serde_json::to_value(&original).unwrap());
+        assert!(apply(&mut transcription, &[TranscriptionEdit::Title { title: " ".to_string() }]).is_err());
+        assert!(apply(&mut transcription, &[TranscriptionEdit::Tags { tags: vec!["x".repeat(121)] }]).is_err());
+    }
+
+    #[test]
+    fn chapter_bounds_move_the_neighbouring_chapter_along() {
+        // Shrinking a chapter extends its neighbour instead of leaving a gap
+        let mut transcription = segment_analysed();
+        apply(&mut transcription, &[bounds("c2", 30.0, 40.0)]).unwrap();
+        assert_eq!(chapters(&transcription), vec![
+            (0.0, 30.0, vec!["seg-0", "seg-1", "seg-2"]),
+            (30.0, 40.0, vec!["seg-3"]),
+        ]);
+        assert_eq!(transcription.chapters[0].content, "Part 0. Part 1. Part 2.");
+        let subsection = &transcription.chapters[0].subsections[0];
+        assert_eq!((subsection.start_time, subsection.end_time), (0.0, 30.0));
+
+        let mut transcription = segment_analysed();
+        apply(&mut transcription, &[bounds("c1", 0.0, 10.0)]).unwrap();
+        assert_eq!(chapters(&transcription), vec![
+            (0.0, 10.0, vec!["seg-0"]),
+            (10.0, 40.0, vec!["seg-1", "seg-2", "seg-3"]),
+        ]);
+    }
+
+    #[test]
+    fn chapter_bounds_are_validated() {
+        let cases = [
+            (bounds("c2", 25.0, 20.0), "Edit 1: a chapter must end after it starts"),
+            (bounds("c2", -1.0, 40.0), "Edit 1: chapter bounds must be non-negative times"),
+            (bounds("c2", 20.0, f64::NAN), "Edit 1: chapter bounds must be non-negative times"),
+            (bounds("c2", 20.0, 41.0), "Edit 1: a chapter cannot end after the recording (40.0s)"),
+            (bounds("c2", 0.0, 40.0), "Edit 1: a chapter cannot start before the previous chapter \"c1\""),
+            (bounds("c1", 0.0, 40.0), "Edit 1: a chapter cannot end after the next chapter \"c2\""),
+            // Found after all edits are applied
+            (bounds("c1", 1.0, 10.0), "chapter \"c1\" would contain no segments"),
+            (bounds("c3", 0.0, 5.0), "Edit 1: chapter c3 not found"),
+        ];
+
+        for (edit, expected) in cases {
+            let mut transcription = segment_analysed();
+            let error = apply(&mut transcription, &[edit]).unwrap_err();
+            assert_eq!(error.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn titles_from_text_take_the_first_words() {
+        assert_eq!(title_from_text("  Good morning\n everyone "), Some("Good morning everyone".to_string()));
+        assert_eq!(title_from_text(" \n "), None);
+
+        let long = "word ".repeat(40);
+        let title = title_from_text(&long).unwrap();
+        assert!(title.ends_with("word…"));
+        assert!(title.chars().count() <= MAX_TITLE_CHARS / 2 + 1);
+
+        let title = title_from_text(&"x".repeat(200)).unwrap();
+        assert_eq!(title, format!("{}…", "x".repeat(MAX_TITLE_CHARS / 2)));
+    }
+
+    #[test]
+    fn split_rebases_the_second_part() {
+        let mut transcription = segment_analysed();
+        transcription.usage.input_tokens = 100;
+        let (first, second) = split(&transcription, 15.0).unwrap();
+
+        assert_eq!((first.id.as_str(), first.duration, first.usage.input_tokens), ("t1", 20, 100));
+        assert_eq!(chapters(&first), vec![(0.0, 20.0, vec!["seg-0", "seg-1"])]);
+
+        assert_ne!(second.id, "t1");
+        assert_eq!((second.title.as_str(), second.duration, second.usage.input_tokens), ("Meeting (2)", 20, 0));
+        assert_eq!(second.created_at, transcription.created_at + chrono::Duration::seconds(20));
+        assert_eq!(second.raw_text, "Part 2. Part 3.");
+        assert_eq!(chapters(&second), vec![(0.0, 20.0, vec!["seg-2", "seg-3"])]);
+        assert_eq!((second.segments[0].start_time, second.segments[1].end_time), (0.0, 20.0));
+
+        assert!(split(&transcription, 0.0).is_err());
+        assert!(split(&transcription, 40.0).is_err());
+        let mut text_only = transcription.clone();
+        text_only.segments.clear();
+        assert!(split(&text_only, 15.0).is_err());
+    }
+
+    #[test]
+    fn merge_appends_parts_after_each_other() {
+        let mut first = segment_analysed();
+        first.tags = vec!["Team".to_string()];
+        first.usage.input_tokens = 10;
+        let (_, mut second) = split(&segment_analysed(), 20.0).unwrap();
+        second.tags = vec!["team".to_string(), "Budget".to_string()];
+        second.usage.input_tokens = 5;
+
+        let merged = merge(&[first, second.clone()]).unwrap();
+
+        assert_eq!((merged.id.as_str(), merged.duration, merged.usage.input_tokens), ("t1", 60, 15));
+        assert_eq!(merged.tags, vec!["Team", "Budget"]);
+        assert_eq!(merged.raw_text, "Part 0. Part 1. Part 2. Part 3.\n\nPart 2. Part 3.");
+        let times: Vec<f64> = merged.segments.iter().map(|s| s.start_time).collect();
+        assert_eq!(times, vec![0.0, 10.0, 20.0, 30.0, 40.0, 50.0]);
+        let chapter = &merged.chapters[2];
+        assert_eq!((chapter.start_time, chapter.end_time), (40.0, 60.0));
+        assert_eq!((chapter.subsections[0].start_time, chapter.subsections[0].end_time), (40.0, 60.0));
+
+        assert!(merge(&[second]).is_err());
+        assert!(merge(&[]).is_err());
+    }
+
+    #[test]
+    fn merged_text_without_segments_survives_segment_edits() {
+        let mut recorded = segment_analysed();
+        recorded.chapters.clear();
+        let mut imported = text_analysed();
+        imported.id = "t2".to_string();
+        imported.segments.clear();
+        imported.raw_text = "Imported notes.".to_string();
+        imported.duration = 20;
+
+        let mut merged = merge(&[recorded, imported]).unwrap();
+        let last = merged.segments.last().unwrap();
+        assert_eq!((last.id.as_str(), last.text.as_str()), ("t2-text-0", "Imported notes."));
+        assert_eq!((last.start_time, last.end_time), (40.0, 60.0));
+
+        apply(&mut merged, &[TranscriptionEdit::SegmentText {
+            segment_id: "seg-0".to_string(),
+            text: "Opening.".to_string(),
+        }])
+        .unwrap();
+        assert_eq!(merged.raw_text, "Opening. Part 1. Part 2. Part 3. Imported notes.");
+        assert_eq!(merged.chapters[0].start_time, 40.0);
+    }
+
+    #[test]
+    fn trim_keeps_text_based_subsections() {
+        let trimmed = trim(&text_analysed(), 10.0, 40.0).unwrap();
+
+        assert_eq!(trimmed.raw_text, "Part 1. Part 2. Part 3.");
+        let chapter = &trimmed.chapters[0];
+        assert_eq!((chapter.start_time, chapter.end_time), (0.0, 30.0));
+
+        let subsections: Vec<(&str, f64, f64)> = chapter
+            .subsections
+            .iter()
+            .map(|s| (s.title.as_str(), s.start_time, s.end_time))
+            .collect();
+        assert_eq!(subsections, vec![("Opening", 0.0, 10.0), ("Closing", 10.0, 30.0)]);
+    }
+}
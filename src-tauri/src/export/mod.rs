@@ -2,9 +2,16 @@ use anyhow::Result;
 use printpdf::*;
 use std::fs::File;
 use std::io::BufWriter;
-use crate::models::{Transcription, ExportFormat, ExportType};
+use crate::models::{Transcription, ExportFormat, ExportType, MeetingNotes};
 use crate::storage::StorageService;
 
+/// One meeting-notes section: either a paragraph or a list of items.
+struct NotesSection {
+    heading: &'static str,
+    paragraph: Option<String>,
+    items: Vec<String>,
+}
+
 pub struct ExportService {
     storage: StorageService,
 }
@@ -39,7 +46,7 @@ impl ExportService {
         let font = doc.add_builtin_font(BuiltinFont::HelveticaBold)?;
         let regular_font = doc.add_builtin_font(BuiltinFont::Helvetica)?;
 
-        let current_layer = doc.get_page(page1).get_layer(layer1);
+        let mut current_layer = doc.get_page(page1).get_layer(layer1);
 
         // Title
         current_layer.use_text(&transcription.title, 24.0, Mm(20.0), Mm(260.0), &font);
@@ -52,13 +59,40 @@ impl ExportService {
 
         let mut y_position = 200.0;
 
+        // Meeting notes
+        for section in Self::notes_sections(&transcription.notes, format) {
+            if y_position < 40.0 {
+                let (page_id, layer_id) = doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
+                current_layer = doc.get_page(page_id).get_layer(layer_id);
+                y_position = 260.0;
+            }
+
+            current_layer.use_text(section.heading, 14.0, Mm(20.0), Mm(y_position), &font);
+            y_position -= 10.0;
+
+            let mut lines = section.paragraph.as_deref().map(|p| Self::wrap_text(p, 80)).unwrap_or_default();
+            for item in &section.items {
+                lines.extend(Self::wrap_text(&format!("- {}", item), 80));
+            }
+            for line in lines {
+                if y_position < 40.0 {
+                    let (page_id, layer_id) = doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
+                    current_layer = doc.get_page(page_id).get_layer(layer_id);
+                    y_position = 260.0;
+                }
+                current_layer.use_text(&line, 11.0, Mm(20.0), Mm(y_position), &regular_font);
+                y_position -= 8.0;
+            }
+            y_position -= 10.0;
+        }
+
         // Chapters
         if format.include_chapters && !transcription.chapters.is_empty() {
             for chapter in &transcription.chapters {
                 if y_position < 40.0 {
                     // Create new page if running out of space
                     let (page_id, layer_id) = doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
-                    let _new_layer = doc.get_page(page_id).get_layer(layer_id);
+                    current_layer = doc.get_page(page_id).get_layer(layer_id);
                     y_position = 260.0;
                 }
 
@@ -77,7 +111,7 @@ impl ExportService {
                 for line in lines {
                     if y_position < 40.0 {
                         let (page_id, layer_id) = doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
-                        let _new_layer = doc.get_page(page_id).get_layer(layer_id);
+                        current_layer = doc.get_page(page_id).get_layer(layer_id);
                         y_position = 260.0;
                     }
                     current_layer.use_text(&line, 11.0, Mm(20.0), Mm(y_position), &regular_font);
@@ -91,7 +125,7 @@ impl ExportService {
             for line in lines {
                 if y_position < 40.0 {
                     let (page_id, layer_id) = doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
-                    let _new_layer = doc.get_page(page_id).get_layer(layer_id);
+                    current_layer = doc.get_page(page_id).get_layer(layer_id);
                     y_position = 260.0;
                 }
                 current_layer.use_text(&line, 11.0, Mm(20.0), Mm(y_position), &regular_font);
@@ -120,6 +154,11 @@ impl ExportService {
         content.push_str(&format!("Date: {}\n", transcription.created_at.format("%Y-%m-%d %H:%M:%S")));
         content.push_str(&format!("Duration: {}s\n\n", transcription.duration));
 
+        for section in Self::notes_sections(&transcription.notes, format) {
+            content.push_str(&format!("## {}\n", section.heading));
+            content.push_str(&Self::notes_body(&section));
+        }
+
         if format.include_chapters && !transcription.chapters.is_empty() {
             for chapter in &transcription.chapters {
                 content.push_str(&format!("## {}\n", chapter.title));
@@ -151,6 +190,11 @@ impl ExportService {
         content.push_str(&format!("Date: {}\n", transcription.created_at.format("%Y-%m-%d %H:%M:%S")));
         content.push_str(&format!("Duration: {}s\n\n", transcription.duration));
 
+        for section in Self::notes_sections(&transcription.notes, format) {
+            content.push_str(&format!("--- {} ---\n", section.heading));
+            content.push_str(&Self::notes_body(&section));
+        }
+
         if format.include_chapters && !transcription.chapters.is_empty() {
             for chapter in &transcription.chapters {
                 content.push_str(&format!("--- {} ---\n", chapter.title));
@@ -182,6 +226,11 @@ impl ExportService {
         content.push_str(&format!("**Date:** {}\n", transcription.created_at.format("%Y-%m-%d %H:%M:%S")));
         content.push_str(&format!("**Duration:** {}s\n\n", transcription.duration));
 
+        for section in Self::notes_sections(&transcription.notes, format) {
+            content.push_str(&format!("## {}\n\n", section.heading));
+            content.push_str(&Self::notes_body(&section));
+        }
+
         if format.include_chapters && !transcription.chapters.is_empty() {
             for chapter in &transcription.chapters {
                 content.push_str(&format!("## {}\n", chapter.title));
@@ -199,6 +248,80 @@ impl ExportService {
         Ok(file_path.to_string_lossy().to_string())
     }
 
+    /// Meeting-notes sections selected by `format` that have been analysed.
+    fn notes_sections(notes: &MeetingNotes, format: &ExportFormat) -> Vec<NotesSection> {
+        let mut sections = Vec::new();
+
+        if format.include_summary {
+            if let Some(summary) = &notes.executive_summary {
+                sections.push(NotesSection {
+                    heading: "Executive Summary",
+                    paragraph: Some(summary.clone()),
+                    items: Vec::new(),
+                });
+            }
+            if let Some(points) = &notes.bullet_summary {
+                sections.push(NotesSection { heading: "Summary", paragraph: None, items: points.clone() });
+            }
+        }
+
+        if format.include_action_items {
+            if let Some(action_items) = &notes.action_items {
+                let items = action_items
+                    .iter()
+                    .map(|item| {
+                        let details: Vec<String> = [
+                            item.owner.as_ref().map(|o| format!("owner: {}", o)),
+                            item.due_date.as_ref().map(|d| format!("due: {}", d)),
+                        ]
+                        .into_iter()
+                        .flatten()
+                        .collect();
+
+                        if details.is_empty() {
+                            item.description.clone()
+                        } else {
+                            format!("{} ({})", item.description, details.join(", "))
+                        }
+                    })
+                    .collect();
+                sections.push(NotesSection { heading: "Action Items", paragraph: None, items });
+            }
+        }
+
+        if format.include_decisions {
+            if let Some(decisions) = &notes.decisions {
+                sections.push(NotesSection { heading: "Decisions", paragraph: None, items: decisions.clone() });
+            }
+        }
+
+        if format.include_open_questions {
+            if let Some(questions) = &notes.open_questions {
+                sections.push(NotesSection { heading: "Open Questions", paragraph: None, items: questions.clone() });
+            }
+        }
+
+        sections
+    }
+
+    /// Plain-text body of a notes section, shared by the text-based formats.
+    fn notes_body(section: &NotesSection) -> String {
+        let mut body = String::new();
+
+        if let Some(paragraph) = &section.paragraph {
+            body.push_str(&format!("{}\n", paragraph));
+        }
+        if section.items.is_empty() && section.paragraph.is_none() {
+            body.push_str("None\n");
+        }
+        for item in &section.items {
+            body.push_str(&format!("- {}\n", item));
+        }
+        body.push('\n');
+
+        body
+    }
+
     fn wrap_text(text: &str, width: usize) -> Vec<String> {
         let mut lines = Vec::new();
         let words: Vec<&str> = text.split_whitespace().collect();
@@ -2,35 +2,209 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod audio;
+mod editing;
 mod transcription;
 mod storage;
 mod export;
 mod models;
+mod pipeline;
+mod search;
+mod secrets;
+mod usage;
 
 use audio::AudioCapture;
-use transcription::TranscriptionService;
+use transcription::{ProviderEndpoint, TranscriptionService};
+use transcription::rate_limit::RateLimiterRegistry;
 use storage::StorageService;
 use export::ExportService;
+use secrets::SecretManager;
 use models::*;
 
 use std::sync::{Arc, Mutex};
 use tauri::{State, Window};
-use tokio::sync::mpsc;
+use pipeline::{Message, PipelineReceiver, QueueConfig, QueueMonitor};
+use tokio_util::sync::CancellationToken;
 use anyhow::Result;
 
 type AppStateType = Arc<Mutex<AppState>>;
+type RecordingSessionType = Mutex<Option<RecordingSlot>>;
+
+/// Reserved by `start_recording` before it sets anything up, so a second
+/// start cannot get past the check in the meantime.
+enum RecordingSlot {
+    Starting,
+    Active(RecordingSession),
+}
+
+/// Handles needed to stop the recording in progress.
+struct RecordingSession {
+    audio_capture: AudioCapture,
+    cancel: CancellationToken,
+    stream_task: Option<tokio::task::JoinHandle<()>>,
+}
+
+/// How long `stop_recording` waits for buffered audio to be transcribed
+/// before cancelling the remaining work.
+const STOP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Segments below this confidence are returned by `low_confidence_segments`
+/// when the caller does not pass an explicit threshold.
+const LOW_CONFIDENCE_THRESHOLD: f32 = 0.6;
+
+/// Hits returned by `search_transcriptions` unless a limit is given.
+const SEARCH_RESULT_LIMIT: usize = 20;
+
+/// Builds a service that tries the `set_api_key` key and every credential
+/// profile with a stored key, starting with `preferred_profile` (or the
+/// default profile). Returns `None` when no key is configured.
+fn create_transcription_service(
+    state: &AppStateType,
+    rate_limiters: &RateLimiterRegistry,
+    preferred_profile: Option<&str>,
+) -> std::result::Result<Option<TranscriptionService>, String> {
+    let app_state = state.lock().unwrap();
+    let settings = &app_state.provider_settings;
+    let rate_limit = app_state.rate_limit("gemini");
+
+    let mut endpoints = Vec::new();
+    if let Some(api_key) = &app_state.gemini_api_key {
+        endpoints.push(ProviderEndpoint {
+            profile_id: transcription::DEFAULT_PROFILE_ID.to_string(),
+            api_key: api_key.clone(),
+            api_root: settings.api_root(),
+            model: app_state.selected_model.clone(),
+            rate_limiter: rate_limiters.limiter(transcription::DEFAULT_PROFILE_ID, &rate_limit),
+        });
+    }
+
+    for profile in &app_state.profiles {
+        let Some(api_key) = app_state.profile_keys.get(&profile.id) else {
+            continue;
+        };
+        let mut profile_settings = settings.clone();
+        if let Some(base_url) = &profile.base_url {
+            profile_settings.base_url = base_url.clone();
+        }
+
+        endpoints.push(ProviderEndpoint {
+            profile_id: profile.id.clone(),
+            api_key: api_key.clone(),
+            api_root: profile_settings.api_root(),
+            model: profile.model.clone(),
+            rate_limiter: rate_limiters.limiter(&profile.id, &app_state.rate_limit(&profile.provider)),
+        });
+    }
+
+    // An explicitly requested profile must be usable; the default one may be stale
+    if let Some(requested) = preferred_profile {
+        if !endpoints.iter().any(|e| e.profile_id == requested) {
+            let known = requested == transcription::DEFAULT_PROFILE_ID
+                || app_state.profiles.iter().any(|p| p.id == requested);
+            return Err(if known {
+                format!("Profile {} has no API key", requested)
+            } else {
+                format!("Unknown profile: {}", requested)
+            });
+        }
+    }
+
+    if endpoints.is_empty() {
+        return Ok(None);
+    }
+
+    if let Some(preferred) = preferred_profile.or(app_state.default_profile_id.as_deref()) {
+        if let Some(position) = endpoints.iter().position(|e| e.profile_id == preferred) {
+            let endpoint = endpoints.remove(position);
+            endpoints.insert(0, endpoint);
+        }
+    }
+
+    TranscriptionService::with_failover(endpoints, settings)
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
 
 #[tauri::command]
 async fn start_recording(
+    profile_id: Option<String>,
     state: State<'_, AppStateType>,
+    rate_limiters: State<'_, RateLimiterRegistry>,
+    storage: State<'_, StorageService>,
+    sessions: State<'_, RecordingSessionType>,
     window: Window,
 ) -> std::result::Result<String, String> {
+    {
+        let mut sessions = sessions.lock().unwrap();
+        if sessions.is_some() {
+            return Err("A recording is already in progress".to_string());
+        }
+        *sessions = Some(RecordingSlot::Starting);
+    }
+
     let transcription_id = uuid::Uuid::new_v4().to_string();
+    let started = begin_recording(&transcription_id, profile_id, &state, &rate_limiters, &storage, &window).await;
+
+    let mut sessions = sessions.lock().unwrap();
+    match started {
+        Ok(session) => {
+            *sessions = Some(RecordingSlot::Active(session));
+            Ok(transcription_id)
+        }
+        Err(e) => {
+            *sessions = None;
+            Err(e)
+        }
+    }
+}
+
+/// Starts capture and streaming transcription for `start_recording`. Either
+/// both are running afterwards or neither is.
+async fn begin_recording(
+    transcription_id: &str,
+    profile_id: Option<String>,
+    state: &AppStateType,
+    rate_limiters: &RateLimiterRegistry,
+    storage: &StorageService,
+    window: &Window,
+) -> std::result::Result<RecordingSession, String> {
+    let cancel = CancellationToken::new();
+    let pipeline_settings = state.lock().unwrap().pipeline_settings.clone();
+    let queue_config = |name: &str, capacity: usize| QueueConfig {
+        name: name.to_string(),
+        capacity,
+        policy: pipeline_settings.overflow_policy,
+        spill_dir: storage.data_dir().join("spill"),
+    };
+
+    // Without a key there is nothing to consume the audio
+    let transcription_service = create_transcription_service(state, rate_limiters, profile_id.as_deref())?
+        .ok_or_else(|| "No API key configured".to_string())?;
 
     // Initialize audio capture
     println!("Starting audio capture for transcription: {}", transcription_id);
     let mut audio_capture_instance = AudioCapture::new().map_err(|e| e.to_string())?;
-    let audio_rx = audio_capture_instance.start_recording().map_err(|e| e.to_string())?;
+    let audio_rx = audio_capture_instance
+        .start_recording(queue_config("audio", pipeline_settings.audio_queue_capacity))
+        .map_err(|e| e.to_string())?;
+    let mut monitors = vec![audio_rx.monitor()];
+
+    let transcription_rx = match transcription_service
+        .start_streaming_transcription(
+            audio_rx,
+            queue_config("transcription", pipeline_settings.transcription_queue_capacity),
+            cancel.clone(),
+        )
+        .await
+    {
+        Ok(transcription_rx) => transcription_rx,
+        Err(e) => {
+            if let Err(stop_error) = audio_capture_instance.stop_recording() {
+                eprintln!("Failed to stop audio capture: {}", stop_error);
+            }
+            return Err(e.to_string());
+        }
+    };
+    monitors.push(transcription_rx.monitor());
 
     // Update app state
     {
@@ -40,122 +214,1076 @@ async fn start_recording(
             current_text: String::new(),
             duration: 0.0,
             audio_level: 0.0,
-            transcription_id: Some(transcription_id.clone()),
+            transcription_id: Some(transcription_id.to_string()),
+            segments: Vec::new(),
+            usage: TokenUsage::default(),
+            usage_by_model: Default::default(),
+            profile_id,
         });
     }
 
-    // Start transcription service if API key is available
-    let (api_key, model) = {
+    // Spawn task to handle transcription updates
+    let window_clone = window.clone();
+    let state_clone = state.clone();
+    let cancel_clone = cancel.clone();
+    let stream_task = Some(tokio::spawn(async move {
+        handle_transcription_stream(transcription_rx, window_clone, state_clone, cancel_clone).await;
+    }));
+
+    tokio::spawn(report_pipeline_diagnostics(
+        transcription_id.to_string(),
+        monitors,
+        window.clone(),
+        state.clone(),
+    ));
+
+    Ok(RecordingSession {
+        audio_capture: audio_capture_instance,
+        cancel,
+        stream_task,
+    })
+}
+
+/// Emits `pipeline-diagnostics` with the depth of every queue once a second
+/// for as long as the recording is active.
+async fn report_pipeline_diagnostics(
+    transcription_id: String,
+    monitors: Vec<Arc<dyn QueueMonitor>>,
+    window: Window,
+    state: AppStateType,
+) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+
+    loop {
+        interval.tick().await;
+
+        let active = {
+            let app_state = state.lock().unwrap();
+            app_state
+                .current_recording
+                .as_ref()
+                .and_then(|r| r.transcription_id.as_deref())
+                == Some(transcription_id.as_str())
+        };
+        if !active {
+            break;
+        }
+
+        let diagnostics = PipelineDiagnostics {
+            transcription_id: transcription_id.clone(),
+            queues: monitors.iter().map(|m| m.stats()).collect(),
+        };
+        let _ = window.emit("pipeline-diagnostics", &diagnostics);
+    }
+}
+
+/// Ends the recording and saves it, titled after its first words. With
+/// `auto_title` a title is then generated from the content, which is a
+/// paid call saved as a revision of its own.
+#[tauri::command]
+async fn stop_recording(
+    auto_title: Option<bool>,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+    rate_limiters: State<'_, RateLimiterRegistry>,
+    sessions: State<'_, RecordingSessionType>,
+    window: Window,
+) -> std::result::Result<Transcription, String> {
+    println!("Stopping recording and creating transcription");
+    if matches!(*sessions.lock().unwrap(), Some(RecordingSlot::Starting)) {
+        return Err("The recording is still starting".to_string());
+    }
+    {
+        let mut app_state = state.lock().unwrap();
+        match app_state.current_recording.as_mut() {
+            Some(recording_state) => recording_state.is_recording = false,
+            None => return Err("No active recording".to_string()),
+        }
+    }
+
+    // Stop capture and let the buffered audio finish transcribing
+    let session = match sessions.lock().unwrap().take() {
+        Some(RecordingSlot::Active(session)) => Some(session),
+        _ => None,
+    };
+    if let Some(mut session) = session {
+        if let Err(e) = session.audio_capture.stop_recording() {
+            // Abandon the session so a new recording can start
+            session.cancel.cancel();
+            if let Some(stream_task) = session.stream_task.take() {
+                let _ = stream_task.await;
+            }
+            state.lock().unwrap().current_recording = None;
+            return Err(e.to_string());
+        }
+
+        if let Some(mut stream_task) = session.stream_task.take() {
+            if tokio::time::timeout(STOP_TIMEOUT, &mut stream_task).await.is_err() {
+                eprintln!("Transcription did not finish within {:?}, cancelling", STOP_TIMEOUT);
+                session.cancel.cancel();
+                let _ = stream_task.await;
+            }
+        }
+        session.cancel.cancel();
+    }
+
+    // Create transcription from current state
+    let (mut transcription, usage_by_model) = {
+        let mut app_state = state.lock().unwrap();
+        if let Some(recording_state) = app_state.current_recording.take() {
+
+            let transcription = Transcription {
+                schema_version: TRANSCRIPTION_SCHEMA_VERSION,
+                id: recording_state.transcription_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+                title: "New Transcription".to_string(),
+                created_at: chrono::Utc::now(),
+                duration: recording_state.duration as u64,
+                chapters: Vec::new(),
+                raw_text: recording_state.current_text,
+                status: TranscriptionStatus::Completed,
+                segments: recording_state.segments,
+                usage: recording_state.usage,
+                notes: MeetingNotes::default(),
+                tags: Vec::new(),
+                folder: None,
+                participants: Vec::new(),
+                location: None,
+                metadata: Default::default(),
+                deleted_at: None,
+            };
+            (transcription, recording_state.usage_by_model)
+        } else {
+            return Err("No active recording".to_string());
+        }
+    };
+
+    // Saved under its first words before anything else can fail
+    if let Some(title) = editing::title_from_text(&transcription.raw_text) {
+        transcription.title = title;
+    }
+
+    state.lock().unwrap().transcriptions.insert(transcription.id.clone(), transcription.clone());
+    storage.save_revision(&transcription, RevisionSource::Live).await.map_err(|e| e.to_string())?;
+
+    // Chunks may come from different profiles and models
+    record_usage_by_model(&state, &storage, &window, &usage_by_model).await?;
+
+    if !auto_title.unwrap_or(false) || transcription.raw_text.trim().is_empty() {
+        return Ok(transcription);
+    }
+    match generate_title(transcription.id.clone(), state, storage, rate_limiters, window).await {
+        Ok(titled) => Ok(titled),
+        Err(e) => {
+            eprintln!("Could not generate a title: {}", e);
+            Ok(transcription)
+        }
+    }
+}
+
+/// Transcriptions matching `filter`, sorted and paginated as it asks.
+/// Without a filter every transcription is returned, newest first.
+#[tauri::command]
+async fn get_transcriptions(
+    filter: Option<TranscriptionFilter>,
+    state: State<'_, AppStateType>,
+) -> std::result::Result<Vec<Transcription>, String> {
+    let filter = filter.unwrap_or_default();
+    let app_state = state.lock().unwrap();
+
+    Ok(filter.select(app_state.transcriptions.values()).into_iter().cloned().collect())
+}
+
+/// Number of transcriptions matching `filter`, ignoring its pagination.
+#[tauri::command]
+async fn count_transcriptions(
+    filter: Option<TranscriptionFilter>,
+    state: State<'_, AppStateType>,
+) -> std::result::Result<usize, String> {
+    let filter = filter.unwrap_or_default();
+    let app_state = state.lock().unwrap();
+    Ok(app_state.transcriptions.values().filter(|t| filter.matches(t)).count())
+}
+
+/// Every tag in use, with the number of transcriptions having it.
+#[tauri::command]
+async fn get_tags(
+    state: State<'_, AppStateType>,
+) -> std::result::Result<Vec<LabelCount>, String> {
+    let app_state = state.lock().unwrap();
+    Ok(label_counts(app_state.transcriptions.values().flat_map(|t| t.tags.iter())))
+}
+
+/// Every folder in use, with the number of transcriptions filed in it.
+#[tauri::command]
+async fn get_folders(
+    state: State<'_, AppStateType>,
+) -> std::result::Result<Vec<LabelCount>, String> {
+    let app_state = state.lock().unwrap();
+    Ok(label_counts(app_state.transcriptions.values().filter_map(|t| t.folder.as_ref())))
+}
+
+/// Labels differing only in case or spacing are counted together under
+/// the first of their spellings in sort order.
+fn label_counts<'a>(labels: impl Iterator<Item = &'a String>) -> Vec<LabelCount> {
+    let mut counts: std::collections::BTreeMap<String, LabelCount> = std::collections::BTreeMap::new();
+    for label in labels {
+        let count = counts
+            .entry(models::label_key(label))
+            .or_insert_with(|| LabelCount { name: label.clone(), count: 0 });
+        count.count += 1;
+        if *label < count.name {
+            count.name = label.clone();
+        }
+    }
+    counts.into_values().collect()
+}
+
+/// Ranked full-text search over titles, summaries, chapters and text.
+#[tauri::command]
+async fn search_transcriptions(
+    query: String,
+    limit: Option<usize>,
+    storage: State<'_, StorageService>,
+) -> std::result::Result<Vec<SearchHit>, String> {
+    Ok(storage.search(&query, limit.unwrap_or(SEARCH_RESULT_LIMIT)))
+}
+
+#[tauri::command]
+async fn get_transcription(
+    id: String,
+    storage: State<'_, StorageService>,
+) -> std::result::Result<Transcription, String> {
+    storage.load_transcription(&id).await.map_err(|e| e.to_string())
+}
+
+/// Loads a transcription that is not in the trash, for commands that
+/// change it.
+async fn load_active(storage: &StorageService, id: &str) -> std::result::Result<Transcription, String> {
+    let transcription = storage.load_transcription(id).await.map_err(|e| e.to_string())?;
+    if transcription.deleted_at.is_some() {
+        return Err(format!("Transcription {} is in the trash", id));
+    }
+    Ok(transcription)
+}
+
+async fn move_to_trash(
+    state: &AppStateType,
+    storage: &StorageService,
+    mut transcription: Transcription,
+) -> std::result::Result<(), String> {
+    transcription.deleted_at = Some(chrono::Utc::now());
+    storage.save_revision(&transcription, RevisionSource::Trashed).await.map_err(|e| e.to_string())?;
+
+    let mut app_state = state.lock().unwrap();
+    app_state.transcriptions.remove(&transcription.id);
+    app_state.trash.insert(transcription.id.clone(), transcription);
+    Ok(())
+}
+
+/// Moves a transcription to the trash. One already in the trash is
+/// deleted for good. There is no audio to move or delete with it; see
+/// `Transcription`.
+#[tauri::command]
+async fn delete_transcription(
+    id: String,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+) -> std::result::Result<(), String> {
+    let transcription = storage.load_transcription(&id).await.map_err(|e| e.to_string())?;
+    if transcription.deleted_at.is_none() {
+        return move_to_trash(&state, &storage, transcription).await;
+    }
+
+    storage.delete_transcription(&id).await.map_err(|e| e.to_string())?;
+    state.lock().unwrap().trash.remove(&id);
+    Ok(())
+}
+
+/// Transcriptions in the trash, most recently deleted first. Those past
+/// the retention period are purged first.
+#[tauri::command]
+async fn list_trash(
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+) -> std::result::Result<Vec<Transcription>, String> {
+    purge_expired_trash(&state, &storage).await.map_err(|e| e.to_string())?;
+
+    let app_state = state.lock().unwrap();
+    let mut trash: Vec<Transcription> = app_state.trash.values().cloned().collect();
+    trash.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at).then_with(|| a.id.cmp(&b.id)));
+    Ok(trash)
+}
+
+#[tauri::command]
+async fn restore_transcription(
+    id: String,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+) -> std::result::Result<Transcription, String> {
+    restore_from_trash(&state, &storage, &id).await
+}
+
+async fn restore_from_trash(
+    state: &AppStateType,
+    storage: &StorageService,
+    id: &str,
+) -> std::result::Result<Transcription, String> {
+    let mut transcription = storage.load_transcription(id).await.map_err(|e| e.to_string())?;
+    if transcription.deleted_at.is_none() {
+        return Err(format!("Transcription {} is not in the trash", id));
+    }
+
+    // Refuse to overwrite a copy that was saved again since it was trashed
+    {
         let app_state = state.lock().unwrap();
-        (app_state.gemini_api_key.clone(), app_state.selected_model.clone())
+        let trashed_at = app_state.trash.get(id).and_then(|t| t.deleted_at);
+        if app_state.transcriptions.contains_key(id) || trashed_at != transcription.deleted_at {
+            return Err(format!("Transcription {} changed since it was moved to the trash", id));
+        }
+    }
+
+    transcription.deleted_at = None;
+    storage.save_revision(&transcription, RevisionSource::RestoredFromTrash).await.map_err(|e| e.to_string())?;
+
+    {
+        let mut app_state = state.lock().unwrap();
+        app_state.trash.remove(id);
+        app_state.transcriptions.insert(transcription.id.clone(), transcription.clone());
+    }
+
+    Ok(transcription)
+}
+
+/// Deletes everything in the trash for good and returns how many
+/// transcriptions were deleted.
+#[tauri::command]
+async fn empty_trash(
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+) -> std::result::Result<usize, String> {
+    let ids: Vec<String> = state.lock().unwrap().trash.keys().cloned().collect();
+    purge(&state, &storage, &ids).await.map_err(|e| e.to_string())
+}
+
+/// Deletes trashed transcriptions older than `trash_retention_days`.
+async fn purge_expired_trash(state: &AppStateType, storage: &StorageService) -> Result<usize> {
+    let expired: Vec<String> = {
+        let app_state = state.lock().unwrap();
+        if app_state.trash_retention_days == 0 {
+            return Ok(0);
+        }
+
+        // A retention reaching before the earliest representable date never purges
+        let Some(cutoff) = chrono::Utc::now()
+            .checked_sub_signed(chrono::Duration::days(app_state.trash_retention_days as i64))
+        else {
+            return Ok(0);
+        };
+        app_state
+            .trash
+            .values()
+            .filter(|t| t.deleted_at.is_some_and(|deleted_at| deleted_at < cutoff))
+            .map(|t| t.id.clone())
+            .collect()
     };
 
+    let purged = purge(state, storage, &expired).await?;
+    if purged > 0 {
+        println!("Purged {} transcriptions from the trash", purged);
+    }
+    Ok(purged)
+}
+
+async fn purge(state: &AppStateType, storage: &StorageService, ids: &[String]) -> Result<usize> {
+    for id in ids {
+        storage.delete_transcription(id).await?;
+        state.lock().unwrap().trash.remove(id);
+    }
+    Ok(ids.len())
+}
+
+/// Applies manual edits, all or none, and records them as a revision.
+#[tauri::command]
+async fn update_transcription(
+    id: String,
+    edits: Vec<TranscriptionEdit>,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+) -> std::result::Result<Transcription, String> {
+    if edits.is_empty() {
+        return Err("No edits given".to_string());
+    }
+
+    let mut transcription = load_active(&storage, &id).await?;
+    editing::apply(&mut transcription, &edits).map_err(|e| e.to_string())?;
+
+    {
+        let mut app_state = state.lock().unwrap();
+        app_state.transcriptions.insert(transcription.id.clone(), transcription.clone());
+    }
+
+    storage.save_revision(&transcription, RevisionSource::ManualEdit).await.map_err(|e| e.to_string())?;
+    Ok(transcription)
+}
+
+/// Splits a transcription in two at `at` seconds. Returns both parts; the
+/// first keeps the id. Only the transcript is split, as no audio is kept
+/// (see `Transcription`).
+#[tauri::command]
+async fn split_transcription(
+    id: String,
+    at: f64,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+) -> std::result::Result<Vec<Transcription>, String> {
+    let transcription = load_active(&storage, &id).await?;
+    let (first, second) = editing::split(&transcription, at).map_err(|e| e.to_string())?;
+
+    storage.save_revision(&second, RevisionSource::ManualEdit).await.map_err(|e| e.to_string())?;
+    if let Err(e) = storage.save_revision(&first, RevisionSource::ManualEdit).await {
+        // Without the shortened first part the second would duplicate its text
+        if let Err(e) = storage.delete_transcription(&second.id).await {
+            eprintln!("Could not remove the second part {} of a failed split: {}", second.id, e);
+        }
+        return Err(e.to_string());
+    }
+
+    {
+        let mut app_state = state.lock().unwrap();
+        app_state.transcriptions.insert(first.id.clone(), first.clone());
+        app_state.transcriptions.insert(second.id.clone(), second.clone());
+    }
+
+    Ok(vec![first, second])
+}
+
+/// Joins transcriptions, in the order of `ids`, into the first one and
+/// moves the others to the trash. If one cannot be moved, the first is
+/// put back as it was and those already moved are restored.
+#[tauri::command]
+async fn merge_transcriptions(
+    ids: Vec<String>,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+) -> std::result::Result<Transcription, String> {
+    let unique: std::collections::HashSet<&String> = ids.iter().collect();
+    if unique.len() != ids.len() {
+        return Err("A transcription cannot be merged with itself".to_string());
+    }
+
+    let mut transcriptions = Vec::with_capacity(ids.len());
+    for id in &ids {
+        transcriptions.push(load_active(&storage, id).await?);
+    }
+    let merged = editing::merge(&transcriptions).map_err(|e| e.to_string())?;
+
+    storage.save_revision(&merged, RevisionSource::ManualEdit).await.map_err(|e| e.to_string())?;
+    state.lock().unwrap().transcriptions.insert(merged.id.clone(), merged.clone());
+
+    for (moved, transcription) in transcriptions.iter().skip(1).enumerate() {
+        if let Err(e) = move_to_trash(&state, &storage, transcription.clone()).await {
+            undo_merge(&state, &storage, &transcriptions[..moved + 1]).await;
+            return Err(format!("Could not merge: {}", e));
+        }
+    }
+
+    Ok(merged)
+}
+
+/// Saves `originals` again as they were before a merge: the first in
+/// place of the merged transcription, the rest back out of the trash.
+async fn undo_merge(state: &AppStateType, storage: &StorageService, originals: &[Transcription]) {
+    for original in originals {
+        let source = if original.id == originals[0].id {
+            RevisionSource::Restore
+        } else {
+            RevisionSource::RestoredFromTrash
+        };
+        if let Err(e) = storage.save_revision(original, source).await {
+            eprintln!("Could not restore {} after a failed merge: {}", original.id, e);
+            continue;
+        }
+        let mut app_state = state.lock().unwrap();
+        app_state.trash.remove(&original.id);
+        app_state.transcriptions.insert(original.id.clone(), original.clone());
+    }
+}
+
+/// Keeps only the part between `start_time` and `end_time`, re-based to
+/// start at zero.
+#[tauri::command]
+async fn trim_transcription(
+    id: String,
+    start_time: f64,
+    end_time: f64,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+) -> std::result::Result<Transcription, String> {
+    let transcription = load_active(&storage, &id).await?;
+    let trimmed = editing::trim(&transcription, start_time, end_time).map_err(|e| e.to_string())?;
+
+    {
+        let mut app_state = state.lock().unwrap();
+        app_state.transcriptions.insert(trimmed.id.clone(), trimmed.clone());
+    }
+
+    storage.save_revision(&trimmed, RevisionSource::ManualEdit).await.map_err(|e| e.to_string())?;
+    Ok(trimmed)
+}
+
+/// Replaces the title with one generated from the content.
+#[tauri::command]
+async fn generate_title(
+    id: String,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+    rate_limiters: State<'_, RateLimiterRegistry>,
+    window: Window,
+) -> std::result::Result<Transcription, String> {
+    let mut transcription = load_active(&storage, &id).await?;
+    let transcription_service = create_transcription_service(&state, &rate_limiters, None)?
+        .ok_or_else(|| "API key not configured".to_string())?;
+
+    let result = transcription_service.generate_title(&transcription).await;
+    let usage = transcription_service.take_usage();
+    if result.is_err() {
+        record_usage_by_model(&state, &storage, &window, &usage).await?;
+    }
+
+    transcription.title = result.map_err(|e| e.to_string())?;
+    usage.values().for_each(|u| transcription.usage.add(u));
+
+    {
+        let mut app_state = state.lock().unwrap();
+        app_state.transcriptions.insert(transcription.id.clone(), transcription.clone());
+    }
+
+    storage.save_revision(&transcription, RevisionSource::Analysis).await.map_err(|e| e.to_string())?;
+    record_usage_by_model(&state, &storage, &window, &usage).await?;
+    Ok(transcription)
+}
+
+/// Saved versions of a transcription, newest first.
+#[tauri::command]
+async fn list_revisions(
+    id: String,
+    storage: State<'_, StorageService>,
+) -> std::result::Result<Vec<RevisionSummary>, String> {
+    let revisions = storage.load_revisions(&id).await.map_err(|e| e.to_string())?;
+    Ok(revisions.iter().rev().map(|revision| revision.summary()).collect())
+}
+
+#[tauri::command]
+async fn get_revision(
+    id: String,
+    revision_id: String,
+    storage: State<'_, StorageService>,
+) -> std::result::Result<Revision, String> {
+    storage
+        .load_revisions(&id)
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .find(|revision| revision.id == revision_id)
+        .ok_or_else(|| format!("Revision {} not found", revision_id))
+}
+
+/// Makes an earlier revision current again, recorded as a new revision so
+/// the restore can itself be undone. Token usage is not rolled back.
+#[tauri::command]
+async fn restore_revision(
+    id: String,
+    revision_id: String,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+) -> std::result::Result<Transcription, String> {
+    let current = load_active(&storage, &id).await?;
+    let revision = storage
+        .load_revisions(&id)
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .find(|revision| revision.id == revision_id)
+        .ok_or_else(|| format!("Revision {} not found", revision_id))?;
+
+    let mut transcription = revision.transcription;
+    transcription.usage = current.usage;
+    // Revisions recorded when it was trashed still carry the deletion time
+    transcription.deleted_at = None;
+
+    {
+        let mut app_state = state.lock().unwrap();
+        app_state.transcriptions.insert(transcription.id.clone(), transcription.clone());
+    }
+
+    storage.save_revision(&transcription, RevisionSource::Restore).await.map_err(|e| e.to_string())?;
+    Ok(transcription)
+}
+
+#[tauri::command]
+async fn export_transcription(
+    id: String,
+    format: ExportFormat,
+    storage: State<'_, StorageService>,
+) -> std::result::Result<String, String> {
+    let transcription = storage.load_transcription(&id).await.map_err(|e| e.to_string())?;
+    let export_service = ExportService::new((*storage).clone());
+    export_service.export_transcription(&transcription, &format).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn validate_api_key(
+    api_key: String,
+    state: State<'_, AppStateType>,
+    rate_limiters: State<'_, RateLimiterRegistry>,
+) -> std::result::Result<ApiKeyValidation, String> {
+    let transcription_service = {
+        let app_state = state.lock().unwrap();
+        let rate_limiter = rate_limiters.limiter(transcription::DEFAULT_PROFILE_ID, &app_state.rate_limit("gemini"));
+        TranscriptionService::new(
+            api_key,
+            app_state.selected_model.clone(),
+            &app_state.provider_settings,
+            rate_limiter,
+        )
+        .map_err(|e| e.to_string())?
+    };
+
+    Ok(transcription_service.validate_api_key().await)
+}
+
+#[tauri::command]
+async fn set_api_key(
+    api_key: String,
+    force: Option<bool>,
+    state: State<'_, AppStateType>,
+    rate_limiters: State<'_, RateLimiterRegistry>,
+    secret_manager: State<'_, SecretManager>,
+) -> std::result::Result<(), String> {
+    if !force.unwrap_or(false) {
+        let validation = validate_api_key(api_key.clone(), state.clone(), rate_limiters).await?;
+        if !validation.is_accepted() {
+            return Err(format!(
+                "API key rejected ({:?}): {}",
+                validation.status,
+                validation.message.unwrap_or_default()
+            ));
+        }
+    }
+
+    secret_manager
+        .set(secrets::GEMINI_API_KEY, &api_key)
+        .map_err(|e| e.to_string())?;
+
+    let mut app_state = state.lock().unwrap();
+    app_state.gemini_api_key = Some(api_key);
+    Ok(())
+}
+
+#[tauri::command]
+async fn clear_api_key(
+    state: State<'_, AppStateType>,
+    secret_manager: State<'_, SecretManager>,
+) -> std::result::Result<(), String> {
+    secret_manager
+        .delete(secrets::GEMINI_API_KEY)
+        .map_err(|e| e.to_string())?;
+
+    let mut app_state = state.lock().unwrap();
+    app_state.gemini_api_key = None;
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_profiles(
+    state: State<'_, AppStateType>,
+) -> std::result::Result<Vec<CredentialProfile>, String> {
+    let app_state = state.lock().unwrap();
+    Ok(app_state.profiles.clone())
+}
+
+/// Creates or updates a credential profile. A new `api_key` is validated
+/// against the profile's endpoint unless `force` is set.
+#[tauri::command]
+async fn save_profile(
+    mut profile: CredentialProfile,
+    api_key: Option<String>,
+    force: Option<bool>,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+    rate_limiters: State<'_, RateLimiterRegistry>,
+    secret_manager: State<'_, SecretManager>,
+) -> std::result::Result<CredentialProfile, String> {
+    if profile.provider != "gemini" {
+        return Err(format!("Unsupported provider: {}", profile.provider));
+    }
+    if profile.id.is_empty() {
+        profile.id = uuid::Uuid::new_v4().to_string();
+    }
+
     if let Some(api_key) = api_key {
-        let transcription_service = TranscriptionService::new(api_key, model);
-        let transcription_rx = transcription_service
-            .start_streaming_transcription(audio_rx)
-            .await
+        if !force.unwrap_or(false) {
+            let (mut settings, rate_limit) = {
+                let app_state = state.lock().unwrap();
+                (app_state.provider_settings.clone(), app_state.rate_limit(&profile.provider))
+            };
+            if let Some(base_url) = &profile.base_url {
+                settings.base_url = base_url.clone();
+            }
+            let rate_limiter = rate_limiters.limiter(&profile.id, &rate_limit);
+
+            let validation = TranscriptionService::new(api_key.clone(), profile.model.clone(), &settings, rate_limiter)
+                .map_err(|e| e.to_string())?
+                .validate_api_key()
+                .await;
+            if !validation.is_accepted() {
+                return Err(format!(
+                    "API key rejected ({:?}): {}",
+                    validation.status,
+                    validation.message.unwrap_or_default()
+                ));
+            }
+        }
+
+        secret_manager
+            .set(&secrets::profile_key_name(&profile.id), &api_key)
             .map_err(|e| e.to_string())?;
+        state.lock().unwrap().profile_keys.insert(profile.id.clone(), api_key);
+    }
+
+    let app_state = {
+        let mut app_state = state.lock().unwrap();
+        match app_state.profiles.iter_mut().find(|p| p.id == profile.id) {
+            Some(existing) => *existing = profile.clone(),
+            None => app_state.profiles.push(profile.clone()),
+        }
+        app_state.clone()
+    };
+
+    storage.save_app_state(&app_state).await.map_err(|e| e.to_string())?;
+    Ok(profile)
+}
+
+#[tauri::command]
+async fn delete_profile(
+    id: String,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+    secret_manager: State<'_, SecretManager>,
+) -> std::result::Result<(), String> {
+    secret_manager
+        .delete(&secrets::profile_key_name(&id))
+        .map_err(|e| e.to_string())?;
+
+    let app_state = {
+        let mut app_state = state.lock().unwrap();
+        app_state.profiles.retain(|p| p.id != id);
+        app_state.profile_keys.remove(&id);
+        if app_state.default_profile_id.as_deref() == Some(id.as_str()) {
+            app_state.default_profile_id = None;
+        }
+        app_state.clone()
+    };
+
+    storage.save_app_state(&app_state).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_default_profile(
+    id: Option<String>,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+) -> std::result::Result<(), String> {
+    let app_state = {
+        let mut app_state = state.lock().unwrap();
+        if let Some(id) = &id {
+            let known = id == transcription::DEFAULT_PROFILE_ID || app_state.profiles.iter().any(|p| &p.id == id);
+            if !known {
+                return Err(format!("Unknown profile: {}", id));
+            }
+        }
+        app_state.default_profile_id = id;
+        app_state.clone()
+    };
+
+    storage.save_app_state(&app_state).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_secret_store_status(
+    secret_manager: State<'_, SecretManager>,
+) -> std::result::Result<SecretStoreStatus, String> {
+    Ok(secret_manager.status())
+}
+
+#[tauri::command]
+async fn unlock_secret_store(
+    passphrase: String,
+    confirmation: Option<String>,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+    secret_manager: State<'_, SecretManager>,
+) -> std::result::Result<SecretStoreStatus, String> {
+    secret_manager.unlock(&passphrase, confirmation.as_deref()).map_err(|e| e.to_string())?;
+    load_secrets(&state, &storage, &secret_manager).await.map_err(|e| e.to_string())?;
+    Ok(secret_manager.status())
+}
+
+/// Moves a plaintext key left over in `app_state.json` into the secret store
+/// (rewriting the file without it), or loads the stored key into memory.
+/// While the store is locked the plaintext key is only used in memory and
+/// stays in the file.
+async fn load_secrets(
+    state: &AppStateType,
+    storage: &StorageService,
+    secret_manager: &SecretManager,
+) -> Result<()> {
+    if !secret_manager.is_unlocked() {
+        let mut app_state = state.lock().unwrap();
+        if app_state.gemini_api_key.is_none() {
+            app_state.gemini_api_key = app_state.plaintext_api_key.clone();
+        }
+        return Ok(());
+    }
+
+    let plaintext_key = state.lock().unwrap().plaintext_api_key.clone();
+
+    match plaintext_key {
+        Some(api_key) if secret_manager.get(secrets::GEMINI_API_KEY)?.is_none() => {
+            println!("Migrating plaintext API key into the secret store");
+            secret_manager.set(secrets::GEMINI_API_KEY, &api_key)?;
+            state.lock().unwrap().gemini_api_key = Some(api_key);
+        }
+        _ => {
+            let stored_key = secret_manager.get(secrets::GEMINI_API_KEY)?;
+            state.lock().unwrap().gemini_api_key = stored_key;
+        }
+    }
+    state.lock().unwrap().plaintext_api_key = None;
+
+    let profile_ids: Vec<String> = state.lock().unwrap().profiles.iter().map(|p| p.id.clone()).collect();
+    for profile_id in profile_ids {
+        if let Some(api_key) = secret_manager.get(&secrets::profile_key_name(&profile_id))? {
+            state.lock().unwrap().profile_keys.insert(profile_id, api_key);
+        }
+    }
+
+    let app_state = state.lock().unwrap().clone();
+    storage.save_app_state(&app_state).await
+}
+
+/// Loads the transcription files into the state and writes out any
+/// transcription that only exists in an old `app_state.json`, which is then
+/// saved again without the map.
+async fn reconcile_transcriptions(state: &AppStateType, storage: &StorageService) -> Result<()> {
+    let loaded = storage.load_all_transcriptions().await?;
+    let legacy = std::mem::take(&mut state.lock().unwrap().transcriptions);
+    let corrupt_ids: std::collections::HashSet<String> = loaded
+        .corrupt
+        .iter()
+        .filter_map(|file| std::path::Path::new(&file.path).file_stem()?.to_str().map(|s| s.to_string()))
+        .collect();
+
+    let mut transcriptions = loaded.transcriptions;
+    storage.index_transcriptions(transcriptions.values());
+
+    let mut report = StorageReport {
+        loaded: transcriptions.len(),
+        ..Default::default()
+    };
+
+    for (id, transcription) in legacy {
+        // Never overwrite a file that failed to parse; it is left for the user
+        if transcriptions.contains_key(&id) || corrupt_ids.contains(&id) {
+            continue;
+        }
+        storage.save_transcription(&transcription).await?;
+        transcriptions.insert(id, transcription);
+        report.recovered += 1;
+    }
+
+    for file in &loaded.corrupt {
+        eprintln!("Failed to load transcription {}: {}", file.path, file.error);
+    }
+    report.corrupt = loaded.corrupt;
+
+    println!(
+        "Loaded {} transcriptions ({} recovered from app state, {} corrupt)",
+        report.loaded,
+        report.recovered,
+        report.corrupt.len()
+    );
+
+    let (trash, transcriptions): (std::collections::HashMap<_, _>, std::collections::HashMap<_, _>) =
+        transcriptions.into_iter().partition(|(_, t)| t.deleted_at.is_some());
+
+    let app_state = {
+        let mut app_state = state.lock().unwrap();
+        app_state.transcriptions = transcriptions;
+        app_state.trash = trash;
+        app_state.storage_report = report;
+        app_state.clone()
+    };
+    storage.save_app_state(&app_state).await
+}
+
+#[tauri::command]
+async fn get_storage_report(
+    state: State<'_, AppStateType>,
+) -> std::result::Result<StorageReport, String> {
+    Ok(state.lock().unwrap().storage_report.clone())
+}
+
+#[tauri::command]
+async fn get_available_models(
+    force_refresh: Option<bool>,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+    rate_limiters: State<'_, RateLimiterRegistry>,
+) -> std::result::Result<Vec<models::GeminiModel>, String> {
+    Ok(resolve_available_models(&state, &storage, &rate_limiters, force_refresh.unwrap_or(false)).await)
+}
+
+/// The provider's catalogue, cached for `MODELS_CACHE_TTL_HOURS`; a stale
+/// cache is still used when the provider cannot be reached, and the static
+/// list only when there is nothing else.
+async fn resolve_available_models(
+    state: &AppStateType,
+    storage: &StorageService,
+    rate_limiters: &RateLimiterRegistry,
+    force_refresh: bool,
+) -> Vec<models::GeminiModel> {
+    let cache = storage.load_models_cache().await.unwrap_or_else(|e| {
+        eprintln!("Failed to load models cache: {}", e);
+        None
+    });
+
+    if let Some(cache) = &cache {
+        if cache.is_fresh() && !force_refresh {
+            return models::merge_models(cache.models.clone());
+        }
+    }
+
+    let transcription_service = create_transcription_service(state, rate_limiters, None).unwrap_or_else(|e| {
+        eprintln!("Failed to create transcription service: {}", e);
+        None
+    });
+
+    if let Some(transcription_service) = transcription_service {
+        match transcription_service.list_models().await {
+            Ok(discovered) => {
+                let cache = ModelsCache {
+                    fetched_at: chrono::Utc::now(),
+                    models: discovered.clone(),
+                };
+                if let Err(e) = storage.save_models_cache(&cache).await {
+                    eprintln!("Failed to save models cache: {}", e);
+                }
+                return models::merge_models(discovered);
+            }
+            Err(e) => eprintln!("Model discovery failed: {}", e),
+        }
+    }
+
+    models::merge_models(cache.map(|c| c.models).unwrap_or_default())
+}
+
+#[tauri::command]
+async fn set_selected_model(
+    model: String,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+    rate_limiters: State<'_, RateLimiterRegistry>,
+) -> std::result::Result<(), String> {
+    let available = resolve_available_models(&state, &storage, &rate_limiters, false).await;
+    if !available.iter().any(|m| m.id == model) {
+        return Err(format!("Unknown model: {}", model));
+    }
 
-        // Spawn task to handle transcription updates
-        let window_clone = window.clone();
-        let state_clone = state.inner().clone();
-        tokio::spawn(async move {
-            handle_transcription_stream(transcription_rx, window_clone, state_clone).await;
-        });
+    {
+        let mut app_state = state.lock().unwrap();
+        app_state.selected_model = model;
     }
 
-    Ok(transcription_id)
+    let app_state = state.lock().unwrap().clone();
+    storage.save_app_state(&app_state).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_provider_settings(
+    state: State<'_, AppStateType>,
+) -> std::result::Result<ProviderSettings, String> {
+    let app_state = state.lock().unwrap();
+    Ok(app_state.provider_settings.clone())
 }
 
 #[tauri::command]
-async fn stop_recording(
+async fn set_provider_settings(
+    settings: ProviderSettings,
     state: State<'_, AppStateType>,
     storage: State<'_, StorageService>,
-) -> std::result::Result<Transcription, String> {
+) -> std::result::Result<(), String> {
+    // Reject settings that would not produce a working client
+    TranscriptionService::build_client(&settings).map_err(|e| e.to_string())?;
 
-    // Create transcription from current state
-    println!("Stopping recording and creating transcription");
-    let transcription = {
+    {
         let mut app_state = state.lock().unwrap();
-        if let Some(mut recording_state) = app_state.current_recording.take() {
-            // Set recording to false to signal audio capture to stop
-            recording_state.is_recording = false;
-
-            let transcription = Transcription {
-                id: recording_state.transcription_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
-                title: "New Transcription".to_string(),
-                created_at: chrono::Utc::now(),
-                duration: recording_state.duration as u64,
-                chapters: Vec::new(),
-                raw_text: recording_state.current_text,
-                status: TranscriptionStatus::Completed,
-            };
-
-            app_state.transcriptions.insert(transcription.id.clone(), transcription.clone());
-            transcription
-        } else {
-            return Err("No active recording".to_string());
-        }
-    };
-
-    // Save transcription
-    storage.save_transcription(&transcription).await.map_err(|e| e.to_string())?;
+        app_state.provider_settings = settings;
+    }
 
-    Ok(transcription)
+    let app_state = state.lock().unwrap().clone();
+    storage.save_app_state(&app_state).await.map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn get_transcriptions(
+async fn get_rate_limits(
     state: State<'_, AppStateType>,
-) -> std::result::Result<Vec<Transcription>, String> {
+) -> std::result::Result<std::collections::HashMap<String, RateLimitSettings>, String> {
     let app_state = state.lock().unwrap();
-    let transcriptions: Vec<Transcription> = app_state.transcriptions.values().cloned().collect();
-    Ok(transcriptions)
-}
-
-#[tauri::command]
-async fn get_transcription(
-    id: String,
-    storage: State<'_, StorageService>,
-) -> std::result::Result<Transcription, String> {
-    storage.load_transcription(&id).await.map_err(|e| e.to_string())
+    Ok(app_state.rate_limits.clone())
 }
 
 #[tauri::command]
-async fn delete_transcription(
-    id: String,
+async fn set_rate_limit(
+    provider: String,
+    settings: RateLimitSettings,
     state: State<'_, AppStateType>,
     storage: State<'_, StorageService>,
 ) -> std::result::Result<(), String> {
+    if settings.requests_per_minute == 0 || settings.tokens_per_minute == 0 || settings.max_concurrent_requests == 0 {
+        return Err("Rate limits must be greater than zero".to_string());
+    }
+
     {
         let mut app_state = state.lock().unwrap();
-        app_state.transcriptions.remove(&id);
+        app_state.rate_limits.insert(provider, settings);
     }
 
-    storage.delete_transcription(&id).await.map_err(|e| e.to_string())
+    let app_state = state.lock().unwrap().clone();
+    storage.save_app_state(&app_state).await.map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn export_transcription(
-    id: String,
-    format: ExportFormat,
-    storage: State<'_, StorageService>,
-) -> std::result::Result<String, String> {
-    let transcription = storage.load_transcription(&id).await.map_err(|e| e.to_string())?;
-    let export_service = ExportService::new((*storage).clone());
-    export_service.export_transcription(&transcription, &format).await.map_err(|e| e.to_string())
+async fn get_pipeline_settings(
+    state: State<'_, AppStateType>,
+) -> std::result::Result<PipelineSettings, String> {
+    let app_state = state.lock().unwrap();
+    Ok(app_state.pipeline_settings.clone())
 }
 
 #[tauri::command]
-async fn set_api_key(
-    api_key: String,
+async fn set_pipeline_settings(
+    settings: PipelineSettings,
     state: State<'_, AppStateType>,
     storage: State<'_, StorageService>,
 ) -> std::result::Result<(), String> {
+    if settings.audio_queue_capacity == 0 || settings.transcription_queue_capacity == 0 {
+        return Err("Queue capacities must be greater than zero".to_string());
+    }
+
     {
         let mut app_state = state.lock().unwrap();
-        app_state.gemini_api_key = Some(api_key);
+        app_state.pipeline_settings = settings;
     }
 
     let app_state = state.lock().unwrap().clone();
@@ -163,19 +1291,56 @@ async fn set_api_key(
 }
 
 #[tauri::command]
-async fn get_available_models() -> std::result::Result<Vec<models::GeminiModel>, String> {
-    Ok(models::get_available_models())
+async fn get_trash_retention_days(
+    state: State<'_, AppStateType>,
+) -> std::result::Result<u32, String> {
+    Ok(state.lock().unwrap().trash_retention_days)
 }
 
+/// 0 keeps trashed transcriptions until the trash is emptied.
 #[tauri::command]
-async fn set_selected_model(
-    model: String,
+async fn set_trash_retention_days(
+    days: u32,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+) -> std::result::Result<(), String> {
+    if days > models::MAX_TRASH_RETENTION_DAYS {
+        return Err(format!(
+            "Trash retention cannot be longer than {} days",
+            models::MAX_TRASH_RETENTION_DAYS
+        ));
+    }
+
+    let app_state = {
+        let mut app_state = state.lock().unwrap();
+        app_state.trash_retention_days = days;
+        app_state.clone()
+    };
+
+    storage.save_app_state(&app_state).await.map_err(|e| e.to_string())?;
+    purge_expired_trash(&state, &storage).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_storage_backend(
+    state: State<'_, AppStateType>,
+) -> std::result::Result<StorageBackend, String> {
+    let app_state = state.lock().unwrap();
+    Ok(app_state.storage_backend)
+}
+
+/// Selects the backend used from the next start on, when existing
+/// transcriptions are migrated to it.
+#[tauri::command]
+async fn set_storage_backend(
+    backend: StorageBackend,
     state: State<'_, AppStateType>,
     storage: State<'_, StorageService>,
 ) -> std::result::Result<(), String> {
     {
         let mut app_state = state.lock().unwrap();
-        app_state.selected_model = model;
+        app_state.storage_backend = backend;
     }
 
     let app_state = state.lock().unwrap().clone();
@@ -203,22 +1368,30 @@ async fn analyze_transcription_structure(
     id: String,
     state: State<'_, AppStateType>,
     storage: State<'_, StorageService>,
+    rate_limiters: State<'_, RateLimiterRegistry>,
+    window: Window,
 ) -> std::result::Result<Transcription, String> {
-    let mut transcription = storage.load_transcription(&id).await.map_err(|e| e.to_string())?;
+    let mut transcription = load_active(&storage, &id).await?;
 
-    let (api_key, model) = {
-        let app_state = state.lock().unwrap();
-        (app_state.gemini_api_key.clone(), app_state.selected_model.clone())
-    };
+    if let Some(transcription_service) = create_transcription_service(&state, &rate_limiters, None)? {
+        let result = {
+            let progress_window = window.clone();
+            let on_progress = move |progress: AnalysisProgress| {
+                let _ = progress_window.emit("analysis-progress", &progress);
+            };
+            transcription_service.analyze_chapters(&transcription, &on_progress).await
+        };
 
-    if let Some(api_key) = api_key {
-        let transcription_service = TranscriptionService::new(api_key, model);
-        let chapters = transcription_service
-            .analyze_content_structure(&transcription.raw_text)
-            .await
-            .map_err(|e| e.to_string())?;
+        // Rejected attempts are billed too
+        let usage = transcription_service.take_usage();
+        if result.is_err() {
+            record_usage_by_model(&state, &storage, &window, &usage).await?;
+        }
+        let chapters = result.map_err(|e| e.to_string())?;
 
         transcription.chapters = chapters;
+        transcription.refresh_chapter_confidence();
+        usage.values().for_each(|u| transcription.usage.add(u));
 
         // Update in state and storage
         {
@@ -226,18 +1399,230 @@ async fn analyze_transcription_structure(
             app_state.transcriptions.insert(transcription.id.clone(), transcription.clone());
         }
 
-        storage.save_transcription(&transcription).await.map_err(|e| e.to_string())?;
+        storage.save_revision(&transcription, RevisionSource::Analysis).await.map_err(|e| e.to_string())?;
+        record_usage_by_model(&state, &storage, &window, &usage).await?;
+    }
+
+    Ok(transcription)
+}
+
+/// Runs the selected meeting-notes analyses, all of them when `analyses` is
+/// empty, and stores the results on the transcription.
+#[tauri::command]
+async fn analyze_transcription(
+    id: String,
+    analyses: Vec<AnalysisKind>,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+    rate_limiters: State<'_, RateLimiterRegistry>,
+    window: Window,
+) -> std::result::Result<Transcription, String> {
+    let mut transcription = load_active(&storage, &id).await?;
+    let kinds: Vec<AnalysisKind> = if analyses.is_empty() {
+        AnalysisKind::ALL.to_vec()
+    } else {
+        AnalysisKind::ALL.into_iter().filter(|kind| analyses.contains(kind)).collect()
+    };
+
+    if let Some(transcription_service) = create_transcription_service(&state, &rate_limiters, None)? {
+        let result = transcription_service.analyze_notes(&transcription, &kinds).await;
+
+        // Rejected attempts are billed too
+        let usage = transcription_service.take_usage();
+        if result.is_err() {
+            record_usage_by_model(&state, &storage, &window, &usage).await?;
+        }
+        let notes = result.map_err(|e| e.to_string())?;
+
+        transcription.notes.merge(notes, &kinds);
+        usage.values().for_each(|u| transcription.usage.add(u));
+
+        {
+            let mut app_state = state.lock().unwrap();
+            app_state.transcriptions.insert(transcription.id.clone(), transcription.clone());
+        }
+
+        storage.save_revision(&transcription, RevisionSource::Analysis).await.map_err(|e| e.to_string())?;
+        record_usage_by_model(&state, &storage, &window, &usage).await?;
     }
 
     Ok(transcription)
 }
 
+/// Answers a question about a transcription with citations to the
+/// segments the answer is based on.
+#[tauri::command]
+async fn query_transcription(
+    id: String,
+    question: String,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+    rate_limiters: State<'_, RateLimiterRegistry>,
+    window: Window,
+) -> std::result::Result<QueryAnswer, String> {
+    if question.trim().is_empty() {
+        return Err("Question cannot be empty".to_string());
+    }
+
+    let transcription = load_active(&storage, &id).await?;
+    let transcription_service = create_transcription_service(&state, &rate_limiters, None)?
+        .ok_or_else(|| "API key not configured".to_string())?;
+
+    let result = transcription_service.query(&transcription, question.trim()).await;
+
+    // Billed to the transcription without a revision, since its content is unchanged
+    let usage = transcription_service.take_usage();
+    if !usage.is_empty() {
+        let mut transcription = load_active(&storage, &id).await?;
+        usage.values().for_each(|u| transcription.usage.add(u));
+        state.lock().unwrap().transcriptions.insert(transcription.id.clone(), transcription.clone());
+        storage.save_transcription(&transcription).await.map_err(|e| e.to_string())?;
+    }
+    record_usage_by_model(&state, &storage, &window, &usage).await?;
+
+    result.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn low_confidence_segments(
+    id: String,
+    threshold: Option<f32>,
+    storage: State<'_, StorageService>,
+) -> std::result::Result<Vec<TranscriptionSegment>, String> {
+    let transcription = storage.load_transcription(&id).await.map_err(|e| e.to_string())?;
+    let threshold = threshold.unwrap_or(LOW_CONFIDENCE_THRESHOLD);
+
+    Ok(transcription
+        .segments
+        .into_iter()
+        .filter(|segment| segment.confidence < threshold)
+        .collect())
+}
+
+#[tauri::command]
+async fn get_usage_report(
+    month: Option<String>,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+) -> std::result::Result<UsageReport, String> {
+    let ledger = storage.load_usage_ledger().await.map_err(|e| e.to_string())?;
+    let month = month.unwrap_or_else(usage::current_month);
+
+    let app_state = state.lock().unwrap();
+    Ok(usage::report(&ledger, &month, &app_state.model_prices, app_state.monthly_budget))
+}
+
+#[tauri::command]
+async fn get_model_prices(
+    state: State<'_, AppStateType>,
+) -> std::result::Result<std::collections::HashMap<String, ModelPrice>, String> {
+    let app_state = state.lock().unwrap();
+    Ok(app_state.model_prices.clone())
+}
+
+#[tauri::command]
+async fn set_model_price(
+    model: String,
+    price: ModelPrice,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+) -> std::result::Result<(), String> {
+    let rates = [price.input_per_million, price.audio_input_per_million, price.output_per_million];
+    if rates.iter().any(|rate| !rate.is_finite() || *rate < 0.0) {
+        return Err("Prices must be zero or greater".to_string());
+    }
+
+    {
+        let mut app_state = state.lock().unwrap();
+        app_state.model_prices.insert(model, price);
+    }
+
+    let app_state = state.lock().unwrap().clone();
+    storage.save_app_state(&app_state).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_monthly_budget(
+    budget: Option<f64>,
+    state: State<'_, AppStateType>,
+    storage: State<'_, StorageService>,
+) -> std::result::Result<(), String> {
+    if budget.is_some_and(|b| !b.is_finite() || b <= 0.0) {
+        return Err("Monthly budget must be greater than zero".to_string());
+    }
+
+    {
+        let mut app_state = state.lock().unwrap();
+        app_state.monthly_budget = budget;
+    }
+
+    let app_state = state.lock().unwrap().clone();
+    storage.save_app_state(&app_state).await.map_err(|e| e.to_string())
+}
+
+/// `record_usage` for each model that served part of an operation.
+async fn record_usage_by_model(
+    state: &AppStateType,
+    storage: &StorageService,
+    window: &Window,
+    usage: &std::collections::BTreeMap<String, TokenUsage>,
+) -> std::result::Result<(), String> {
+    for (model, usage) in usage {
+        record_usage(state, storage, window, model, usage).await?;
+    }
+    Ok(())
+}
+
+/// Adds `usage` to the daily ledger and emits `budget-alert` when this call
+/// pushes the current month over the warning or budget threshold.
+async fn record_usage(
+    state: &AppStateType,
+    storage: &StorageService,
+    window: &Window,
+    model: &str,
+    usage: &TokenUsage,
+) -> std::result::Result<(), String> {
+    if usage.is_empty() {
+        return Ok(());
+    }
+
+    let ledger = storage.record_usage(model, usage).await.map_err(|e| e.to_string())?;
+
+    let (report, added_cost) = {
+        let app_state = state.lock().unwrap();
+        let report = usage::report(&ledger, &usage::current_month(), &app_state.model_prices, app_state.monthly_budget);
+        (report, usage::cost(&app_state.model_prices, model, usage))
+    };
+
+    let previous_alert = usage::budget_alert(report.cost - added_cost, report.monthly_budget);
+    if report.budget_alert > previous_alert {
+        let _ = window.emit("budget-alert", &report);
+    }
+
+    Ok(())
+}
+
 async fn handle_transcription_stream(
-    mut transcription_rx: mpsc::UnboundedReceiver<TranscriptionChunk>,
+    mut transcription_rx: PipelineReceiver<TranscriptionChunk>,
     window: Window,
     state: AppStateType,
+    cancel: CancellationToken,
 ) {
-    while let Some(chunk) = transcription_rx.recv().await {
+    loop {
+        let message = tokio::select! {
+            _ = cancel.cancelled() => break,
+            message = transcription_rx.recv() => message,
+        };
+        let Some(message) = message else { break };
+
+        let chunk = match message {
+            Message::Item(chunk) => chunk,
+            Message::Gap { dropped } => {
+                let _ = window.emit("transcription-gap", dropped);
+                continue;
+            }
+        };
+
         // Update current recording state
         {
             let mut app_state = state.lock().unwrap();
@@ -245,11 +1630,18 @@ async fn handle_transcription_stream(
                 if !chunk.text.trim().is_empty() {
                     recording.current_text.push(' ');
                     recording.current_text.push_str(&chunk.text);
+                    recording.segments.push(chunk.to_segment());
                 }
                 recording.duration = chunk.end_time;
+                recording.usage.add(&chunk.usage);
+                recording.usage_by_model.entry(chunk.model.clone()).or_default().add(&chunk.usage);
             }
         }
 
+        if chunk.text.trim().is_empty() {
+            continue;
+        }
+
         // Emit event to frontend
         let _ = window.emit("transcription-chunk", &chunk);
     }
@@ -258,25 +1650,269 @@ async fn handle_transcription_stream(
 #[tokio::main]
 async fn main() {
     let storage = StorageService::new().expect("Failed to initialize storage");
-    let app_state = storage.load_app_state().await.unwrap_or_default();
+    let app_state: AppStateType = Arc::new(Mutex::new(storage.load_app_state().await.unwrap_or_default()));
+
+    let backend = app_state.lock().unwrap().storage_backend;
+    let storage = match storage.clone().use_backend(backend).await {
+        Ok(storage) => storage,
+        Err(e) => {
+            eprintln!("Failed to open {:?} storage, using JSON files: {}", backend, e);
+            storage
+        }
+    };
+
+    // Before anything else saves the app state, so a plaintext key is
+    // migrated rather than rewritten
+    let secret_manager = SecretManager::new(storage.data_dir());
+    if let Err(e) = load_secrets(&app_state, &storage, &secret_manager).await {
+        eprintln!("Failed to load secrets: {}", e);
+    }
+
+    if let Err(e) = reconcile_transcriptions(&app_state, &storage).await {
+        eprintln!("Failed to load transcriptions: {}", e);
+    }
+    if let Err(e) = purge_expired_trash(&app_state, &storage).await {
+        eprintln!("Failed to purge the trash: {}", e);
+    }
 
     tauri::Builder::default()
-        .manage(Arc::new(Mutex::new(app_state)))
+        .manage(app_state)
         .manage(storage)
+        .manage(secret_manager)
+        .manage(RateLimiterRegistry::default())
+        .manage(RecordingSessionType::default())
         .invoke_handler(tauri::generate_handler![
             start_recording,
             stop_recording,
             get_transcriptions,
+            count_transcriptions,
+            get_tags,
+            get_folders,
+            search_transcriptions,
             get_transcription,
             delete_transcription,
+            list_trash,
+            restore_transcription,
+            empty_trash,
+            get_trash_retention_days,
+            set_trash_retention_days,
+            update_transcription,
+            split_transcription,
+            merge_transcriptions,
+            trim_transcription,
+            generate_title,
+            list_revisions,
+            get_revision,
+            restore_revision,
+            get_storage_report,
             export_transcription,
             set_api_key,
+            validate_api_key,
+            clear_api_key,
+            get_profiles,
+            save_profile,
+            delete_profile,
+            set_default_profile,
+            get_secret_store_status,
+            unlock_secret_store,
             get_available_models,
             set_selected_model,
             get_selected_model,
+            get_provider_settings,
+            set_provider_settings,
+            get_rate_limits,
+            set_rate_limit,
+            get_pipeline_settings,
+            set_pipeline_settings,
+            get_storage_backend,
+            set_storage_backend,
             get_recording_state,
-            analyze_transcription_structure
+            analyze_transcription_structure,
+            analyze_transcription,
+            query_transcription,
+            low_confidence_segments,
+            get_usage_report,
+            get_model_prices,
+            set_model_price,
+            set_monthly_budget
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn transcription(id: &str, title: &str) -> Transcription {
+        serde_json::from_value(json!({
+            "schema_version": TRANSCRIPTION_SCHEMA_VERSION,
+            "id": id,
+            "title": title,
+            "created_at": "2024-05-01T10:00:00Z",
+            "duration": 60,
+            "chapters": [],
+            "raw_text": format!("{} text", title),
+            "status": "Completed"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn label_counts_group_spellings_of_the_same_label() {
+        let labels: Vec<String> = ["team", "Città", "TEAM", "città", "Team", "budget"]
+            .iter()
+            .map(|l| l.to_string())
+            .collect();
+
+        let counts: Vec<(String, usize)> = label_counts(labels.iter()).into_iter().map(|c| (c.name, c.count)).collect();
+
+        assert_eq!(
+            counts,
+            vec![("budget".to_string(), 1), ("Città".to_string(), 2), ("TEAM".to_string(), 3)]
+        );
+    }
+
+    #[tokio::test]
+    async fn reconciling_prefers_stored_files_and_recovers_missing_ones() {
+        // The JSON backend only loads files named by a UUID
+        const STORED: &str = "00000000-0000-4000-8000-000000000001";
+        const MISSING: &str = "00000000-0000-4000-8000-000000000002";
+        const TRASHED: &str = "00000000-0000-4000-8000-000000000003";
+        const CORRUPT: &str = "00000000-0000-4000-8000-000000000004";
+        let corrupt_path = format!("{}.json", CORRUPT);
+
+        let dir = tempfile::tempdir().unwrap();
+        let storage = StorageService::with_data_dir(dir.path().to_path_buf()).unwrap();
+
+        storage.save_transcription(&transcription(STORED, "Stored version")).await.unwrap();
+        let mut trashed = transcription(TRASHED, "Trashed");
+        trashed.deleted_at = Some(chrono::Utc::now());
+        storage.save_transcription(&trashed).await.unwrap();
+        std::fs::write(dir.path().join(&corrupt_path), "{ not json").unwrap();
+
+        // An app state from before transcriptions had files of their own
+        let state: AppStateType = Arc::new(Mutex::new(AppState::default()));
+        {
+            let mut app_state = state.lock().unwrap();
+            for t in [
+                transcription(STORED, "Stale version"),
+                transcription(MISSING, "Only in app state"),
+                transcription(CORRUPT, "Would overwrite"),
+            ] {
+                app_state.transcriptions.insert(t.id.clone(), t);
+            }
+        }
+
+        reconcile_transcriptions(&state, &storage).await.unwrap();
+
+        let app_state = state.lock().unwrap().clone();
+        let mut ids: Vec<&String> = app_state.transcriptions.keys().collect();
+        ids.sort();
+        assert_eq!(ids, vec![STORED, MISSING]);
+        assert_eq!(app_state.transcriptions[STORED].title, "Stored version");
+        assert!(app_state.trash.contains_key(TRASHED));
+
+        let report = &app_state.storage_report;
+        assert_eq!((report.loaded, report.recovered, report.corrupt.len()), (2, 1, 1));
+
+        assert_eq!(storage.load_transcription(MISSING).await.unwrap().title, "Only in app state");
+        assert_eq!(std::fs::read_to_string(dir.path().join(&corrupt_path)).unwrap(), "{ not json");
+        assert_eq!(storage.search("app state", 10).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_failed_merge_puts_the_parts_back() {
+        const FIRST: &str = "00000000-0000-4000-8000-000000000011";
+        const SECOND: &str = "00000000-0000-4000-8000-000000000012";
+        let dir = tempfile::tempdir().unwrap();
+        let storage = StorageService::with_data_dir(dir.path().to_path_buf()).unwrap();
+        let state: AppStateType = Arc::new(Mutex::new(AppState::default()));
+
+        let parts = [transcription(FIRST, "First"), transcription(SECOND, "Second")];
+        for part in &parts {
+            storage.save_transcription(part).await.unwrap();
+        }
+        let merged = editing::merge(&parts).unwrap();
+        storage.save_revision(&merged, RevisionSource::ManualEdit).await.unwrap();
+        move_to_trash(&state, &storage, parts[1].clone()).await.unwrap();
+
+        undo_merge(&state, &storage, &parts).await;
+
+        for part in &parts {
+            let stored = storage.load_transcription(&part.id).await.unwrap();
+            assert_eq!(serde_json::to_value(&stored).unwrap(), serde_json::to_value(part).unwrap());
+        }
+        let app_state = state.lock().unwrap();
+        assert!(app_state.trash.is_empty());
+        assert_eq!(app_state.transcriptions[FIRST].raw_text, "First text");
+        assert_eq!(app_state.transcriptions[SECOND].raw_text, "Second text");
+    }
+
+    #[tokio::test]
+    async fn trashed_transcriptions_are_restored_intact() {
+        const ID: &str = "00000000-0000-4000-8000-000000000021";
+        let dir = tempfile::tempdir().unwrap();
+        let storage = StorageService::with_data_dir(dir.path().to_path_buf()).unwrap();
+        let state: AppStateType = Arc::new(Mutex::new(AppState::default()));
+
+        let mut original = transcription(ID, "Budget review");
+        original.tags = vec!["Finance".to_string()];
+        original.metadata.insert("room".to_string(), "B2".to_string());
+        storage.save_revision(&original, RevisionSource::Live).await.unwrap();
+        state.lock().unwrap().transcriptions.insert(ID.to_string(), original.clone());
+
+        move_to_trash(&state, &storage, original.clone()).await.unwrap();
+        assert!(storage.search("budget", 10).is_empty());
+        assert!(load_active(&storage, ID).await.is_err());
+        assert!(state.lock().unwrap().trash.contains_key(ID));
+
+        let restored = restore_from_trash(&state, &storage, ID).await.unwrap();
+
+        let stored = storage.load_transcription(ID).await.unwrap();
+        for t in [&restored, &stored] {
+            assert_eq!(serde_json::to_value(t).unwrap(), serde_json::to_value(&original).unwrap());
+        }
+        {
+            let app_state = state.lock().unwrap();
+            assert!(app_state.trash.is_empty());
+            assert!(app_state.transcriptions.contains_key(ID));
+        }
+        assert_eq!(storage.search("budget", 10).len(), 1);
+        let sources: Vec<RevisionSource> = storage.load_revisions(ID).await.unwrap().iter().map(|r| r.source).collect();
+        assert_eq!(sources, vec![RevisionSource::Live, RevisionSource::Trashed, RevisionSource::RestoredFromTrash]);
+
+        assert!(restore_from_trash(&state, &storage, ID).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn only_expired_trash_is_purged() {
+        const EXPIRED: &str = "00000000-0000-4000-8000-000000000031";
+        const RECENT: &str = "00000000-0000-4000-8000-000000000032";
+        let dir = tempfile::tempdir().unwrap();
+        let storage = StorageService::with_data_dir(dir.path().to_path_buf()).unwrap();
+        let state: AppStateType = Arc::new(Mutex::new(AppState::default()));
+        state.lock().unwrap().trash_retention_days = 30;
+
+        for (id, days) in [(EXPIRED, 31), (RECENT, 29)] {
+            let mut trashed = transcription(id, "Trashed");
+            trashed.deleted_at = Some(chrono::Utc::now() - chrono::Duration::days(days));
+            storage.save_transcription(&trashed).await.unwrap();
+            state.lock().unwrap().trash.insert(id.to_string(), trashed);
+        }
+
+        // A retention of zero keeps the trash until it is emptied
+        state.lock().unwrap().trash_retention_days = 0;
+        assert_eq!(purge_expired_trash(&state, &storage).await.unwrap(), 0);
+        state.lock().unwrap().trash_retention_days = 30;
+        assert_eq!(purge_expired_trash(&state, &storage).await.unwrap(), 1);
+
+        assert!(storage.load_transcription(EXPIRED).await.is_err());
+        assert!(storage.load_revisions(EXPIRED).await.unwrap().is_empty());
+        let recent = storage.load_transcription(RECENT).await.unwrap();
+        assert!(recent.deleted_at.is_some());
+        let trash: Vec<String> = state.lock().unwrap().trash.keys().cloned().collect();
+        assert_eq!(trash, vec![RECENT]);
+    }
+}
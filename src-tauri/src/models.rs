@@ -1,8 +1,24 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
+/// Version written with every `Transcription`. Bump it together with a new
+/// step in `storage::migrations` whenever a change is not purely additive.
+pub const TRANSCRIPTION_SCHEMA_VERSION: u32 = 2;
+/// Version written with `AppState`; see `TRANSCRIPTION_SCHEMA_VERSION`.
+pub const APP_STATE_SCHEMA_VERSION: u32 = 1;
+
+/// Version of documents written before versioning was introduced.
+fn unversioned_schema() -> u32 {
+    1
+}
+
+/// Recorded audio is not part of a transcription: it is only held, in
+/// memory or in a spill file, until it has been transcribed. Splitting,
+/// merging, trimming and the trash therefore only deal with the transcript.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transcription {
+    #[serde(default = "unversioned_schema")]
+    pub schema_version: u32,
     pub id: String,
     pub title: String,
     pub created_at: chrono::DateTime<chrono::Utc>,
@@ -10,6 +26,96 @@ pub struct Transcription {
     pub chapters: Vec<Chapter>,
     pub raw_text: String,
     pub status: TranscriptionStatus,
+    #[serde(default)]
+    pub segments: Vec<TranscriptionSegment>,
+    #[serde(default)]
+    pub usage: TokenUsage,
+    #[serde(default)]
+    pub notes: MeetingNotes,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Folder or project the transcription is filed under.
+    #[serde(default)]
+    pub folder: Option<String>,
+    #[serde(default)]
+    pub participants: Vec<String>,
+    #[serde(default)]
+    pub location: Option<String>,
+    /// Free-form key/value pairs set by the user.
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    /// When the transcription was moved to the trash; `None` while it is
+    /// not in the trash.
+    #[serde(default)]
+    pub deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl Transcription {
+    /// Recomputes chapter and subsection confidence as the mean confidence
+    /// of their segments. Chapters analysed from plain text use the segments
+    /// that start inside them. Chapters without segments keep the confidence
+    /// reported by the analysis call.
+    pub fn refresh_chapter_confidence(&mut self) {
+        let by_id: HashMap<&str, f32> = self.segments.iter().map(|s| (s.id.as_str(), s.confidence)).collect();
+        let starts: Vec<f64> = self.chapters.iter().map(|c| c.start_time).collect();
+
+        for (i, chapter) in self.chapters.iter_mut().enumerate() {
+            let confidences: Vec<f32> = if chapter.segment_ids.is_empty() {
+                let end = starts.get(i + 1).copied().unwrap_or(f64::INFINITY);
+                self.segments
+                    .iter()
+                    .filter(|s| s.start_time >= chapter.start_time && s.start_time < end)
+                    .map(|s| s.confidence)
+                    .collect()
+            } else {
+                chapter.segment_ids.iter().filter_map(|id| by_id.get(id.as_str()).copied()).collect()
+            };
+
+            if let Some(confidence) = mean(&confidences) {
+                chapter.confidence = confidence;
+            }
+
+            for subsection in chapter.subsections.iter_mut() {
+                let confidences: Vec<f32> = subsection
+                    .segment_ids
+                    .iter()
+                    .filter_map(|id| by_id.get(id.as_str()).copied())
+                    .collect();
+                if let Some(confidence) = mean(&confidences) {
+                    subsection.confidence = confidence;
+                }
+            }
+        }
+    }
+}
+
+fn mean(values: &[f32]) -> Option<f32> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.iter().sum::<f32>() / values.len() as f32)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscriptionSegment {
+    pub id: String,
+    pub text: String,
+    pub start_time: f64,
+    pub end_time: f64,
+    pub confidence: f32,
+    pub words: Vec<WordConfidence>,
+    /// Credential profile whose request produced this segment.
+    #[serde(default)]
+    pub profile_id: Option<String>,
+    #[serde(default)]
+    pub speaker: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WordConfidence {
+    pub text: String,
+    pub confidence: f32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,18 +123,148 @@ pub struct Chapter {
     pub id: String,
     pub title: String,
     pub start_time: f64,
+    #[serde(default)]
+    pub end_time: f64,
     pub content: String,
     pub confidence: f32,
     pub subsections: Vec<Subsection>,
+    /// Segments covered by the chapter, in order. Empty for chapters
+    /// analysed from plain text.
+    #[serde(default)]
+    pub segment_ids: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Subsection {
     pub id: String,
+    #[serde(default)]
+    pub title: String,
     pub content: String,
     pub start_time: f64,
     pub end_time: f64,
     pub confidence: f32,
+    #[serde(default)]
+    pub segment_ids: Vec<String>,
+}
+
+/// Analyses `analyze_transcription` can run besides chapters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnalysisKind {
+    ExecutiveSummary,
+    BulletSummary,
+    ActionItems,
+    Decisions,
+    OpenQuestions,
+}
+
+impl AnalysisKind {
+    pub const ALL: [AnalysisKind; 5] = [
+        AnalysisKind::ExecutiveSummary,
+        AnalysisKind::BulletSummary,
+        AnalysisKind::ActionItems,
+        AnalysisKind::Decisions,
+        AnalysisKind::OpenQuestions,
+    ];
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActionItem {
+    pub description: String,
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// Due date as stated in the recording, e.g. "next Friday".
+    #[serde(default)]
+    pub due_date: Option<String>,
+}
+
+/// Meeting notes extracted from a transcription. A `None` field has not
+/// been analysed yet; an empty list means nothing was found.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MeetingNotes {
+    pub executive_summary: Option<String>,
+    pub bullet_summary: Option<Vec<String>>,
+    pub action_items: Option<Vec<ActionItem>>,
+    pub decisions: Option<Vec<String>>,
+    pub open_questions: Option<Vec<String>>,
+    pub generated_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl MeetingNotes {
+    pub fn has(&self, kind: AnalysisKind) -> bool {
+        match kind {
+            AnalysisKind::ExecutiveSummary => self.executive_summary.is_some(),
+            AnalysisKind::BulletSummary => self.bullet_summary.is_some(),
+            AnalysisKind::ActionItems => self.action_items.is_some(),
+            AnalysisKind::Decisions => self.decisions.is_some(),
+            AnalysisKind::OpenQuestions => self.open_questions.is_some(),
+        }
+    }
+
+    /// Replaces the fields for `kinds` with those from `other`, keeping the rest.
+    pub fn merge(&mut self, other: MeetingNotes, kinds: &[AnalysisKind]) {
+        for kind in kinds {
+            match kind {
+                AnalysisKind::ExecutiveSummary => self.executive_summary = other.executive_summary.clone(),
+                AnalysisKind::BulletSummary => self.bullet_summary = other.bullet_summary.clone(),
+                AnalysisKind::ActionItems => self.action_items = other.action_items.clone(),
+                AnalysisKind::Decisions => self.decisions = other.decisions.clone(),
+                AnalysisKind::OpenQuestions => self.open_questions = other.open_questions.clone(),
+            }
+        }
+        self.generated_at = other.generated_at.or(self.generated_at);
+    }
+}
+
+/// A passage of the transcription an answer is based on. `segment_id` is
+/// `None` for transcriptions without stored segments.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Citation {
+    pub segment_id: Option<String>,
+    pub start_time: f64,
+    pub end_time: f64,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryAnswer {
+    pub question: String,
+    pub answer: String,
+    /// Whether the transcription contained an answer at all.
+    pub found: bool,
+    pub citations: Vec<Citation>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnalysisStage {
+    /// Finding sections in each window of a long transcript.
+    Sections,
+    /// Grouping the transcript into chapters.
+    Chapters,
+    Done,
+}
+
+/// Emitted as `analysis-progress` while chapters are being analysed.
+/// `completed` and `total` count provider requests, not counting repairs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnalysisProgress {
+    pub transcription_id: String,
+    pub stage: AnalysisStage,
+    pub completed: usize,
+    pub total: usize,
+}
+
+impl AnalysisProgress {
+    pub fn new(transcription_id: &str, stage: AnalysisStage, completed: usize, total: usize) -> Self {
+        Self {
+            transcription_id: transcription_id.to_string(),
+            stage,
+            completed,
+            total,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -46,6 +282,15 @@ pub struct RecordingState {
     pub duration: f64,
     pub audio_level: f32,
     pub transcription_id: Option<String>,
+    #[serde(default)]
+    pub segments: Vec<TranscriptionSegment>,
+    #[serde(default)]
+    pub usage: TokenUsage,
+    /// `usage` split by the model that was billed for it.
+    #[serde(default)]
+    pub usage_by_model: BTreeMap<String, TokenUsage>,
+    #[serde(default)]
+    pub profile_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -54,6 +299,14 @@ pub struct ExportFormat {
     pub include_timestamps: bool,
     pub include_chapters: bool,
     pub custom_template: Option<String>,
+    #[serde(default)]
+    pub include_summary: bool,
+    #[serde(default)]
+    pub include_action_items: bool,
+    #[serde(default)]
+    pub include_decisions: bool,
+    #[serde(default)]
+    pub include_open_questions: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -66,23 +319,606 @@ pub enum ExportType {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppState {
+    #[serde(default = "unversioned_schema")]
+    pub schema_version: u32,
+    /// Loaded from the per-transcription files at startup. Older versions
+    /// kept the whole map here, so it is still read once for migration.
+    #[serde(default, skip_serializing)]
     pub transcriptions: HashMap<String, Transcription>,
     pub current_recording: Option<RecordingState>,
+    /// Held in memory only; persisted through the secret store.
+    #[serde(skip)]
     pub gemini_api_key: Option<String>,
+    /// Plaintext key from older files. Written back unchanged until it has
+    /// been moved into the secret store, which may stay locked for a while.
+    #[serde(default, rename = "gemini_api_key", skip_serializing_if = "Option::is_none")]
+    pub plaintext_api_key: Option<String>,
     pub selected_model: String,
+    #[serde(default = "default_model_prices")]
+    pub model_prices: HashMap<String, ModelPrice>,
+    #[serde(default)]
+    pub monthly_budget: Option<f64>,
+    #[serde(default)]
+    pub provider_settings: ProviderSettings,
+    #[serde(default)]
+    pub profiles: Vec<CredentialProfile>,
+    #[serde(default)]
+    pub default_profile_id: Option<String>,
+    #[serde(default = "default_rate_limits")]
+    pub rate_limits: HashMap<String, RateLimitSettings>,
+    #[serde(default)]
+    pub pipeline_settings: PipelineSettings,
+    #[serde(default)]
+    pub storage_backend: StorageBackend,
+    /// API keys of `profiles`, loaded from the secret store.
+    #[serde(skip)]
+    pub profile_keys: HashMap<String, String>,
+    /// Outcome of loading transcriptions at startup.
+    #[serde(skip)]
+    pub storage_report: StorageReport,
+    /// Transcriptions in the trash, kept apart from `transcriptions`.
+    #[serde(skip)]
+    pub trash: HashMap<String, Transcription>,
+    /// Days after which trashed transcriptions are deleted for good; 0
+    /// keeps them until the trash is emptied.
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u32,
 }
 
 impl Default for AppState {
     fn default() -> Self {
         Self {
+            schema_version: APP_STATE_SCHEMA_VERSION,
             transcriptions: HashMap::new(),
             current_recording: None,
             gemini_api_key: None,
+            plaintext_api_key: None,
             selected_model: "gemini-2.5-flash".to_string(),
+            model_prices: default_model_prices(),
+            monthly_budget: None,
+            provider_settings: ProviderSettings::default(),
+            profiles: Vec::new(),
+            default_profile_id: None,
+            rate_limits: default_rate_limits(),
+            pipeline_settings: PipelineSettings::default(),
+            storage_backend: StorageBackend::default(),
+            storage_report: StorageReport::default(),
+            profile_keys: HashMap::new(),
+            trash: HashMap::new(),
+            trash_retention_days: default_trash_retention_days(),
+        }
+    }
+}
+
+impl AppState {
+    pub fn rate_limit(&self, provider: &str) -> RateLimitSettings {
+        self.rate_limits.get(provider).cloned().unwrap_or_default()
+    }
+}
+
+/// Client-side limits applied per set of credentials.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitSettings {
+    pub requests_per_minute: u32,
+    pub tokens_per_minute: u64,
+    pub max_concurrent_requests: usize,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            requests_per_minute: 60,
+            tokens_per_minute: 1_000_000,
+            max_concurrent_requests: 4,
+        }
+    }
+}
+
+impl RateLimitSettings {
+    /// These settings with every limit raised to at least one. Settings read
+    /// from disk have not been through `set_rate_limit`, and a zero limit
+    /// would never let a request through.
+    pub fn clamped(&self) -> Self {
+        Self {
+            requests_per_minute: self.requests_per_minute.max(1),
+            tokens_per_minute: self.tokens_per_minute.max(1),
+            max_concurrent_requests: self.max_concurrent_requests.max(1),
+        }
+    }
+}
+
+/// What a full pipeline queue does with new items.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OverflowPolicy {
+    /// Wait for room. Blocks the audio callback thread for the audio queue.
+    Block,
+    /// Discard the oldest queued item and leave a gap marker in its place.
+    DropOldest,
+    /// Write overflow to a temporary file and read it back in order. The
+    /// file is written on the sending thread, which for the audio queue is
+    /// the audio callback.
+    SpillToDisk,
+}
+
+/// Capacities are in items: 100 ms audio chunks and transcribed segments.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PipelineSettings {
+    pub audio_queue_capacity: usize,
+    pub transcription_queue_capacity: usize,
+    pub overflow_policy: OverflowPolicy,
+}
+
+impl Default for PipelineSettings {
+    fn default() -> Self {
+        Self {
+            audio_queue_capacity: 600,
+            transcription_queue_capacity: 100,
+            overflow_policy: OverflowPolicy::DropOldest,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueStats {
+    pub name: String,
+    pub capacity: usize,
+    /// Items waiting, including those spilled to disk.
+    pub depth: usize,
+    pub spilled: usize,
+    pub spilled_total: u64,
+    pub dropped: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PipelineDiagnostics {
+    pub transcription_id: String,
+    pub queues: Vec<QueueStats>,
+}
+
+/// Where transcriptions are stored. Takes effect on the next start, when
+/// existing transcriptions are migrated to the selected backend.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    /// One JSON file per transcription.
+    #[default]
+    Json,
+    /// Embedded SQLite database.
+    Sqlite,
+}
+
+/// Part of a transcription a search match was found in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchField {
+    Title,
+    Summary,
+    Chapter,
+    Segment,
+    /// Plain text of a transcription without segments.
+    Text,
+}
+
+impl SearchField {
+    /// Relative weight of a match in this field.
+    pub fn weight(&self) -> f64 {
+        match self {
+            SearchField::Title => 3.0,
+            SearchField::Summary => 1.5,
+            SearchField::Chapter => 1.2,
+            SearchField::Segment | SearchField::Text => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchMatch {
+    pub field: SearchField,
+    pub snippet: String,
+    /// Id of the matching chapter or segment.
+    pub reference_id: Option<String>,
+    pub start_time: Option<f64>,
+    pub end_time: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub transcription_id: String,
+    pub title: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub score: f64,
+    /// Best matching passages, best first.
+    pub matches: Vec<SearchMatch>,
+}
+
+/// A transcription file that could not be read or parsed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorruptFile {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StorageReport {
+    /// Transcriptions loaded from their files.
+    pub loaded: usize,
+    /// Transcriptions only found in an old `app_state.json` and written
+    /// back to their own files.
+    pub recovered: usize,
+    pub corrupt: Vec<CorruptFile>,
+}
+
+/// What produced a revision of a transcription.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RevisionSource {
+    /// Text transcribed while recording.
+    Live,
+    /// Chapters, notes or a title from an analysis request.
+    Analysis,
+    ManualEdit,
+    /// An earlier revision made current again.
+    Restore,
+    /// Moved to the trash.
+    Trashed,
+    /// Taken back out of the trash.
+    RestoredFromTrash,
+    /// The version stored before revision history was kept.
+    Unknown,
+}
+
+/// A change made by hand with `update_transcription`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "field", rename_all = "snake_case")]
+pub enum TranscriptionEdit {
+    Title { title: String },
+    /// Corrected text is taken as certain: the segment's confidence and
+    /// the confidence of its words become 1.
+    SegmentText { segment_id: String, text: String },
+    /// An empty or missing speaker clears it.
+    Speaker { segment_id: String, speaker: Option<String> },
+    ChapterTitle { chapter_id: String, title: String },
+    /// Neighbouring chapters grow or shrink to meet the new range, so no
+    /// gap is left, and segments are reassigned to the chapters they now
+    /// start in.
+    ChapterBounds { chapter_id: String, start_time: f64, end_time: f64 },
+    /// Replaces the tags. Duplicates differing only in case are dropped.
+    Tags { tags: Vec<String> },
+    /// An empty or missing folder files the transcription nowhere.
+    Folder { folder: Option<String> },
+    Participants { participants: Vec<String> },
+    Location { location: Option<String> },
+    /// Sets one metadata entry; a missing value removes it.
+    Metadata { key: String, value: Option<String> },
+}
+
+/// Criteria for `get_transcriptions`. Every field that is set must match.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TranscriptionFilter {
+    /// Transcriptions having all of these tags, ignoring case and spacing.
+    pub tags: Vec<String>,
+    /// Matched like `tags`.
+    pub folder: Option<String>,
+    pub created_after: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_before: Option<chrono::DateTime<chrono::Utc>>,
+    /// Duration bounds in seconds, inclusive.
+    pub min_duration: Option<u64>,
+    pub max_duration: Option<u64>,
+    /// Compared by variant only, so any `Error` matches `Error`.
+    pub status: Option<TranscriptionStatus>,
+    pub sort: TranscriptionSort,
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+impl TranscriptionFilter {
+    /// The page of matching transcriptions in the requested order.
+    pub fn select<'a>(&self, transcriptions: impl Iterator<Item = &'a Transcription>) -> Vec<&'a Transcription> {
+        let mut selected: Vec<&Transcription> = transcriptions.filter(|t| self.matches(t)).collect();
+        selected.sort_by(|a, b| self.sort.compare(a, b));
+        selected
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+
+    pub fn matches(&self, transcription: &Transcription) -> bool {
+        let has_tag = |tag: &String| {
+            let key = label_key(tag);
+            transcription.tags.iter().any(|t| label_key(t) == key)
+        };
+
+        self.tags.iter().all(has_tag)
+            && self.folder.as_ref().is_none_or(|folder| {
+                transcription.folder.as_deref().map(label_key) == Some(label_key(folder))
+            })
+            && self.created_after.is_none_or(|after| transcription.created_at >= after)
+            && self.created_before.is_none_or(|before| transcription.created_at < before)
+            && self.min_duration.is_none_or(|min| transcription.duration >= min)
+            && self.max_duration.is_none_or(|max| transcription.duration <= max)
+            && self.status.as_ref().is_none_or(|status| {
+                std::mem::discriminant(status) == std::mem::discriminant(&transcription.status)
+            })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscriptionSort {
+    #[default]
+    NewestFirst,
+    OldestFirst,
+    Title,
+    LongestFirst,
+    ShortestFirst,
+}
+
+impl TranscriptionSort {
+    /// Ties are broken by id so pages do not overlap.
+    pub fn compare(&self, a: &Transcription, b: &Transcription) -> std::cmp::Ordering {
+        let order = match self {
+            TranscriptionSort::NewestFirst => b.created_at.cmp(&a.created_at),
+            TranscriptionSort::OldestFirst => a.created_at.cmp(&b.created_at),
+            TranscriptionSort::Title => a.title.to_lowercase().cmp(&b.title.to_lowercase()),
+            TranscriptionSort::LongestFirst => b.duration.cmp(&a.duration),
+            TranscriptionSort::ShortestFirst => a.duration.cmp(&b.duration),
+        };
+        order.then_with(|| a.id.cmp(&b.id))
+    }
+}
+
+/// Form of a tag or folder that labels differing only in case or spacing
+/// share, for grouping and matching them.
+pub fn label_key(label: &str) -> String {
+    label.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// A tag or folder and the number of transcriptions using it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LabelCount {
+    pub name: String,
+    pub count: usize,
+}
+
+/// A saved version of a transcription. Revisions are only appended, and
+/// the oldest are dropped once a transcription has more than
+/// `storage::MAX_REVISIONS`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Revision {
+    pub id: String,
+    pub transcription_id: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub source: RevisionSource,
+    pub transcription: Transcription,
+}
+
+impl Revision {
+    pub fn new(transcription: &Transcription, source: RevisionSource) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            transcription_id: transcription.id.clone(),
+            created_at: chrono::Utc::now(),
+            source,
+            transcription: transcription.clone(),
+        }
+    }
+
+    pub fn summary(&self) -> RevisionSummary {
+        RevisionSummary {
+            id: self.id.clone(),
+            created_at: self.created_at,
+            source: self.source,
+            title: self.transcription.title.clone(),
+            chapter_count: self.transcription.chapters.len(),
+            character_count: self.transcription.raw_text.chars().count(),
+        }
+    }
+}
+
+/// A revision without its snapshot, for listing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevisionSummary {
+    pub id: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub source: RevisionSource,
+    pub title: String,
+    pub chapter_count: usize,
+    pub character_count: usize,
+}
+
+/// Longest accepted `trash_retention_days`, about a century.
+pub const MAX_TRASH_RETENTION_DAYS: u32 = 36_500;
+
+pub fn default_trash_retention_days() -> u32 {
+    30
+}
+
+pub fn default_rate_limits() -> HashMap<String, RateLimitSettings> {
+    HashMap::from([("gemini".to_string(), RateLimitSettings::default())])
+}
+
+/// A named set of credentials. The API key itself lives in the secret store.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CredentialProfile {
+    pub id: String,
+    pub name: String,
+    pub provider: String,
+    /// Overrides `ProviderSettings::base_url` for this profile.
+    pub base_url: Option<String>,
+    pub model: String,
+}
+
+/// Connection settings for the generative language API, so traffic can go
+/// through a corporate proxy or a compatible gateway.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderSettings {
+    pub base_url: String,
+    pub api_version: String,
+    pub proxy_url: Option<String>,
+    pub ca_bundle_path: Option<String>,
+    pub timeout_secs: u64,
+}
+
+impl Default for ProviderSettings {
+    fn default() -> Self {
+        Self {
+            base_url: "https://generativelanguage.googleapis.com".to_string(),
+            api_version: "v1beta".to_string(),
+            proxy_url: None,
+            ca_bundle_path: None,
+            timeout_secs: 120,
+        }
+    }
+}
+
+impl ProviderSettings {
+    /// Base URL joined with the API version, without a trailing slash.
+    pub fn api_root(&self) -> String {
+        let base_url = self.base_url.trim_end_matches('/');
+        let api_version = self.api_version.trim_matches('/');
+
+        if api_version.is_empty() {
+            base_url.to_string()
+        } else {
+            format!("{}/{}", base_url, api_version)
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SecretBackend {
+    Keyring,
+    EncryptedFile,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecretStoreStatus {
+    pub backend: SecretBackend,
+    pub unlocked: bool,
+    pub encrypted_file_exists: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ApiKeyStatus {
+    Valid,
+    Invalid,
+    PermissionDenied,
+    /// The key authenticated but its quota is exhausted.
+    QuotaExceeded,
+    NetworkError,
+    UnexpectedResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyValidation {
+    pub status: ApiKeyStatus,
+    pub message: Option<String>,
+}
+
+impl ApiKeyValidation {
+    pub fn new(status: ApiKeyStatus, message: Option<String>) -> Self {
+        Self { status, message }
+    }
+
+    /// Whether the provider accepted the key, even if it is out of quota.
+    pub fn is_accepted(&self) -> bool {
+        matches!(self.status, ApiKeyStatus::Valid | ApiKeyStatus::QuotaExceeded)
+    }
+}
+
+/// Tokens billed by the provider. `audio_tokens` is the audio share of
+/// `input_tokens`; `output_tokens` includes thinking tokens.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub audio_tokens: u64,
+    pub output_tokens: u64,
+    pub requests: u64,
+}
+
+impl TokenUsage {
+    pub fn add(&mut self, other: &TokenUsage) {
+        self.input_tokens += other.input_tokens;
+        self.audio_tokens += other.audio_tokens;
+        self.output_tokens += other.output_tokens;
+        self.requests += other.requests;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests == 0
+    }
+
+    pub fn cost(&self, price: &ModelPrice) -> f64 {
+        let text_input = self.input_tokens.saturating_sub(self.audio_tokens) as f64;
+        (text_input * price.input_per_million
+            + self.audio_tokens as f64 * price.audio_input_per_million
+            + self.output_tokens as f64 * price.output_per_million)
+            / 1_000_000.0
+    }
+}
+
+/// Price in USD per million tokens.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub input_per_million: f64,
+    pub audio_input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+pub fn default_model_prices() -> HashMap<String, ModelPrice> {
+    let price = |input: f64, audio: f64, output: f64| ModelPrice {
+        input_per_million: input,
+        audio_input_per_million: audio,
+        output_per_million: output,
+    };
+
+    HashMap::from([
+        ("gemini-2.5-flash".to_string(), price(0.30, 1.00, 2.50)),
+        ("gemini-2.5-pro".to_string(), price(1.25, 1.25, 10.00)),
+        ("gemini-2.0-flash".to_string(), price(0.10, 0.70, 0.40)),
+        ("gemini-1.5-pro".to_string(), price(1.25, 1.25, 5.00)),
+    ])
+}
+
+/// Token usage per day (`YYYY-MM-DD`) and model.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageLedger {
+    pub days: BTreeMap<String, BTreeMap<String, TokenUsage>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageReport {
+    pub month: String,
+    pub usage: TokenUsage,
+    pub cost: f64,
+    pub models: Vec<ModelUsage>,
+    pub days: Vec<DailyUsage>,
+    pub monthly_budget: Option<f64>,
+    pub budget_alert: Option<BudgetAlert>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelUsage {
+    pub model: String,
+    pub usage: TokenUsage,
+    pub cost: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DailyUsage {
+    pub date: String,
+    pub usage: TokenUsage,
+    pub cost: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum BudgetAlert {
+    Warning,
+    Exceeded,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AudioChunk {
     pub data: Vec<f32>,
@@ -92,11 +928,32 @@ pub struct AudioChunk {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TranscriptionChunk {
+    pub id: String,
     pub text: String,
     pub confidence: f32,
+    pub words: Vec<WordConfidence>,
     pub start_time: f64,
     pub end_time: f64,
     pub is_final: bool,
+    pub usage: TokenUsage,
+    pub profile_id: String,
+    /// Model that transcribed the chunk, which differs between profiles.
+    pub model: String,
+}
+
+impl TranscriptionChunk {
+    pub fn to_segment(&self) -> TranscriptionSegment {
+        TranscriptionSegment {
+            id: self.id.clone(),
+            text: self.text.trim().to_string(),
+            start_time: self.start_time,
+            end_time: self.end_time,
+            confidence: self.confidence,
+            words: self.words.clone(),
+            profile_id: Some(self.profile_id.clone()),
+            speaker: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -108,6 +965,47 @@ pub struct GeminiModel {
     pub context_window: String,
 }
 
+/// How long a discovered model list is reused before querying the provider again.
+pub const MODELS_CACHE_TTL_HOURS: i64 = 24;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelsCache {
+    pub fetched_at: chrono::DateTime<chrono::Utc>,
+    pub models: Vec<GeminiModel>,
+}
+
+impl ModelsCache {
+    pub fn is_fresh(&self) -> bool {
+        chrono::Utc::now() - self.fetched_at < chrono::Duration::hours(MODELS_CACHE_TTL_HOURS)
+    }
+}
+
+/// Whether a model id from the provider's catalogue accepts audio input.
+/// The models endpoint does not report modalities, so this goes by family.
+pub fn model_supports_audio(id: &str) -> bool {
+    const EXCLUDED: [&str; 6] = ["embedding", "image", "tts", "aqa", "gemini-1.0", "gemini-pro"];
+
+    id.starts_with("gemini-") && !EXCLUDED.iter().any(|excluded| id.contains(excluded))
+}
+
+/// The provider's catalogue, with curated names and descriptions from the
+/// static list where it has them. The static list is only returned as a
+/// whole when nothing was discovered, so retired models drop out.
+pub fn merge_models(discovered: Vec<GeminiModel>) -> Vec<GeminiModel> {
+    if discovered.is_empty() {
+        return get_available_models();
+    }
+
+    let curated = get_available_models();
+    discovered
+        .into_iter()
+        .map(|model| match curated.iter().find(|c| c.id == model.id) {
+            Some(curated) => curated.clone(),
+            None => model,
+        })
+        .collect()
+}
+
 pub fn get_available_models() -> Vec<GeminiModel> {
     vec![
         GeminiModel {
@@ -139,4 +1037,145 @@ pub fn get_available_models() -> Vec<GeminiModel> {
             context_window: "2M tokens".to_string(),
         },
     ]
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn transcription(id: &str, title: &str, day: u32, duration: u64, tags: &[&str], folder: Option<&str>) -> Transcription {
+        serde_json::from_value(json!({
+            "id": id,
+            "title": title,
+            "created_at": format!("2024-05-{:02}T10:00:00Z", day),
+            "duration": duration,
+            "chapters": [],
+            "raw_text": "",
+            "status": "Completed",
+            "tags": tags,
+            "folder": folder
+        }))
+        .unwrap()
+    }
+
+    fn library() -> Vec<Transcription> {
+        vec![
+            transcription("a", "beta review", 1, 600, &["Città", "team"], Some("Projects")),
+            transcription("b", "Alpha kickoff", 3, 1200, &["TEAM"], Some("projects")),
+            transcription("c", "Gamma sync", 2, 300, &["città"], None),
+            transcription("d", "Delta notes", 2, 1200, &[], Some("Personal")),
+        ]
+    }
+
+    fn select(filter: &TranscriptionFilter, library: &[Transcription]) -> Vec<String> {
+        filter.select(library.iter()).into_iter().map(|t| t.id.clone()).collect()
+    }
+
+    #[test]
+    fn tags_and_folders_match_regardless_of_case() {
+        let library = library();
+        let filter = |tags: &[&str], folder: Option<&str>| TranscriptionFilter {
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            folder: folder.map(|f| f.to_string()),
+            sort: TranscriptionSort::Title,
+            ..Default::default()
+        };
+
+        assert_eq!(select(&filter(&["CITTÀ"], None), &library), vec!["a", "c"]);
+        assert_eq!(select(&filter(&["team", " città "], None), &library), vec!["a"]);
+        assert_eq!(select(&filter(&[], Some("PROJECTS")), &library), vec!["b", "a"]);
+        assert!(select(&filter(&["unknown"], None), &library).is_empty());
+    }
+
+    #[test]
+    fn dates_durations_and_status_narrow_the_selection() {
+        let library = library();
+        let filter = TranscriptionFilter {
+            created_after: Some("2024-05-02T00:00:00Z".parse().unwrap()),
+            created_before: Some("2024-05-03T00:00:00Z".parse().unwrap()),
+            min_duration: Some(300),
+            max_duration: Some(600),
+            ..Default::default()
+        };
+        assert_eq!(select(&filter, &library), vec!["c"]);
+
+        let failed = TranscriptionFilter {
+            status: Some(TranscriptionStatus::Error(String::new())),
+            ..Default::default()
+        };
+        assert!(select(&failed, &library).is_empty());
+    }
+
+    #[test]
+    fn sorting_breaks_ties_by_id_and_pages_do_not_overlap() {
+        let library = library();
+        let sorted = |sort| select(&TranscriptionFilter { sort, ..Default::default() }, &library);
+
+        assert_eq!(sorted(TranscriptionSort::NewestFirst), vec!["b", "c", "d", "a"]);
+        assert_eq!(sorted(TranscriptionSort::OldestFirst), vec!["a", "c", "d", "b"]);
+        assert_eq!(sorted(TranscriptionSort::Title), vec!["b", "a", "d", "c"]);
+        assert_eq!(sorted(TranscriptionSort::LongestFirst), vec!["b", "d", "a", "c"]);
+        assert_eq!(sorted(TranscriptionSort::ShortestFirst), vec!["c", "a", "b", "d"]);
+
+        let page = |offset, limit| {
+            select(&TranscriptionFilter { offset, limit: Some(limit), ..Default::default() }, &library)
+        };
+        assert_eq!(page(0, 3), vec!["b", "c", "d"]);
+        assert_eq!(page(3, 3), vec!["a"]);
+        assert!(page(4, 3).is_empty());
+    }
+
+    #[test]
+    fn api_root_joins_base_url_and_version() {
+        let settings = |base_url: &str, api_version: &str| ProviderSettings {
+            base_url: base_url.to_string(),
+            api_version: api_version.to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(ProviderSettings::default().api_root(), "https://generativelanguage.googleapis.com/v1beta");
+        assert_eq!(settings("http://localhost:8080/", "/v1/").api_root(), "http://localhost:8080/v1");
+        assert_eq!(settings("https://proxy.example/gemini/v1beta", "").api_root(), "https://proxy.example/gemini/v1beta");
+    }
+
+    #[test]
+    fn models_cache_expires_after_the_ttl() {
+        let cache = |age: chrono::Duration| ModelsCache {
+            fetched_at: chrono::Utc::now() - age,
+            models: Vec::new(),
+        };
+
+        assert!(cache(chrono::Duration::zero()).is_fresh());
+        assert!(cache(chrono::Duration::hours(MODELS_CACHE_TTL_HOURS) - chrono::Duration::minutes(1)).is_fresh());
+        assert!(!cache(chrono::Duration::hours(MODELS_CACHE_TTL_HOURS)).is_fresh());
+        assert!(!cache(chrono::Duration::days(7)).is_fresh());
+    }
+
+    #[test]
+    fn merged_models_keep_discovered_ids_with_curated_details() {
+        let discovered = |id: &str| GeminiModel {
+            id: id.to_string(),
+            name: id.to_string(),
+            description: String::new(),
+            supports_audio: true,
+            context_window: String::new(),
+        };
+
+        let merged = merge_models(vec![discovered("gemini-2.5-flash"), discovered("gemini-3.0-flash")]);
+        let ids: Vec<&str> = merged.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, vec!["gemini-2.5-flash", "gemini-3.0-flash"]);
+        assert_eq!(merged[0].name, "Gemini 2.5 Flash");
+        assert_eq!(merged[1].name, "gemini-3.0-flash");
+
+        assert_eq!(merge_models(Vec::new()).len(), get_available_models().len());
+    }
+
+    #[test]
+    fn audio_support_goes_by_model_family() {
+        assert!(model_supports_audio("gemini-2.5-pro"));
+        assert!(!model_supports_audio("gemini-embedding-001"));
+        assert!(!model_supports_audio("gemini-pro-vision"));
+        assert!(!model_supports_audio("text-bison-001"));
+    }
+}
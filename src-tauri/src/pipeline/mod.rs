@@ -0,0 +1,482 @@
+use anyhow::{Result, anyhow};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use tokio::sync::Notify;
+use crate::models::{OverflowPolicy, QueueStats};
+
+/// What a receiver gets from a pipeline queue. `Gap` stands in for items
+/// discarded by `OverflowPolicy::DropOldest` or lost from the spill file.
+#[derive(Debug)]
+pub enum Message<T> {
+    Item(T),
+    Gap { dropped: u64 },
+}
+
+#[derive(Debug, Clone)]
+pub struct QueueConfig {
+    pub name: String,
+    pub capacity: usize,
+    pub policy: OverflowPolicy,
+    /// Directory for the spill file used by `OverflowPolicy::SpillToDisk`.
+    pub spill_dir: PathBuf,
+}
+
+/// Overflow items appended as JSON lines and read back in order.
+struct SpillFile {
+    path: PathBuf,
+    writer: File,
+    reader: BufReader<File>,
+    count: usize,
+}
+
+impl SpillFile {
+    fn create(path: PathBuf) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Appending keeps writes at the end after the file is truncated in `pop`
+        let writer = OpenOptions::new().create(true).append(true).open(&path)?;
+        let reader = BufReader::new(File::open(&path)?);
+        Ok(Self { path, writer, reader, count: 0 })
+    }
+
+    fn push<T: Serialize>(&mut self, item: &T) -> Result<()> {
+        let mut line = serde_json::to_vec(item)?;
+        line.push(b'\n');
+        self.writer.write_all(&line)?;
+        self.writer.flush()?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn pop<T: DeserializeOwned>(&mut self) -> Result<Option<T>> {
+        if self.count == 0 {
+            return Ok(None);
+        }
+
+        self.count -= 1;
+        let mut line = String::new();
+        self.reader.read_line(&mut line)?;
+
+        // Start over once drained so the file does not grow for the whole session
+        if self.count == 0 {
+            self.writer.set_len(0)?;
+            self.reader = BufReader::new(File::open(&self.path)?);
+        }
+
+        Ok(Some(serde_json::from_str(&line)?))
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+struct QueueState<T> {
+    queue: VecDeque<Message<T>>,
+    items: usize,
+    spill: Option<SpillFile>,
+    dropped: u64,
+    spilled_total: u64,
+    senders: usize,
+    receiver_alive: bool,
+}
+
+struct Shared<T> {
+    config: QueueConfig,
+    state: Mutex<QueueState<T>>,
+    item_ready: Notify,
+    space_ready: Notify,
+    space_ready_blocking: Condvar,
+}
+
+/// Type-erased access to a queue's counters for diagnostics.
+pub trait QueueMonitor: Send + Sync {
+    fn stats(&self) -> QueueStats;
+}
+
+impl<T: Send> QueueMonitor for Shared<T> {
+    fn stats(&self) -> QueueStats {
+        let state = self.state.lock().unwrap();
+        let spilled = state.spill.as_ref().map_or(0, |s| s.count);
+
+        QueueStats {
+            name: self.config.name.clone(),
+            capacity: self.config.capacity,
+            depth: state.items + spilled,
+            spilled,
+            spilled_total: state.spilled_total,
+            dropped: state.dropped,
+        }
+    }
+}
+
+pub struct PipelineSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct PipelineReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Bounded queue whose behaviour when full is set by `config.policy`.
+pub fn channel<T>(config: QueueConfig) -> (PipelineSender<T>, PipelineReceiver<T>)
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+{
+    let shared = Arc::new(Shared {
+        config,
+        state: Mutex::new(QueueState {
+            queue: VecDeque::new(),
+            items: 0,
+            spill: None,
+            dropped: 0,
+            spilled_total: 0,
+            senders: 1,
+            receiver_alive: true,
+        }),
+        item_ready: Notify::new(),
+        space_ready: Notify::new(),
+        space_ready_blocking: Condvar::new(),
+    });
+
+    (
+        PipelineSender { shared: Arc::clone(&shared) },
+        PipelineReceiver { shared },
+    )
+}
+
+impl<T> Shared<T>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+{
+    /// Enqueues unless the queue is full under `OverflowPolicy::Block`, in
+    /// which case the item is handed back.
+    fn try_push(&self, state: &mut QueueState<T>, item: T) -> Result<Option<T>> {
+        if !state.receiver_alive {
+            return Err(anyhow!("{} queue receiver dropped", self.config.name));
+        }
+
+        let capacity = self.config.capacity.max(1);
+        let spilling = state.spill.as_ref().is_some_and(|s| s.count > 0);
+
+        if state.items < capacity && !spilling {
+            state.queue.push_back(Message::Item(item));
+            state.items += 1;
+        } else {
+            match self.config.policy {
+                OverflowPolicy::Block => return Ok(Some(item)),
+                OverflowPolicy::DropOldest => {
+                    Self::drop_oldest(state);
+                    state.queue.push_back(Message::Item(item));
+                    state.items += 1;
+                }
+                OverflowPolicy::SpillToDisk => {
+                    if state.spill.is_none() {
+                        let path = self.config.spill_dir.join(format!(
+                            "{}-{}.jsonl",
+                            self.config.name,
+                            uuid::Uuid::new_v4()
+                        ));
+                        state.spill = Some(SpillFile::create(path)?);
+                    }
+                    state.spill.as_mut().unwrap().push(&item)?;
+                    state.spilled_total += 1;
+                }
+            }
+        }
+
+        self.item_ready.notify_one();
+        Ok(None)
+    }
+
+    /// Removes the oldest item, merging it into the gap marker in front of it.
+    fn drop_oldest(state: &mut QueueState<T>) {
+        let position = state.queue.iter().position(|m| matches!(m, Message::Item(_)));
+        let Some(position) = position else { return };
+
+        state.queue.remove(position);
+        state.items -= 1;
+        state.dropped += 1;
+
+        match state.queue.get_mut(position.saturating_sub(1)) {
+            Some(Message::Gap { dropped }) if position > 0 => *dropped += 1,
+            _ => state.queue.insert(position, Message::Gap { dropped: 1 }),
+        }
+    }
+
+    /// Marks an item that could not be read back from disk, merging it into
+    /// a gap marker already at the back.
+    fn push_gap(state: &mut QueueState<T>) {
+        state.dropped += 1;
+
+        match state.queue.back_mut() {
+            Some(Message::Gap { dropped }) => *dropped += 1,
+            _ => state.queue.push_back(Message::Gap { dropped: 1 }),
+        }
+    }
+
+    fn pop(&self, state: &mut QueueState<T>) -> Option<Message<T>> {
+        let mut message = state.queue.pop_front();
+        if matches!(message, Some(Message::Item(_))) {
+            state.items -= 1;
+        }
+
+        // Refill from disk in order as room frees up
+        let capacity = self.config.capacity.max(1);
+        while state.items < capacity {
+            let Some(spill) = state.spill.as_mut() else { break };
+            if spill.count == 0 {
+                break;
+            }
+            match spill.pop() {
+                Ok(Some(item)) => {
+                    state.queue.push_back(Message::Item(item));
+                    state.items += 1;
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("Pipeline: Failed to read spilled {} item: {}", self.config.name, e);
+                    Self::push_gap(state);
+                }
+            }
+        }
+
+        if message.is_none() {
+            message = state.queue.pop_front();
+            if matches!(message, Some(Message::Item(_))) {
+                state.items -= 1;
+            }
+        }
+
+        if message.is_some() {
+            self.space_ready.notify_waiters();
+            self.space_ready_blocking.notify_all();
+        }
+
+        message
+    }
+}
+
+impl<T> PipelineSender<T>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+{
+    /// Sends from async code; waits for room under `OverflowPolicy::Block`.
+    pub async fn send(&self, item: T) -> Result<()> {
+        let mut item = item;
+
+        loop {
+            let notified = self.shared.space_ready.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                match self.shared.try_push(&mut state, item)? {
+                    None => return Ok(()),
+                    Some(returned) => item = returned,
+                }
+            }
+
+            notified.await;
+        }
+    }
+
+    /// Sends from a non-async thread such as the audio callback; blocks the
+    /// thread for room under `OverflowPolicy::Block`.
+    pub fn send_blocking(&self, item: T) -> Result<()> {
+        let mut item = item;
+        let mut state = self.shared.state.lock().unwrap();
+
+        loop {
+            match self.shared.try_push(&mut state, item)? {
+                None => return Ok(()),
+                Some(returned) => item = returned,
+            }
+            state = self.shared.space_ready_blocking.wait(state).unwrap();
+        }
+    }
+}
+
+impl<T> Clone for PipelineSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        Self { shared: Arc::clone(&self.shared) }
+    }
+}
+
+impl<T> Drop for PipelineSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            self.shared.item_ready.notify_one();
+        }
+    }
+}
+
+impl<T> PipelineReceiver<T>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+{
+    /// Next message, or `None` once every sender is gone and the queue
+    /// (including anything spilled to disk) is drained.
+    pub async fn recv(&mut self) -> Option<Message<T>> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if let Some(message) = self.shared.pop(&mut state) {
+                    return Some(message);
+                }
+                if state.senders == 0 {
+                    return None;
+                }
+            }
+
+            self.shared.item_ready.notified().await;
+        }
+    }
+
+    pub fn monitor(&self) -> Arc<dyn QueueMonitor> {
+        Arc::clone(&self.shared) as Arc<dyn QueueMonitor>
+    }
+}
+
+impl<T> Drop for PipelineReceiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.receiver_alive = false;
+        self.shared.space_ready.notify_waiters();
+        self.shared.space_ready_blocking.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn config(policy: OverflowPolicy, spill_dir: &std::path::Path) -> QueueConfig {
+        QueueConfig {
+            name: "test".to_string(),
+            capacity: 2,
+            policy,
+            spill_dir: spill_dir.to_path_buf(),
+        }
+    }
+
+    async fn drain(rx: &mut PipelineReceiver<u32>) -> Vec<String> {
+        let mut received = Vec::new();
+        while let Some(message) = rx.recv().await {
+            received.push(match message {
+                Message::Item(item) => item.to_string(),
+                Message::Gap { dropped } => format!("gap {}", dropped),
+            });
+        }
+        received
+    }
+
+    #[test]
+    fn spill_file_starts_over_cleanly_once_drained() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spill = SpillFile::create(dir.path().join("queue.jsonl")).unwrap();
+
+        spill.push(&"first".to_string()).unwrap();
+        spill.push(&"second".to_string()).unwrap();
+        assert_eq!(spill.pop::<String>().unwrap().as_deref(), Some("first"));
+        assert_eq!(spill.pop::<String>().unwrap().as_deref(), Some("second"));
+
+        spill.push(&"third".to_string()).unwrap();
+        assert_eq!(spill.pop::<String>().unwrap().as_deref(), Some("third"));
+        assert_eq!(spill.pop::<String>().unwrap(), None);
+        assert_eq!(std::fs::metadata(&spill.path).unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_the_receiver() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tx, mut rx) = channel::<u32>(config(OverflowPolicy::Block, dir.path()));
+        tx.send(1).await.unwrap();
+        tx.send(2).await.unwrap();
+
+        let sender = tokio::spawn(async move { tx.send(3).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!sender.is_finished());
+
+        assert!(matches!(rx.recv().await, Some(Message::Item(1))));
+        sender.await.unwrap().unwrap();
+        assert_eq!(drain(&mut rx).await, vec!["2", "3"]);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_merges_consecutive_drops_into_one_gap() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tx, mut rx) = channel::<u32>(config(OverflowPolicy::DropOldest, dir.path()));
+        let monitor = rx.monitor();
+        for item in 1..=5 {
+            tx.send(item).await.unwrap();
+        }
+        drop(tx);
+
+        assert_eq!(monitor.stats().dropped, 3);
+        assert_eq!(drain(&mut rx).await, vec!["gap 3", "4", "5"]);
+    }
+
+    #[tokio::test]
+    async fn spill_to_disk_keeps_items_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tx, mut rx) = channel::<u32>(config(OverflowPolicy::SpillToDisk, dir.path()));
+        let monitor = rx.monitor();
+        for item in 1..=6 {
+            tx.send_blocking(item).unwrap();
+        }
+        drop(tx);
+
+        let stats = monitor.stats();
+        assert_eq!((stats.depth, stats.spilled, stats.spilled_total), (6, 4, 4));
+        assert_eq!(drain(&mut rx).await, vec!["1", "2", "3", "4", "5", "6"]);
+    }
+
+    #[tokio::test]
+    async fn unreadable_spilled_items_leave_a_gap() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config(OverflowPolicy::SpillToDisk, dir.path());
+        config.capacity = 1;
+        let (tx, mut rx) = channel::<u32>(config);
+        for item in 1..=3 {
+            tx.send(item).await.unwrap();
+        }
+        drop(tx);
+
+        let spill_path = rx.shared.state.lock().unwrap().spill.as_ref().unwrap().path.clone();
+        std::fs::write(&spill_path, "not json\n3\n").unwrap();
+
+        assert_eq!(drain(&mut rx).await, vec!["1", "gap 1", "3"]);
+        assert_eq!(rx.monitor().stats().dropped, 1);
+    }
+
+    #[tokio::test]
+    async fn recv_ends_once_every_sender_is_gone() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tx, mut rx) = channel::<u32>(config(OverflowPolicy::Block, dir.path()));
+        let other = tx.clone();
+        tx.send(1).await.unwrap();
+        drop(tx);
+
+        let receiver = tokio::spawn(async move { drain(&mut rx).await });
+        other.send(2).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!receiver.is_finished());
+
+        drop(other);
+        assert_eq!(receiver.await.unwrap(), vec!["1", "2"]);
+    }
+}
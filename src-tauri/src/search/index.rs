@@ -0,0 +1,403 @@
+use std::collections::{HashMap, HashSet};
+use super::{tokenize, B, K1};
+use crate::models::{SearchField, SearchHit, SearchMatch, Transcription};
+
+/// Characters of context shown on each side of the first matching word.
+const SNIPPET_CONTEXT: usize = 80;
+/// Length of the passages plain text is cut into when there are no segments.
+const TEXT_PASSAGE_CHARS: usize = 400;
+/// Matching passages returned per transcription.
+const MATCHES_PER_HIT: usize = 3;
+
+struct Passage {
+    transcription_id: String,
+    field: SearchField,
+    text: String,
+    reference_id: Option<String>,
+    start_time: Option<f64>,
+    end_time: Option<f64>,
+    length: usize,
+    terms: Vec<String>,
+}
+
+struct IndexedTranscription {
+    title: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+    passages: Vec<u64>,
+}
+
+/// Inverted index over the passages of every transcription: the title, the
+/// summaries, each chapter and each segment (or chunk of plain text for
+/// transcriptions without segments). Passages are ranked with BM25 and
+/// grouped into one hit per transcription.
+#[derive(Default)]
+pub struct SearchIndex {
+    postings: HashMap<String, HashMap<u64, usize>>,
+    passages: HashMap<u64, Passage>,
+    transcriptions: HashMap<String, IndexedTranscription>,
+    total_length: usize,
+    next_id: u64,
+}
+
+impl SearchIndex {
+    /// Indexes `transcription`, replacing what was indexed for it before.
+    pub fn upsert(&mut self, transcription: &Transcription) {
+        self.remove(&transcription.id);
+
+        let mut passage_ids = Vec::new();
+        for passage in Self::passages(transcription) {
+            let id = self.next_id;
+            self.next_id += 1;
+
+            let mut frequencies: HashMap<String, usize> = HashMap::new();
+            for term in tokenize(&passage.text) {
+                *frequencies.entry(term).or_default() += 1;
+            }
+
+            let length = frequencies.values().sum();
+            let terms: Vec<String> = frequencies.keys().cloned().collect();
+            for (term, frequency) in frequencies {
+                self.postings.entry(term).or_default().insert(id, frequency);
+            }
+
+            self.total_length += length;
+            self.passages.insert(id, Passage { length, terms, ..passage });
+            passage_ids.push(id);
+        }
+
+        self.transcriptions.insert(
+            transcription.id.clone(),
+            IndexedTranscription {
+                title: transcription.title.clone(),
+                created_at: transcription.created_at,
+                passages: passage_ids,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, transcription_id: &str) {
+        let Some(indexed) = self.transcriptions.remove(transcription_id) else {
+            return;
+        };
+
+        for id in indexed.passages {
+            let Some(passage) = self.passages.remove(&id) else {
+                continue;
+            };
+            self.total_length -= passage.length;
+
+            for term in passage.terms {
+                if let Some(postings) = self.postings.get_mut(&term) {
+                    postings.remove(&id);
+                    if postings.is_empty() {
+                        self.postings.remove(&term);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Transcriptions matching any word of `query`, best first.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchHit> {
+        let mut terms = tokenize(query);
+        terms.sort();
+        terms.dedup();
+
+        if terms.is_empty() || self.passages.is_empty() {
+            return Vec::new();
+        }
+
+        let count = self.passages.len() as f64;
+        let average_length = (self.total_length as f64 / count).max(1.0);
+
+        let mut passage_scores: HashMap<u64, f64> = HashMap::new();
+        for term in &terms {
+            let Some(postings) = self.postings.get(term) else {
+                continue;
+            };
+
+            let df = postings.len() as f64;
+            let idf = ((count - df + 0.5) / (df + 0.5) + 1.0).ln();
+
+            for (id, frequency) in postings {
+                let passage = &self.passages[id];
+                let frequency = *frequency as f64;
+                let length_ratio = passage.length as f64 / average_length;
+                let score = idf * frequency * (K1 + 1.0) / (frequency + K1 * (1.0 - B + B * length_ratio));
+                *passage_scores.entry(*id).or_default() += score * passage.field.weight();
+            }
+        }
+
+        let mut by_transcription: HashMap<&str, Vec<(u64, f64)>> = HashMap::new();
+        for (id, score) in passage_scores {
+            by_transcription
+                .entry(self.passages[&id].transcription_id.as_str())
+                .or_default()
+                .push((id, score));
+        }
+
+        let term_set: HashSet<&str> = terms.iter().map(|t| t.as_str()).collect();
+        let mut hits: Vec<SearchHit> = by_transcription
+            .into_iter()
+            .map(|(transcription_id, mut scored)| {
+                // Ties broken by passage order so results are stable
+                scored.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+
+                // The best passage counts fully, further ones add a little
+                let score = scored[0].1 + scored[1..].iter().take(4).map(|(_, s)| s * 0.25).sum::<f64>();
+                let indexed = &self.transcriptions[transcription_id];
+
+                let matches = scored
+                    .iter()
+                    .take(MATCHES_PER_HIT)
+                    .map(|(id, _)| {
+                        let passage = &self.passages[id];
+                        SearchMatch {
+                            field: passage.field,
+                            snippet: snippet(&passage.text, &term_set),
+                            reference_id: passage.reference_id.clone(),
+                            start_time: passage.start_time,
+                            end_time: passage.end_time,
+                        }
+                    })
+                    .collect();
+
+                SearchHit {
+                    transcription_id: transcription_id.to_string(),
+                    title: indexed.title.clone(),
+                    created_at: indexed.created_at,
+                    score,
+                    matches,
+                }
+            })
+            .collect();
+
+        hits.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then(b.created_at.cmp(&a.created_at))
+                .then(a.transcription_id.cmp(&b.transcription_id))
+        });
+        hits.truncate(limit);
+        hits
+    }
+
+    fn passages(transcription: &Transcription) -> Vec<Passage> {
+        let passage = |field, text: String, reference_id, start_time, end_time| Passage {
+            transcription_id: transcription.id.clone(),
+            field,
+            text,
+            reference_id,
+            start_time,
+            end_time,
+            length: 0,
+            terms: Vec::new(),
+        };
+
+        let mut passages = vec![passage(SearchField::Title, transcription.title.clone(), None, None, None)];
+
+        let notes = &transcription.notes;
+        let summary: Vec<&str> = notes
+            .executive_summary
+            .iter()
+            .map(|s| s.as_str())
+            .chain(notes.bullet_summary.iter().flatten().map(|s| s.as_str()))
+            .collect();
+        if !summary.is_empty() {
+            passages.push(passage(SearchField::Summary, summary.join("\n"), None, None, None));
+        }
+
+        for chapter in &transcription.chapters {
+            passages.push(passage(
+                SearchField::Chapter,
+                format!("{}\n{}", chapter.title, chapter.content),
+                Some(chapter.id.clone()),
+                Some(chapter.start_time),
+                Some(chapter.end_time),
+            ));
+        }
+
+        if transcription.segments.is_empty() {
+            for chunk in text_chunks(&transcription.raw_text) {
+                passages.push(passage(SearchField::Text, chunk, None, None, None));
+            }
+        } else {
+            for segment in &transcription.segments {
+                passages.push(passage(
+                    SearchField::Segment,
+                    segment.text.clone(),
+                    Some(segment.id.clone()),
+                    Some(segment.start_time),
+                    Some(segment.end_time),
+                ));
+            }
+        }
+
+        passages
+    }
+}
+
+fn text_chunks(text: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+
+    for word in text.split_whitespace() {
+        if current.len() + word.len() >= TEXT_PASSAGE_CHARS && !current.is_empty() {
+            chunks.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
+/// Text around the first word of `text` that is one of `terms`.
+fn snippet(text: &str, terms: &HashSet<&str>) -> String {
+    let mut word_start = None;
+    let mut found = None;
+
+    for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+        match (c.is_alphanumeric(), word_start) {
+            (true, None) => word_start = Some(i),
+            (false, Some(start)) => {
+                if terms.contains(text[start..i].to_lowercase().as_str()) {
+                    found = Some(start);
+                    break;
+                }
+                word_start = None;
+            }
+            _ => {}
+        }
+    }
+
+    let position = found.unwrap_or(0);
+    let mut start = position.saturating_sub(SNIPPET_CONTEXT);
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = (position + SNIPPET_CONTEXT * 2).min(text.len());
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+
+    // Drop words cut in half at either end
+    let mut words: Vec<&str> = text[start..end].split_whitespace().collect();
+    if end < text.len() && !text[end..].starts_with(char::is_whitespace) && words.len() > 1 {
+        words.pop();
+    }
+    if start > 0 && !text[..start].ends_with(char::is_whitespace) && words.len() > 1 {
+        words.remove(0);
+    }
+
+    let mut snippet = words.join(" ");
+    if start > 0 {
+        snippet.insert_str(0, "… ");
+    }
+    if end < text.len() {
+        snippet.push_str(" …");
+    }
+    snippet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn transcription(id: &str, title: &str, segments: &[&str], raw_text: &str) -> Transcription {
+        let segments: Vec<_> = segments
+            .iter()
+            .enumerate()
+            .map(|(i, text)| {
+                json!({
+                    "id": format!("{}-s{}", id, i),
+                    "text": text,
+                    "start_time": i as f64 * 5.0,
+                    "end_time": (i + 1) as f64 * 5.0,
+                    "confidence": 0.9,
+                    "words": []
+                })
+            })
+            .collect();
+
+        serde_json::from_value(json!({
+            "id": id,
+            "title": title,
+            "created_at": "2024-05-01T10:00:00Z",
+            "duration": 60,
+            "chapters": [],
+            "raw_text": raw_text,
+            "status": "Completed",
+            "segments": segments
+        }))
+        .unwrap()
+    }
+
+    fn ids(hits: &[SearchHit]) -> Vec<&str> {
+        hits.iter().map(|h| h.transcription_id.as_str()).collect()
+    }
+
+    #[test]
+    fn search_points_at_the_matching_segment() {
+        let mut index = SearchIndex::default();
+        index.upsert(&transcription("a", "Weekly sync", &["Hello everyone", "The budget is approved"], ""));
+
+        let hits = index.search("Budget", 10);
+
+        assert_eq!(ids(&hits), vec!["a"]);
+        let best = &hits[0].matches[0];
+        assert_eq!(best.field, SearchField::Segment);
+        assert_eq!(best.reference_id.as_deref(), Some("a-s1"));
+        assert_eq!((best.start_time, best.end_time), (Some(5.0), Some(10.0)));
+        assert_eq!(best.snippet, "The budget is approved");
+    }
+
+    #[test]
+    fn title_matches_rank_above_text_matches() {
+        let mut index = SearchIndex::default();
+        index.upsert(&transcription("text", "Weekly sync", &[], "We talked about the roadmap for a while"));
+        index.upsert(&transcription("title", "Roadmap review", &[], "Nothing else to add"));
+
+        assert_eq!(ids(&index.search("roadmap", 10)), vec!["title", "text"]);
+        assert_eq!(ids(&index.search("roadmap", 1)), vec!["title"]);
+        assert!(index.search("", 10).is_empty());
+        assert!(index.search("missing", 10).is_empty());
+    }
+
+    #[test]
+    fn upsert_replaces_the_previous_version() {
+        let mut index = SearchIndex::default();
+        index.upsert(&transcription("a", "Draft", &["apples and pears"], ""));
+        index.upsert(&transcription("a", "Draft", &["oranges"], ""));
+
+        assert!(index.search("apples", 10).is_empty());
+        assert_eq!(ids(&index.search("oranges", 10)), vec!["a"]);
+        assert!(!index.postings.contains_key("apples"));
+        assert_eq!(index.passages.len(), 2);
+        assert_eq!(index.total_length, 2);
+    }
+
+    #[test]
+    fn remove_drops_every_passage() {
+        let mut index = SearchIndex::default();
+        index.upsert(&transcription("a", "Planning", &["first topic", "second topic"], ""));
+        index.upsert(&transcription("b", "Retro", &["another topic"], ""));
+
+        index.remove("a");
+        index.remove("unknown");
+
+        assert_eq!(ids(&index.search("topic planning", 10)), vec!["b"]);
+        assert!(!index.postings.contains_key("planning"));
+        assert_eq!(index.postings["topic"].len(), 1);
+
+        index.remove("b");
+        assert!(index.passages.is_empty() && index.postings.is_empty());
+        assert_eq!(index.total_length, 0);
+    }
+}
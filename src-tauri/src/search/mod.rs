@@ -0,0 +1,95 @@
+pub mod index;
+
+use std::collections::HashMap;
+
+/// BM25 term-frequency saturation.
+const K1: f64 = 1.2;
+/// BM25 document-length normalisation.
+const B: f64 = 0.75;
+
+/// Lowercased alphanumeric words of at least two characters.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= 2)
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+/// Okapi BM25 over a fixed set of documents.
+pub struct Bm25 {
+    term_frequencies: Vec<HashMap<String, usize>>,
+    lengths: Vec<usize>,
+    document_frequencies: HashMap<String, usize>,
+    average_length: f64,
+}
+
+impl Bm25 {
+    pub fn new<I, S>(documents: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut term_frequencies = Vec::new();
+        let mut lengths = Vec::new();
+        let mut document_frequencies: HashMap<String, usize> = HashMap::new();
+
+        for document in documents {
+            let tokens = tokenize(document.as_ref());
+            let mut frequencies: HashMap<String, usize> = HashMap::new();
+            for token in &tokens {
+                *frequencies.entry(token.clone()).or_default() += 1;
+            }
+            for term in frequencies.keys() {
+                *document_frequencies.entry(term.clone()).or_default() += 1;
+            }
+
+            lengths.push(tokens.len());
+            term_frequencies.push(frequencies);
+        }
+
+        let average_length = if lengths.is_empty() {
+            0.0
+        } else {
+            lengths.iter().sum::<usize>() as f64 / lengths.len() as f64
+        };
+
+        Self { term_frequencies, lengths, document_frequencies, average_length }
+    }
+
+    /// Documents matching at least one query term, best first. Ties keep
+    /// document order so results are deterministic.
+    pub fn rank(&self, query: &str) -> Vec<(usize, f64)> {
+        let mut terms = tokenize(query);
+        terms.sort();
+        terms.dedup();
+
+        let count = self.term_frequencies.len() as f64;
+        let mut scores: Vec<(usize, f64)> = self
+            .term_frequencies
+            .iter()
+            .enumerate()
+            .filter_map(|(i, frequencies)| {
+                let length_ratio = if self.average_length > 0.0 {
+                    self.lengths[i] as f64 / self.average_length
+                } else {
+                    1.0
+                };
+
+                let score: f64 = terms
+                    .iter()
+                    .filter_map(|term| {
+                        let frequency = *frequencies.get(term)? as f64;
+                        let df = self.document_frequencies[term] as f64;
+                        let idf = ((count - df + 0.5) / (df + 0.5) + 1.0).ln();
+                        Some(idf * frequency * (K1 + 1.0) / (frequency + K1 * (1.0 - B + B * length_ratio)))
+                    })
+                    .sum();
+
+                (score > 0.0).then_some((i, score))
+            })
+            .collect();
+
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        scores
+    }
+}
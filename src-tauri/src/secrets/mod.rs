@@ -0,0 +1,340 @@
+use anyhow::{Result, anyhow};
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::RngCore;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use crate::models::{SecretBackend, SecretStoreStatus};
+
+/// Secret name under which the Gemini API key is stored.
+pub const GEMINI_API_KEY: &str = "gemini_api_key";
+
+/// Secret name of a credential profile's API key.
+pub fn profile_key_name(profile_id: &str) -> String {
+    format!("profile:{}", profile_id)
+}
+
+const KEYRING_SERVICE: &str = "trascrivi-ai";
+const ENCRYPTED_FILE_NAME: &str = "secrets.enc";
+const ENCRYPTED_FILE_MAGIC: &[u8; 4] = b"TRSC";
+const ENCRYPTED_FILE_VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+pub trait SecretStore: Send + Sync {
+    fn get(&self, name: &str) -> Result<Option<String>>;
+    fn set(&self, name: &str, value: &str) -> Result<()>;
+    fn delete(&self, name: &str) -> Result<()>;
+}
+
+/// OS credential store: Secret Service (libsecret/GNOME Keyring/KWallet) on
+/// Linux, Keychain on macOS and Credential Manager on Windows.
+pub struct KeyringStore;
+
+impl KeyringStore {
+    /// Probes the platform store; a missing entry still means it is usable.
+    pub fn is_available() -> bool {
+        match keyring::Entry::new(KEYRING_SERVICE, "availability-probe").and_then(|e| e.get_password()) {
+            Ok(_) | Err(keyring::Error::NoEntry) => true,
+            Err(e) => {
+                eprintln!("SecretStore: OS keyring unavailable: {}", e);
+                false
+            }
+        }
+    }
+}
+
+impl SecretStore for KeyringStore {
+    fn get(&self, name: &str) -> Result<Option<String>> {
+        match keyring::Entry::new(KEYRING_SERVICE, name)?.get_password() {
+            Ok(value) => Ok(Some(value)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn set(&self, name: &str, value: &str) -> Result<()> {
+        keyring::Entry::new(KEYRING_SERVICE, name)?.set_password(value)?;
+        Ok(())
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        match keyring::Entry::new(KEYRING_SERVICE, name)?.delete_password() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Fallback store: a JSON map encrypted with ChaCha20-Poly1305 under a key
+/// derived from the user's passphrase with Argon2id.
+///
+/// File layout: magic, version, salt, nonce, ciphertext.
+pub struct EncryptedFileStore {
+    path: PathBuf,
+    salt: [u8; SALT_LEN],
+    key: [u8; 32],
+    secrets: Mutex<HashMap<String, String>>,
+}
+
+impl EncryptedFileStore {
+    /// Creates an empty store protected by `passphrase` and writes it right
+    /// away, so later opens check the passphrase. `confirmation` must repeat
+    /// the passphrase: a typo here would become the key for good.
+    pub fn create(path: PathBuf, passphrase: &str, confirmation: &str) -> Result<Self> {
+        if path.exists() {
+            return Err(anyhow!("{} already exists", path.display()));
+        }
+        if passphrase.is_empty() {
+            return Err(anyhow!("Passphrase cannot be empty"));
+        }
+        if passphrase != confirmation {
+            return Err(anyhow!("Passphrase confirmation does not match"));
+        }
+
+        let mut salt = [0u8; SALT_LEN];
+        rand::rngs::OsRng.fill_bytes(&mut salt);
+        let key = Self::derive_key(passphrase, &salt)?;
+        let store = Self { path, salt, key, secrets: Mutex::new(HashMap::new()) };
+
+        store.persist(&HashMap::new())?;
+        Ok(store)
+    }
+
+    /// Opens an existing store. Fails when the file is not a secrets file of
+    /// this version or the passphrase does not decrypt it.
+    pub fn open(path: PathBuf, passphrase: &str) -> Result<Self> {
+        let data = std::fs::read(&path)?;
+        let magic_len = ENCRYPTED_FILE_MAGIC.len();
+        let header_len = magic_len + 1 + SALT_LEN + NONCE_LEN;
+        if data.len() < header_len || &data[..magic_len] != ENCRYPTED_FILE_MAGIC {
+            return Err(anyhow!("{} is not a secrets file", path.display()));
+        }
+        if data[magic_len] != ENCRYPTED_FILE_VERSION {
+            return Err(anyhow!("Unsupported secrets file version {}", data[magic_len]));
+        }
+
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(&data[magic_len + 1..magic_len + 1 + SALT_LEN]);
+        let nonce = &data[magic_len + 1 + SALT_LEN..header_len];
+        let key = Self::derive_key(passphrase, &salt)?;
+
+        let plaintext = ChaCha20Poly1305::new(Key::from_slice(&key))
+            .decrypt(Nonce::from_slice(nonce), &data[header_len..])
+            .map_err(|_| anyhow!("Wrong passphrase or corrupted secrets file"))?;
+        let secrets: HashMap<String, String> = serde_json::from_slice(&plaintext)?;
+
+        Ok(Self { path, salt, key, secrets: Mutex::new(secrets) })
+    }
+
+    fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32]> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| anyhow!("Failed to derive key: {}", e))?;
+        Ok(key)
+    }
+
+    fn persist(&self, secrets: &HashMap<String, String>) -> Result<()> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::rngs::OsRng.fill_bytes(&mut nonce);
+
+        let plaintext = serde_json::to_vec(secrets)?;
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&self.key))
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+            .map_err(|_| anyhow!("Failed to encrypt secrets"))?;
+
+        let mut data = Vec::with_capacity(ENCRYPTED_FILE_MAGIC.len() + 1 + SALT_LEN + NONCE_LEN + ciphertext.len());
+        data.extend_from_slice(ENCRYPTED_FILE_MAGIC);
+        data.push(ENCRYPTED_FILE_VERSION);
+        data.extend_from_slice(&self.salt);
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&ciphertext);
+
+        // No backup: it would keep secrets that were deleted or replaced
+        crate::storage::write_atomic_sync_no_backup(&self.path, &data)
+    }
+}
+
+impl SecretStore for EncryptedFileStore {
+    fn get(&self, name: &str) -> Result<Option<String>> {
+        Ok(self.secrets.lock().unwrap().get(name).cloned())
+    }
+
+    fn set(&self, name: &str, value: &str) -> Result<()> {
+        let mut secrets = self.secrets.lock().unwrap();
+        secrets.insert(name.to_string(), value.to_string());
+        self.persist(&secrets)
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        let mut secrets = self.secrets.lock().unwrap();
+        if secrets.remove(name).is_some() {
+            self.persist(&secrets)?;
+        }
+        Ok(())
+    }
+}
+
+/// Picks the OS keyring when available, otherwise the encrypted file, which
+/// stays locked until `unlock` is called with the user's passphrase.
+#[derive(Clone)]
+pub struct SecretManager {
+    backend: SecretBackend,
+    store: Arc<Mutex<Option<Arc<dyn SecretStore>>>>,
+    encrypted_file_path: PathBuf,
+}
+
+impl SecretManager {
+    pub fn new(data_dir: &Path) -> Self {
+        let encrypted_file_path = data_dir.join(ENCRYPTED_FILE_NAME);
+
+        let (backend, store): (SecretBackend, Option<Arc<dyn SecretStore>>) = if KeyringStore::is_available() {
+            (SecretBackend::Keyring, Some(Arc::new(KeyringStore)))
+        } else {
+            (SecretBackend::EncryptedFile, None)
+        };
+
+        println!("SecretManager: Using {:?} backend", backend);
+
+        Self {
+            backend,
+            store: Arc::new(Mutex::new(store)),
+            encrypted_file_path,
+        }
+    }
+
+    pub fn status(&self) -> SecretStoreStatus {
+        SecretStoreStatus {
+            backend: self.backend,
+            unlocked: self.store.lock().unwrap().is_some(),
+            encrypted_file_exists: self.encrypted_file_path.exists(),
+        }
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.store.lock().unwrap().is_some()
+    }
+
+    /// Opens the encrypted file, or creates it when there is none yet; that
+    /// needs `confirmation` repeating the passphrase.
+    pub fn unlock(&self, passphrase: &str, confirmation: Option<&str>) -> Result<()> {
+        if self.backend != SecretBackend::EncryptedFile {
+            return Ok(());
+        }
+
+        let path = self.encrypted_file_path.clone();
+        let store = if path.exists() {
+            EncryptedFileStore::open(path, passphrase)?
+        } else {
+            let confirmation = confirmation.ok_or_else(|| anyhow!("Confirm the passphrase to create the secret store"))?;
+            EncryptedFileStore::create(path, passphrase, confirmation)?
+        };
+        *self.store.lock().unwrap() = Some(Arc::new(store));
+        Ok(())
+    }
+
+    fn unlocked_store(&self) -> Result<Arc<dyn SecretStore>> {
+        self.store
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| anyhow!("Secret store is locked"))
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<String>> {
+        self.unlocked_store()?.get(name)
+    }
+
+    pub fn set(&self, name: &str, value: &str) -> Result<()> {
+        self.unlocked_store()?.set(name, value)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        self.unlocked_store()?.delete(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store_path(dir: &tempfile::TempDir) -> PathBuf {
+        dir.path().join(ENCRYPTED_FILE_NAME)
+    }
+
+    fn open_error(path: &Path, passphrase: &str) -> String {
+        match EncryptedFileStore::open(path.to_path_buf(), passphrase) {
+            Ok(_) => panic!("{} opened", path.display()),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn secrets_survive_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = store_path(&dir);
+
+        let store = EncryptedFileStore::create(path.clone(), "correct horse", "correct horse").unwrap();
+        store.set(GEMINI_API_KEY, "AIza-secret").unwrap();
+        store.set(&profile_key_name("work"), "AIza-work").unwrap();
+        store.delete(&profile_key_name("work")).unwrap();
+
+        let reopened = EncryptedFileStore::open(path.clone(), "correct horse").unwrap();
+        assert_eq!(reopened.get(GEMINI_API_KEY).unwrap().as_deref(), Some("AIza-secret"));
+        assert_eq!(reopened.get(&profile_key_name("work")).unwrap(), None);
+
+        let data = std::fs::read(&path).unwrap();
+        assert!(!data.windows(11).any(|w| w == b"AIza-secret"));
+    }
+
+    #[test]
+    fn wrong_passphrase_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = store_path(&dir);
+        EncryptedFileStore::create(path.clone(), "correct horse", "correct horse").unwrap();
+
+        assert_eq!(open_error(&path, "correct hose"), "Wrong passphrase or corrupted secrets file");
+    }
+
+    #[test]
+    fn creating_needs_a_matching_confirmation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = store_path(&dir);
+
+        assert!(EncryptedFileStore::create(path.clone(), "correct horse", "correct hose").is_err());
+        assert!(EncryptedFileStore::create(path.clone(), "", "").is_err());
+        assert!(!path.exists());
+
+        EncryptedFileStore::create(path.clone(), "correct horse", "correct horse").unwrap();
+        assert!(EncryptedFileStore::create(path, "other", "other").is_err());
+    }
+
+    #[test]
+    fn foreign_and_damaged_files_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = store_path(&dir);
+        let store = EncryptedFileStore::create(path.clone(), "pass", "pass").unwrap();
+        store.set(GEMINI_API_KEY, "AIza-secret").unwrap();
+        let data = std::fs::read(&path).unwrap();
+        let header_len = ENCRYPTED_FILE_MAGIC.len() + 1 + SALT_LEN + NONCE_LEN;
+
+        let mut bad_magic = data.clone();
+        bad_magic[0] = b'X';
+        std::fs::write(&path, &bad_magic).unwrap();
+        assert!(open_error(&path, "pass").ends_with("is not a secrets file"));
+
+        let mut bad_version = data.clone();
+        bad_version[ENCRYPTED_FILE_MAGIC.len()] = ENCRYPTED_FILE_VERSION + 1;
+        std::fs::write(&path, &bad_version).unwrap();
+        assert_eq!(open_error(&path, "pass"), "Unsupported secrets file version 2");
+
+        std::fs::write(&path, &data[..header_len - 1]).unwrap();
+        assert!(open_error(&path, "pass").ends_with("is not a secrets file"));
+
+        std::fs::write(&path, &data[..data.len() - 1]).unwrap();
+        assert_eq!(open_error(&path, "pass"), "Wrong passphrase or corrupted secrets file");
+    }
+}
@@ -0,0 +1,219 @@
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use super::migrations::Document;
+use super::{backup_path, read_json, write_atomic, write_atomic_sync_no_backup, LoadedTranscriptions, Storage, MAX_REVISIONS};
+use crate::models::{CorruptFile, Revision, Transcription};
+
+/// Subdirectory holding one `<id>.jsonl` revision log per transcription.
+pub const REVISIONS_DIR: &str = "revisions";
+
+/// One pretty-printed `<id>.json` file per transcription in the data
+/// directory, and a revision log with one JSON revision per line.
+pub struct JsonStorage {
+    dir: PathBuf,
+}
+
+impl JsonStorage {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    pub fn revisions_path(&self, id: &str) -> PathBuf {
+        self.dir.join(REVISIONS_DIR).join(format!("{}.jsonl", id))
+    }
+
+    /// Appends a line to the log. Once it holds twice `keep` lines it is
+    /// compacted to the newest `keep`, so the rewrite happens only every
+    /// `keep` appends; readers skip the surplus.
+    fn append_line(path: &Path, line: &str, keep: usize) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let existing = match fs::read_to_string(path) {
+            Ok(existing) => existing,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let lines: Vec<&str> = existing.lines().filter(|l| !l.trim().is_empty()).collect();
+
+        let keep = keep.max(1);
+        if lines.len() + 1 >= keep * 2 {
+            let mut kept = lines[lines.len() + 1 - keep..].to_vec();
+            kept.push(line);
+            // The log itself is the history, so no backup of it is kept
+            return write_atomic_sync_no_backup(path, format!("{}\n", kept.join("\n")).as_bytes());
+        }
+
+        // Don't join the new line onto one torn by a crash
+        let separator = if existing.is_empty() || existing.ends_with('\n') { "" } else { "\n" };
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(format!("{}{}\n", separator, line).as_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// Transcription files are named `<uuid>.json`.
+    pub fn transcription_id(path: &Path) -> Option<String> {
+        if path.extension().and_then(|s| s.to_str()) != Some("json") {
+            return None;
+        }
+        let stem = path.file_stem()?.to_str()?;
+        uuid::Uuid::parse_str(stem).ok()?;
+        Some(stem.to_string())
+    }
+}
+
+#[async_trait]
+impl Storage for JsonStorage {
+    async fn save_transcription(&self, transcription: &Transcription) -> Result<()> {
+        let json_data = serde_json::to_string_pretty(transcription)?;
+        write_atomic(&self.path(&transcription.id), json_data.into_bytes()).await
+    }
+
+    async fn load_transcription(&self, id: &str) -> Result<Transcription> {
+        read_json(&self.path(id))
+            .await?
+            .ok_or_else(|| anyhow!("Transcription {} not found", id))
+    }
+
+    /// Files that cannot be read or parsed are reported rather than skipped
+    /// silently; other JSON files (app state, caches) are not transcriptions
+    /// and are ignored.
+    async fn load_all_transcriptions(&self) -> Result<LoadedTranscriptions> {
+        let mut loaded = LoadedTranscriptions::default();
+
+        if !self.dir.exists() {
+            return Ok(loaded);
+        }
+
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            // A lone backup means a crash hit between the two renames of a save
+            let path = match entry.path() {
+                path if path.extension().and_then(|s| s.to_str()) == Some("bak") => {
+                    let primary = path.with_extension("");
+                    if primary.exists() {
+                        continue;
+                    }
+                    primary
+                }
+                path => path,
+            };
+            let Some(id) = Self::transcription_id(&path) else {
+                continue;
+            };
+
+            match read_json::<Transcription>(&path).await {
+                Ok(None) => {}
+                Ok(Some(transcription)) if transcription.id == id => {
+                    loaded.transcriptions.insert(transcription.id.clone(), transcription);
+                }
+                Ok(Some(transcription)) => loaded.corrupt.push(CorruptFile {
+                    path: path.to_string_lossy().to_string(),
+                    error: format!("file contains transcription {}", transcription.id),
+                }),
+                Err(e) => loaded.corrupt.push(CorruptFile {
+                    path: path.to_string_lossy().to_string(),
+                    error: e.to_string(),
+                }),
+            }
+        }
+
+        Ok(loaded)
+    }
+
+    async fn delete_transcription(&self, id: &str) -> Result<()> {
+        let file_path = self.path(id);
+        let revisions_path = self.revisions_path(id);
+        for path in [backup_path(&file_path), file_path, backup_path(&revisions_path), revisions_path] {
+            if path.exists() {
+                tokio::fs::remove_file(path).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn append_revision(&self, revision: &Revision, keep: usize) -> Result<()> {
+        let path = self.revisions_path(&revision.transcription_id);
+        let line = serde_json::to_string(revision)?;
+        tokio::task::spawn_blocking(move || Self::append_line(&path, &line, keep)).await?
+    }
+
+    /// Lines that do not parse, such as one cut short by a crash, are
+    /// skipped.
+    async fn load_revisions(&self, transcription_id: &str) -> Result<Vec<Revision>> {
+        let path = self.revisions_path(transcription_id);
+        let log = match tokio::fs::read_to_string(&path).await {
+            Ok(log) => log,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut revisions = Vec::new();
+        for (number, line) in log.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+            let revision = serde_json::from_str(line)
+                .map_err(anyhow::Error::from)
+                .and_then(Revision::migrate)
+                .and_then(|document| Ok(serde_json::from_value::<Revision>(document)?));
+            match revision {
+                Ok(revision) => revisions.push(revision),
+                Err(e) => eprintln!("StorageService: Skipping line {} of {}: {}", number + 1, path.display(), e),
+            }
+        }
+
+        // Lines beyond the newest MAX_REVISIONS wait for the next compaction
+        let surplus = revisions.len().saturating_sub(MAX_REVISIONS);
+        revisions.drain(..surplus);
+        Ok(revisions)
+    }
+
+    async fn has_revisions(&self, transcription_id: &str) -> Result<bool> {
+        Ok(self.revisions_path(transcription_id).exists())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(path: &Path) -> Vec<String> {
+        fs::read_to_string(path).unwrap().lines().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn revision_log_is_compacted_every_keep_appends() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(REVISIONS_DIR).join("log.jsonl");
+
+        for line in ["1", "2", "3"] {
+            JsonStorage::append_line(&path, line, 2).unwrap();
+        }
+        assert_eq!(lines(&path), vec!["1", "2", "3"]);
+
+        JsonStorage::append_line(&path, "4", 2).unwrap();
+        assert_eq!(lines(&path), vec!["3", "4"]);
+
+        JsonStorage::append_line(&path, "5", 2).unwrap();
+        assert_eq!(lines(&path), vec!["3", "4", "5"]);
+        assert!(!backup_path(&path).exists());
+    }
+
+    #[test]
+    fn append_does_not_join_a_torn_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.jsonl");
+        fs::write(&path, "1\n{\"torn").unwrap();
+
+        JsonStorage::append_line(&path, "2", 10).unwrap();
+        assert_eq!(lines(&path), vec!["1", "{\"torn", "2"]);
+    }
+}
@@ -0,0 +1,200 @@
+//! Upgrades persisted documents to the current schema before they are
+//! deserialized.
+//!
+//! Fields that are only added get `#[serde(default)]` (or a default
+//! function) and need no migration. Renames, removals, type changes and
+//! values that must be derived from other fields need a step here and a
+//! bump of the type's schema version. Step `n` upgrades a document from
+//! version `n + 1` to `n + 2`; documents without a version are version 1.
+
+use anyhow::{Result, anyhow};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use crate::models::{
+    AppState, ModelsCache, Revision, Transcription, UsageLedger, APP_STATE_SCHEMA_VERSION,
+    TRANSCRIPTION_SCHEMA_VERSION,
+};
+
+type Step = fn(&mut Value) -> Result<()>;
+
+/// A type persisted as JSON that may need upgrading when read.
+pub trait Document: DeserializeOwned {
+    fn migrate(document: Value) -> Result<Value> {
+        Ok(document)
+    }
+}
+
+impl Document for Transcription {
+    fn migrate(document: Value) -> Result<Value> {
+        run("transcription", document, TRANSCRIPTION_SCHEMA_VERSION, &[transcription_v1_to_v2])
+    }
+}
+
+impl Document for AppState {
+    /// Newer files are still loaded so that settings survive a downgrade;
+    /// fields this version does not know are dropped on the next save.
+    /// Transcriptions still embedded by old versions are upgraded too.
+    fn migrate(document: Value) -> Result<Value> {
+        let mut document = match run("app state", document.clone(), APP_STATE_SCHEMA_VERSION, &[]) {
+            Ok(document) => document,
+            Err(e) => {
+                eprintln!("StorageService: {}", e);
+                document
+            }
+        };
+
+        if let Some(transcriptions) = document.get_mut("transcriptions").and_then(|t| t.as_object_mut()) {
+            for (id, transcription) in transcriptions.iter_mut() {
+                match Transcription::migrate(transcription.clone()) {
+                    Ok(migrated) => *transcription = migrated,
+                    Err(e) => eprintln!("StorageService: Embedded transcription {}: {}", id, e),
+                }
+            }
+        }
+
+        Ok(document)
+    }
+}
+
+impl Document for Revision {
+    /// The snapshot is upgraded like any stored transcription.
+    fn migrate(mut document: Value) -> Result<Value> {
+        let snapshot = document
+            .get_mut("transcription")
+            .ok_or_else(|| anyhow!("revision has no transcription"))?;
+        *snapshot = Transcription::migrate(snapshot.take())?;
+        Ok(document)
+    }
+}
+
+impl Document for UsageLedger {}
+
+impl Document for ModelsCache {}
+
+fn run(name: &str, mut document: Value, current: u32, steps: &[Step]) -> Result<Value> {
+    debug_assert_eq!(steps.len() as u32, current - 1);
+
+    let fields = document
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("{} is not a JSON object", name))?;
+    let version = match fields.get("schema_version") {
+        None => 1,
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| anyhow!("{} has an invalid schema_version", name))? as u32,
+    };
+
+    if version > current {
+        return Err(anyhow!(
+            "{} has schema version {}, newer than the supported version {}",
+            name,
+            version,
+            current
+        ));
+    }
+
+    for step in &steps[(version as usize).saturating_sub(1)..] {
+        step(&mut document)?;
+    }
+
+    if let Some(fields) = document.as_object_mut() {
+        fields.insert("schema_version".to_string(), Value::from(current));
+    }
+    Ok(document)
+}
+
+/// Version 2 added `Chapter::end_time`. Chapters written before then end
+/// where the next one starts, the last one at the end of the recording.
+fn transcription_v1_to_v2(document: &mut Value) -> Result<()> {
+    let duration = document.get("duration").and_then(|d| d.as_f64()).unwrap_or(0.0);
+    let Some(chapters) = document.get_mut("chapters").and_then(|c| c.as_array_mut()) else {
+        return Ok(());
+    };
+
+    let starts: Vec<f64> = chapters
+        .iter()
+        .map(|c| c.get("start_time").and_then(|t| t.as_f64()).unwrap_or(0.0))
+        .collect();
+
+    for (i, chapter) in chapters.iter_mut().enumerate() {
+        let Some(fields) = chapter.as_object_mut() else {
+            continue;
+        };
+        if !fields.contains_key("end_time") {
+            let end = starts.get(i + 1).copied().unwrap_or(duration).max(starts[i]);
+            fields.insert("end_time".to_string(), Value::from(end));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const APP_STATE_BASELINE: &str = include_str!("fixtures/app_state_baseline.json");
+    const TRANSCRIPTION_V1: &str = include_str!("fixtures/transcription_v1.json");
+    const TRANSCRIPTION_V2: &str = include_str!("fixtures/transcription_v2.json");
+
+    fn load<T: Document>(json: &str) -> T {
+        serde_json::from_value(T::migrate(serde_json::from_str(json).unwrap()).unwrap()).unwrap()
+    }
+
+    fn chapter_ends(transcription: &Transcription) -> Vec<f64> {
+        transcription.chapters.iter().map(|c| c.end_time).collect()
+    }
+
+    #[test]
+    fn v1_chapters_end_where_the_next_starts() {
+        let transcription: Transcription = load(TRANSCRIPTION_V1);
+
+        assert_eq!(transcription.schema_version, TRANSCRIPTION_SCHEMA_VERSION);
+        assert_eq!(chapter_ends(&transcription), vec![45.5, 90.0]);
+        assert_eq!(transcription.segments.len(), 2);
+    }
+
+    #[test]
+    fn v2_transcription_is_unchanged() {
+        let original: Value = serde_json::from_str(TRANSCRIPTION_V2).unwrap();
+        assert_eq!(Transcription::migrate(original.clone()).unwrap(), original);
+
+        let transcription: Transcription = load(TRANSCRIPTION_V2);
+        assert_eq!(chapter_ends(&transcription), vec![40.0]);
+        assert_eq!(transcription.segments[0].speaker.as_deref(), Some("Anna"));
+        assert_eq!(transcription.folder.as_deref(), Some("Retros"));
+    }
+
+    #[test]
+    fn newer_transcription_is_rejected() {
+        let mut document: Value = serde_json::from_str(TRANSCRIPTION_V2).unwrap();
+        document["schema_version"] = Value::from(TRANSCRIPTION_SCHEMA_VERSION + 1);
+
+        assert!(Transcription::migrate(document).is_err());
+    }
+
+    #[test]
+    fn baseline_app_state_upgrades_embedded_transcriptions() {
+        let state: AppState = load(APP_STATE_BASELINE);
+
+        assert_eq!(state.schema_version, APP_STATE_SCHEMA_VERSION);
+        assert_eq!(state.plaintext_api_key.as_deref(), Some("AIza-plaintext-key"));
+        assert_eq!(state.gemini_api_key, None);
+        assert_eq!(state.selected_model, "gemini-2.5-flash");
+
+        let transcription = &state.transcriptions["0f8fad5b-d9cb-469f-a165-70867728950e"];
+        assert_eq!(transcription.schema_version, TRANSCRIPTION_SCHEMA_VERSION);
+        assert_eq!(chapter_ends(transcription), vec![120.0, 300.0]);
+        assert_eq!(transcription.chapters[1].subsections[0].end_time, 300.0);
+    }
+
+    #[tokio::test]
+    async fn read_json_migrates_files_on_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transcription.json");
+        std::fs::write(&path, TRANSCRIPTION_V1).unwrap();
+
+        let transcription: Transcription = crate::storage::read_json(&path).await.unwrap().unwrap();
+        assert_eq!(chapter_ends(&transcription), vec![45.5, 90.0]);
+    }
+}
@@ -1,21 +1,84 @@
+pub mod json;
+pub mod migrations;
+pub mod sqlite;
+
 use anyhow::{Result, anyhow};
+use async_trait::async_trait;
 use serde_json;
 use std::fs;
-use std::path::PathBuf;
-use crate::models::{Transcription, AppState};
+use std::path::{Path, PathBuf};
+use crate::models::{
+    Transcription, AppState, CorruptFile, ModelsCache, Revision, RevisionSource, SearchHit, StorageBackend, TokenUsage,
+    UsageLedger,
+};
+use std::sync::{Arc, RwLock};
 use std::collections::HashMap;
+use crate::search::index::SearchIndex;
+use json::JsonStorage;
+use migrations::Document;
+use sqlite::SqliteStorage;
+
+const SQLITE_FILE_NAME: &str = "transcriptions.db";
+/// Where JSON files go once they have been migrated into SQLite.
+const MIGRATED_JSON_DIR: &str = "migrated-json";
+/// Revisions kept per transcription; older ones are dropped.
+pub const MAX_REVISIONS: usize = 20;
+
+#[derive(Default)]
+pub struct LoadedTranscriptions {
+    pub transcriptions: HashMap<String, Transcription>,
+    pub corrupt: Vec<CorruptFile>,
+}
+
+/// Persistence of transcriptions. App state, usage and caches stay in JSON
+/// files managed by `StorageService` whichever backend is used.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn save_transcription(&self, transcription: &Transcription) -> Result<()>;
+    async fn load_transcription(&self, id: &str) -> Result<Transcription>;
+    async fn load_all_transcriptions(&self) -> Result<LoadedTranscriptions>;
+    /// Deletes the transcription together with its revisions.
+    async fn delete_transcription(&self, id: &str) -> Result<()>;
+    /// Appends `revision` and drops the oldest revisions of the same
+    /// transcription beyond `keep`.
+    async fn append_revision(&self, revision: &Revision, keep: usize) -> Result<()>;
+    /// Revisions of a transcription, oldest first.
+    async fn load_revisions(&self, transcription_id: &str) -> Result<Vec<Revision>>;
+    async fn has_revisions(&self, transcription_id: &str) -> Result<bool>;
+}
 
 #[derive(Clone)]
 pub struct StorageService {
     data_dir: PathBuf,
+    backend: Arc<dyn Storage>,
+    /// Transcriptions that could not be migrated when switching backends.
+    unmigrated: Arc<Vec<CorruptFile>>,
+    /// Kept in step with every save and delete.
+    search_index: Arc<RwLock<SearchIndex>>,
+    usage_lock: Arc<tokio::sync::Mutex<()>>,
 }
 
 impl StorageService {
+    /// Starts on the JSON backend; see `use_backend`.
     pub fn new() -> Result<Self> {
-        let data_dir = Self::get_app_data_dir()?;
+        Self::with_data_dir(Self::get_app_data_dir()?)
+    }
+
+    /// Like `new`, storing everything under `data_dir`.
+    pub fn with_data_dir(data_dir: PathBuf) -> Result<Self> {
         fs::create_dir_all(&data_dir)?;
 
-        Ok(Self { data_dir })
+        Ok(Self {
+            backend: Arc::new(JsonStorage::new(data_dir.clone())),
+            data_dir,
+            unmigrated: Arc::new(Vec::new()),
+            search_index: Arc::new(RwLock::new(SearchIndex::default())),
+            usage_lock: Arc::new(tokio::sync::Mutex::new(())),
+        })
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
     }
 
     fn get_app_data_dir() -> Result<PathBuf> {
@@ -25,69 +88,193 @@ impl StorageService {
         Ok(path)
     }
 
-    pub async fn save_transcription(&self, transcription: &Transcription) -> Result<()> {
-        let file_path = self.data_dir.join(format!("{}.json", transcription.id));
-        let json_data = serde_json::to_string_pretty(transcription)?;
+    /// Switches transcription storage to `backend`, first moving over
+    /// anything left in the other one. JSON files are moved to
+    /// `migrated-json` after being copied into SQLite; a database migrated
+    /// back to JSON is renamed, so each migration only runs once.
+    pub async fn use_backend(mut self, backend: StorageBackend) -> Result<Self> {
+        let json = JsonStorage::new(self.data_dir.clone());
+        let db_path = self.data_dir.join(SQLITE_FILE_NAME);
 
-        tokio::fs::write(file_path, json_data).await?;
-        Ok(())
-    }
+        match backend {
+            StorageBackend::Json => {
+                if db_path.exists() {
+                    let sqlite = SqliteStorage::open(&db_path)?;
+                    let (migrated, failed) = Self::migrate(&sqlite, &json).await?;
+                    drop(sqlite);
 
-    pub async fn load_transcription(&self, id: &str) -> Result<Transcription> {
-        let file_path = self.data_dir.join(format!("{}.json", id));
-        let json_data = tokio::fs::read_to_string(file_path).await?;
-        let transcription: Transcription = serde_json::from_str(&json_data)?;
-        Ok(transcription)
-    }
+                    // Archived even after a partial migration: migrating again on the
+                    // next start would overwrite newer files and bring back deleted ones.
+                    // Rows that failed stay readable in the archive.
+                    let stamp = chrono::Utc::now().format("%Y%m%d%H%M%S");
+                    for suffix in ["", "-wal", "-shm"] {
+                        let path = self.data_dir.join(format!("{}{}", SQLITE_FILE_NAME, suffix));
+                        if path.exists() {
+                            fs::rename(&path, self.data_dir.join(format!("{}{}.migrated-{}", SQLITE_FILE_NAME, suffix, stamp)))?;
+                        }
+                    }
+                    println!("StorageService: Migrated {} transcriptions from SQLite to JSON", migrated.len());
+                    for file in &failed {
+                        eprintln!(
+                            "StorageService: Could not migrate {} ({}); it is kept in {}.migrated-{}",
+                            file.path, file.error, SQLITE_FILE_NAME, stamp
+                        );
+                    }
+                    self.unmigrated = Arc::new(failed);
+                }
+                self.backend = Arc::new(json);
+            }
+            StorageBackend::Sqlite => {
+                let sqlite = SqliteStorage::open(&db_path)?;
+                let (migrated, failed) = Self::migrate(&json, &sqlite).await?;
 
-    pub async fn load_all_transcriptions(&self) -> Result<HashMap<String, Transcription>> {
-        let mut transcriptions = HashMap::new();
+                if !migrated.is_empty() {
+                    let archive = self.data_dir.join(MIGRATED_JSON_DIR);
+                    fs::create_dir_all(&archive)?;
+                    fs::create_dir_all(archive.join(json::REVISIONS_DIR))?;
+                    for id in &migrated {
+                        let file_path = self.data_dir.join(format!("{}.json", id));
+                        let revisions_path = json.revisions_path(id);
+                        let moves = [
+                            (backup_path(&file_path), archive.clone()),
+                            (file_path, archive.clone()),
+                            (backup_path(&revisions_path), archive.join(json::REVISIONS_DIR)),
+                            (revisions_path, archive.join(json::REVISIONS_DIR)),
+                        ];
+                        for (path, target_dir) in moves {
+                            if let (true, Some(file_name)) = (path.exists(), path.file_name()) {
+                                fs::rename(&path, target_dir.join(file_name))?;
+                            }
+                        }
+                    }
+                    println!("StorageService: Migrated {} transcriptions from JSON to SQLite", migrated.len());
+                }
 
-        if !self.data_dir.exists() {
-            return Ok(transcriptions);
+                self.unmigrated = Arc::new(failed);
+                self.backend = Arc::new(sqlite);
+            }
         }
 
-        let mut entries = tokio::fs::read_dir(&self.data_dir).await?;
+        Ok(self)
+    }
 
-        while let Some(entry) = entries.next_entry().await? {
-            let path = entry.path();
-            if path.extension().and_then(|s| s.to_str()) == Some("json") {
-                if let Ok(json_data) = tokio::fs::read_to_string(&path).await {
-                    if let Ok(transcription) = serde_json::from_str::<Transcription>(&json_data) {
-                        transcriptions.insert(transcription.id.clone(), transcription);
-                    }
+    /// Copies every readable transcription and its revisions from `from`
+    /// into `to`, replacing what `to` holds for the same id. Returns the ids
+    /// copied and the entries that could not be read.
+    async fn migrate(from: &dyn Storage, to: &dyn Storage) -> Result<(Vec<String>, Vec<CorruptFile>)> {
+        let loaded = from.load_all_transcriptions().await?;
+        let mut migrated = Vec::new();
+
+        for (id, transcription) in loaded.transcriptions {
+            to.save_transcription(&transcription).await?;
+            if !to.has_revisions(&id).await? {
+                for revision in from.load_revisions(&id).await? {
+                    to.append_revision(&revision, MAX_REVISIONS).await?;
                 }
             }
+            migrated.push(id);
         }
 
-        Ok(transcriptions)
+        Ok((migrated, loaded.corrupt))
     }
 
-    pub async fn delete_transcription(&self, id: &str) -> Result<()> {
-        let file_path = self.data_dir.join(format!("{}.json", id));
-        if file_path.exists() {
-            tokio::fs::remove_file(file_path).await?;
+    /// Transcriptions in the trash are kept out of search results.
+    pub async fn save_transcription(&self, transcription: &Transcription) -> Result<()> {
+        self.backend.save_transcription(transcription).await?;
+        let mut index = self.search_index.write().unwrap();
+        match transcription.deleted_at {
+            Some(_) => index.remove(&transcription.id),
+            None => index.upsert(transcription),
+        }
+        Ok(())
+    }
+
+    /// Saves `transcription` and records it as a new revision. The first
+    /// change to a transcription stored before revisions were kept records
+    /// the stored version too, so it can be restored.
+    pub async fn save_revision(&self, transcription: &Transcription, source: RevisionSource) -> Result<()> {
+        if !self.backend.has_revisions(&transcription.id).await? {
+            if let Ok(previous) = self.backend.load_transcription(&transcription.id).await {
+                let original = Revision::new(&previous, RevisionSource::Unknown);
+                self.backend.append_revision(&original, MAX_REVISIONS).await?;
+            }
         }
+
+        self.save_transcription(transcription).await?;
+        self.backend
+            .append_revision(&Revision::new(transcription, source), MAX_REVISIONS)
+            .await
+    }
+
+    pub async fn load_transcription(&self, id: &str) -> Result<Transcription> {
+        self.backend.load_transcription(id).await
+    }
+
+    /// Revisions of a transcription, oldest first.
+    pub async fn load_revisions(&self, transcription_id: &str) -> Result<Vec<Revision>> {
+        self.backend.load_revisions(transcription_id).await
+    }
+
+    /// Every stored transcription, with entries that failed to load or to
+    /// migrate reported as corrupt.
+    pub async fn load_all_transcriptions(&self) -> Result<LoadedTranscriptions> {
+        let mut loaded = self.backend.load_all_transcriptions().await?;
+        loaded.corrupt.extend(self.unmigrated.iter().cloned());
+        Ok(loaded)
+    }
+
+    pub async fn delete_transcription(&self, id: &str) -> Result<()> {
+        self.backend.delete_transcription(id).await?;
+        self.search_index.write().unwrap().remove(id);
         Ok(())
     }
 
+    /// Indexes transcriptions loaded at startup, except those in the trash.
+    pub fn index_transcriptions<'a>(&self, transcriptions: impl IntoIterator<Item = &'a Transcription>) {
+        let mut index = self.search_index.write().unwrap();
+        for transcription in transcriptions.into_iter().filter(|t| t.deleted_at.is_none()) {
+            index.upsert(transcription);
+        }
+    }
+
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchHit> {
+        self.search_index.read().unwrap().search(query, limit)
+    }
+
     pub async fn save_app_state(&self, state: &AppState) -> Result<()> {
-        let file_path = self.data_dir.join("app_state.json");
         let json_data = serde_json::to_string_pretty(state)?;
-        tokio::fs::write(file_path, json_data).await?;
-        Ok(())
+        write_atomic(&self.data_dir.join("app_state.json"), json_data.into_bytes()).await
     }
 
     pub async fn load_app_state(&self) -> Result<AppState> {
-        let file_path = self.data_dir.join("app_state.json");
+        let state = read_json(&self.data_dir.join("app_state.json")).await?;
+        Ok(state.unwrap_or_default())
+    }
 
-        if !file_path.exists() {
-            return Ok(AppState::default());
-        }
+    pub async fn load_usage_ledger(&self) -> Result<UsageLedger> {
+        let ledger = read_json(&self.data_dir.join("usage.json")).await?;
+        Ok(ledger.unwrap_or_default())
+    }
+
+    /// Adds `usage` to today's totals for `model` and returns the updated ledger.
+    pub async fn record_usage(&self, model: &str, usage: &TokenUsage) -> Result<UsageLedger> {
+        let _guard = self.usage_lock.lock().await;
+
+        let mut ledger = self.load_usage_ledger().await?;
+        crate::usage::record(&mut ledger, chrono::Utc::now().date_naive(), model, usage);
+
+        let json_data = serde_json::to_string_pretty(&ledger)?;
+        write_atomic(&self.data_dir.join("usage.json"), json_data.into_bytes()).await?;
+        Ok(ledger)
+    }
 
-        let json_data = tokio::fs::read_to_string(file_path).await?;
-        let state: AppState = serde_json::from_str(&json_data)?;
-        Ok(state)
+    pub async fn save_models_cache(&self, cache: &ModelsCache) -> Result<()> {
+        let json_data = serde_json::to_string_pretty(cache)?;
+        write_atomic(&self.data_dir.join("models_cache.json"), json_data.into_bytes()).await
+    }
+
+    pub async fn load_models_cache(&self) -> Result<Option<ModelsCache>> {
+        read_json(&self.data_dir.join("models_cache.json")).await
     }
 
     pub fn get_export_path(&self, filename: &str) -> PathBuf {
@@ -100,4 +287,304 @@ impl StorageService {
         export_dir.push(filename);
         export_dir
     }
-}
\ No newline at end of file
+}
+
+/// `path` with `suffix` appended to the file name, e.g. `app_state.json.bak`.
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Previous generation of a file written with `write_atomic`.
+pub fn backup_path(path: &Path) -> PathBuf {
+    sibling_path(path, "bak")
+}
+
+/// Replaces `path` so that a crash leaves either the old or the new
+/// contents: the data goes to a temporary file that is synced and then
+/// renamed over the original, which is kept as `<name>.bak`.
+pub fn write_atomic_sync(path: &Path, data: &[u8]) -> Result<()> {
+    replace_file(path, data, true)
+}
+
+/// `write_atomic_sync` without the backup, for files whose previous
+/// contents must not linger on disk. A backup left by an earlier
+/// `write_atomic_sync` is removed.
+pub fn write_atomic_sync_no_backup(path: &Path, data: &[u8]) -> Result<()> {
+    replace_file(path, data, false)
+}
+
+fn replace_file(path: &Path, data: &[u8], keep_backup: bool) -> Result<()> {
+    use std::io::Write;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    // Unique per write so concurrent writers never share a temporary file
+    static NEXT_TMP: AtomicU64 = AtomicU64::new(0);
+    let tmp_path = sibling_path(
+        path,
+        &format!("{}-{}.tmp", std::process::id(), NEXT_TMP.fetch_add(1, Ordering::Relaxed)),
+    );
+
+    let written = fs::File::create(&tmp_path).and_then(|mut file| {
+        file.write_all(data)?;
+        file.sync_all()
+    });
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e.into());
+    }
+
+    // Another writer may have moved the original away already
+    let backup = backup_path(path);
+    let moved_aside = match keep_backup {
+        true => fs::rename(path, &backup),
+        false => fs::remove_file(&backup),
+    };
+    match moved_aside {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e.into());
+        }
+        _ => {}
+    }
+    fs::rename(&tmp_path, path)?;
+
+    // Make the renames themselves durable
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        fs::File::open(parent)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+pub async fn write_atomic(path: &Path, data: Vec<u8>) -> Result<()> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || write_atomic_sync(&path, &data)).await?
+}
+
+async fn read_json_file<T: Document>(path: &Path) -> Result<Option<T>> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(json_data) => {
+            let document = T::migrate(serde_json::from_str(&json_data)?)?;
+            Ok(Some(serde_json::from_value(document)?))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Reads a file written with `write_atomic`, falling back to its backup when
+/// the file is missing or does not parse. Documents are upgraded to the
+/// current schema on the way. `None` when neither file exists.
+pub async fn read_json<T: Document>(path: &Path) -> Result<Option<T>> {
+    let primary = read_json_file(path).await;
+    if let Ok(Some(value)) = primary {
+        return Ok(Some(value));
+    }
+
+    match read_json_file(&backup_path(path)).await {
+        Ok(Some(value)) => {
+            match &primary {
+                Err(e) => eprintln!("StorageService: {} is unreadable ({}), using backup", path.display(), e),
+                _ => eprintln!("StorageService: {} is missing, using backup", path.display()),
+            }
+            Ok(Some(value))
+        }
+        _ => primary,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{UsageLedger, TRANSCRIPTION_SCHEMA_VERSION};
+    use serde_json::json;
+
+    const FIRST: &str = "6f1c1a52-3b9e-4d6a-9a41-0c5d8e2f7b10";
+    const SECOND: &str = "a2d4e6f8-1b3c-4d5e-8f9a-0b1c2d3e4f50";
+    const BROKEN: &str = "c0ffee00-0000-4000-8000-000000000001";
+
+    fn ledger(day: &str) -> Vec<u8> {
+        format!(r#"{{"days": {{"{}": {{}}}}}}"#, day).into_bytes()
+    }
+
+    fn days(ledger: Option<UsageLedger>) -> Vec<String> {
+        ledger.unwrap().days.into_keys().collect()
+    }
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn atomic_write_keeps_the_previous_generation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("usage.json");
+
+        write_atomic_sync(&path, b"first").unwrap();
+        write_atomic_sync(&path, b"second").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert_eq!(fs::read(backup_path(&path)).unwrap(), b"first");
+        assert_eq!(file_names(dir.path()), vec!["usage.json", "usage.json.bak"]);
+    }
+
+    #[test]
+    fn write_without_backup_removes_a_stale_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secrets.enc");
+
+        write_atomic_sync(&path, b"old").unwrap();
+        write_atomic_sync(&path, b"older").unwrap();
+        write_atomic_sync_no_backup(&path, b"new").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(file_names(dir.path()), vec!["secrets.enc"]);
+    }
+
+    #[test]
+    fn concurrent_writes_leave_one_complete_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app_state.json");
+        let contents: Vec<Vec<u8>> = (0..8).map(|i| vec![b'a' + i; 64 * 1024]).collect();
+
+        std::thread::scope(|scope| {
+            for data in &contents {
+                let path = &path;
+                scope.spawn(move || {
+                    for _ in 0..10 {
+                        write_atomic_sync(path, data).unwrap();
+                    }
+                });
+            }
+        });
+
+        assert!(contents.contains(&fs::read(&path).unwrap()));
+        assert_eq!(file_names(dir.path()), vec!["app_state.json", "app_state.json.bak"]);
+    }
+
+    #[tokio::test]
+    async fn read_json_falls_back_to_the_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("usage.json");
+        assert!(read_json::<UsageLedger>(&path).await.unwrap().is_none());
+
+        write_atomic_sync(&path, &ledger("2024-05-01")).unwrap();
+        write_atomic_sync(&path, &ledger("2024-05-02")).unwrap();
+        assert_eq!(days(read_json(&path).await.unwrap()), vec!["2024-05-02"]);
+
+        // Torn write
+        fs::write(&path, b"{\"days\": {").unwrap();
+        assert_eq!(days(read_json(&path).await.unwrap()), vec!["2024-05-01"]);
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(days(read_json(&path).await.unwrap()), vec!["2024-05-01"]);
+    }
+
+    #[tokio::test]
+    async fn read_json_reports_an_unreadable_file_without_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("usage.json");
+        fs::write(&path, b"not json").unwrap();
+
+        assert!(read_json::<UsageLedger>(&path).await.is_err());
+    }
+
+    fn transcription(id: &str, title: &str) -> Transcription {
+        serde_json::from_value(json!({
+            "schema_version": TRANSCRIPTION_SCHEMA_VERSION,
+            "id": id,
+            "title": title,
+            "created_at": "2024-05-01T10:00:00Z",
+            "duration": 10,
+            "chapters": [],
+            "raw_text": "Hello",
+            "status": "Completed",
+            "segments": [{
+                "id": "s0",
+                "text": "Hello",
+                "start_time": 0.0,
+                "end_time": 10.0,
+                "confidence": 0.5,
+                "words": [{ "text": "Hello", "confidence": 0.5 }]
+            }]
+        }))
+        .unwrap()
+    }
+
+    async fn titles(storage: &StorageService) -> Vec<String> {
+        let mut titles: Vec<String> = storage
+            .load_all_transcriptions()
+            .await
+            .unwrap()
+            .transcriptions
+            .into_values()
+            .map(|t| t.title)
+            .collect();
+        titles.sort();
+        titles
+    }
+
+    async fn revision_titles(storage: &StorageService, id: &str) -> Vec<String> {
+        storage.load_revisions(id).await.unwrap().into_iter().map(|r| r.transcription.title).collect()
+    }
+
+    #[tokio::test]
+    async fn json_transcriptions_move_into_sqlite_and_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().to_path_buf();
+
+        let storage = StorageService::with_data_dir(data_dir.clone()).unwrap();
+        storage.save_revision(&transcription(FIRST, "First"), RevisionSource::Live).await.unwrap();
+        storage.save_revision(&transcription(FIRST, "First, edited"), RevisionSource::ManualEdit).await.unwrap();
+        storage.save_transcription(&transcription(SECOND, "Second")).await.unwrap();
+        fs::write(data_dir.join(format!("{}.json", BROKEN)), b"{").unwrap();
+
+        let storage = storage.use_backend(StorageBackend::Sqlite).await.unwrap();
+        assert_eq!(titles(&storage).await, vec!["First, edited", "Second"]);
+        assert_eq!(revision_titles(&storage, FIRST).await, vec!["First", "First, edited"]);
+        let corrupt = storage.load_all_transcriptions().await.unwrap().corrupt;
+        assert_eq!(corrupt.len(), 1);
+        assert!(corrupt[0].path.contains(BROKEN));
+
+        // Migrated files are archived, the unreadable one stays where it was
+        let archive = data_dir.join(MIGRATED_JSON_DIR);
+        assert_eq!(
+            file_names(&archive),
+            vec![
+                format!("{}.json", FIRST),
+                format!("{}.json.bak", FIRST),
+                format!("{}.json", SECOND),
+                json::REVISIONS_DIR.to_string(),
+            ]
+        );
+        assert_eq!(file_names(&archive.join(json::REVISIONS_DIR)), vec![format!("{}.jsonl", FIRST)]);
+        assert!(data_dir.join(format!("{}.json", BROKEN)).exists());
+        assert!(!data_dir.join(format!("{}.json", FIRST)).exists());
+
+        // Reopening does not migrate the archived files again
+        storage.delete_transcription(SECOND).await.unwrap();
+        let storage = StorageService::with_data_dir(data_dir.clone())
+            .unwrap()
+            .use_backend(StorageBackend::Sqlite)
+            .await
+            .unwrap();
+        assert_eq!(titles(&storage).await, vec!["First, edited"]);
+
+        let storage = storage.use_backend(StorageBackend::Json).await.unwrap();
+        assert_eq!(titles(&storage).await, vec!["First, edited"]);
+        assert_eq!(revision_titles(&storage, FIRST).await, vec!["First", "First, edited"]);
+        assert!(!data_dir.join(SQLITE_FILE_NAME).exists());
+        assert!(file_names(&data_dir)
+            .iter()
+            .any(|name| name.starts_with(&format!("{}.migrated-", SQLITE_FILE_NAME))));
+    }
+}
@@ -0,0 +1,557 @@
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use super::migrations::Document;
+use super::{LoadedTranscriptions, Storage};
+use crate::models::{Chapter, CorruptFile, Revision, Transcription, TranscriptionSegment};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS transcriptions (
+    id TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    duration INTEGER NOT NULL,
+    document TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS transcriptions_created_at ON transcriptions (created_at);
+
+CREATE TABLE IF NOT EXISTS segments (
+    transcription_id TEXT NOT NULL REFERENCES transcriptions (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    id TEXT NOT NULL,
+    text TEXT NOT NULL,
+    start_time REAL NOT NULL,
+    end_time REAL NOT NULL,
+    confidence REAL NOT NULL,
+    words TEXT NOT NULL,
+    profile_id TEXT,
+    speaker TEXT,
+    PRIMARY KEY (transcription_id, position)
+);
+
+CREATE TABLE IF NOT EXISTS chapters (
+    transcription_id TEXT NOT NULL REFERENCES transcriptions (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    id TEXT NOT NULL,
+    title TEXT NOT NULL,
+    start_time REAL NOT NULL,
+    end_time REAL NOT NULL,
+    content TEXT NOT NULL,
+    confidence REAL NOT NULL,
+    subsections TEXT NOT NULL,
+    segment_ids TEXT NOT NULL,
+    PRIMARY KEY (transcription_id, position)
+);
+
+CREATE TABLE IF NOT EXISTS revisions (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    id TEXT NOT NULL UNIQUE,
+    transcription_id TEXT NOT NULL REFERENCES transcriptions (id) ON DELETE CASCADE,
+    created_at TEXT NOT NULL,
+    source TEXT NOT NULL,
+    document TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS revisions_transcription ON revisions (transcription_id, seq);
+";
+
+/// Embedded SQLite database. Segments and chapters get their own tables;
+/// every other field is kept in the `document` column as JSON, so fields
+/// added to `Transcription` later are stored without a schema change.
+/// Revisions are stored whole as JSON.
+pub struct SqliteStorage {
+    path: PathBuf,
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &Path) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.pragma_update(None, "foreign_keys", "ON")?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Runs `f` on the connection in the blocking thread pool.
+    async fn with_connection<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let connection = Arc::clone(&self.connection);
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().unwrap();
+            f(&mut connection)
+        })
+        .await?
+    }
+
+    fn insert(connection: &mut Connection, transcription: &Transcription) -> Result<()> {
+        let mut document = serde_json::to_value(transcription)?;
+        if let Some(fields) = document.as_object_mut() {
+            fields.remove("segments");
+            fields.remove("chapters");
+        }
+
+        let tx = connection.transaction()?;
+        tx.execute(
+            "INSERT INTO transcriptions (id, title, created_at, duration, document)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (id) DO UPDATE SET
+                 title = excluded.title,
+                 created_at = excluded.created_at,
+                 duration = excluded.duration,
+                 document = excluded.document",
+            params![
+                transcription.id,
+                transcription.title,
+                transcription.created_at.to_rfc3339(),
+                transcription.duration as i64,
+                document.to_string(),
+            ],
+        )?;
+
+        tx.execute("DELETE FROM segments WHERE transcription_id = ?1", params![transcription.id])?;
+        tx.execute("DELETE FROM chapters WHERE transcription_id = ?1", params![transcription.id])?;
+
+        {
+            let mut insert_segment = tx.prepare(
+                "INSERT INTO segments (transcription_id, position, id, text, start_time, end_time, confidence, words, profile_id, speaker)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            )?;
+            for (position, segment) in transcription.segments.iter().enumerate() {
+                insert_segment.execute(params![
+                    transcription.id,
+                    position as i64,
+                    segment.id,
+                    segment.text,
+                    segment.start_time,
+                    segment.end_time,
+                    segment.confidence as f64,
+                    serde_json::to_string(&segment.words)?,
+                    segment.profile_id,
+                    segment.speaker,
+                ])?;
+            }
+
+            let mut insert_chapter = tx.prepare(
+                "INSERT INTO chapters (transcription_id, position, id, title, start_time, end_time, content, confidence, subsections, segment_ids)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            )?;
+            for (position, chapter) in transcription.chapters.iter().enumerate() {
+                insert_chapter.execute(params![
+                    transcription.id,
+                    position as i64,
+                    chapter.id,
+                    chapter.title,
+                    chapter.start_time,
+                    chapter.end_time,
+                    chapter.content,
+                    chapter.confidence as f64,
+                    serde_json::to_string(&chapter.subsections)?,
+                    serde_json::to_string(&chapter.segment_ids)?,
+                ])?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Segment row and its transcription id. A `words` value that does not
+    /// parse is an error for that transcription rather than the whole query.
+    fn segment_from_row(row: &rusqlite::Row) -> rusqlite::Result<(String, Result<TranscriptionSegment>)> {
+        let words: String = row.get(6)?;
+        let mut segment = TranscriptionSegment {
+            id: row.get(1)?,
+            text: row.get(2)?,
+            start_time: row.get(3)?,
+            end_time: row.get(4)?,
+            confidence: row.get::<_, f64>(5)? as f32,
+            words: Vec::new(),
+            profile_id: row.get(7)?,
+            speaker: row.get(8)?,
+        };
+
+        let parsed = match serde_json::from_str(&words) {
+            Ok(words) => {
+                segment.words = words;
+                Ok(segment)
+            }
+            Err(e) => Err(anyhow!("segment {} has invalid words: {}", segment.id, e)),
+        };
+        Ok((row.get(0)?, parsed))
+    }
+
+    /// Chapter row and its transcription id; see `segment_from_row`.
+    fn chapter_from_row(row: &rusqlite::Row) -> rusqlite::Result<(String, Result<Chapter>)> {
+        let subsections: String = row.get(7)?;
+        let segment_ids: String = row.get(8)?;
+        let mut chapter = Chapter {
+            id: row.get(1)?,
+            title: row.get(2)?,
+            start_time: row.get(3)?,
+            end_time: row.get(4)?,
+            content: row.get(5)?,
+            confidence: row.get::<_, f64>(6)? as f32,
+            subsections: Vec::new(),
+            segment_ids: Vec::new(),
+        };
+
+        let parsed = serde_json::from_str(&subsections)
+            .map_err(|e| anyhow!("chapter {} has invalid subsections: {}", chapter.id, e))
+            .and_then(|subsections| {
+                let segment_ids = serde_json::from_str(&segment_ids)
+                    .map_err(|e| anyhow!("chapter {} has invalid segment_ids: {}", chapter.id, e))?;
+                chapter.subsections = subsections;
+                chapter.segment_ids = segment_ids;
+                Ok(chapter)
+            });
+        Ok((row.get(0)?, parsed))
+    }
+
+    const SEGMENT_COLUMNS: &'static str =
+        "transcription_id, id, text, start_time, end_time, confidence, words, profile_id, speaker";
+    const CHAPTER_COLUMNS: &'static str =
+        "transcription_id, id, title, start_time, end_time, content, confidence, subsections, segment_ids";
+
+    fn assemble(document: &str, segments: Vec<TranscriptionSegment>, chapters: Vec<Chapter>) -> Result<Transcription> {
+        let mut document: Value = serde_json::from_str(document)?;
+        let fields = document
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("transcription document is not an object"))?;
+        fields.insert("segments".to_string(), serde_json::to_value(segments)?);
+        fields.insert("chapters".to_string(), serde_json::to_value(chapters)?);
+        Ok(serde_json::from_value(Transcription::migrate(document)?)?)
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn save_transcription(&self, transcription: &Transcription) -> Result<()> {
+        let transcription = transcription.clone();
+        self.with_connection(move |connection| Self::insert(connection, &transcription)).await
+    }
+
+    async fn load_transcription(&self, id: &str) -> Result<Transcription> {
+        let id = id.to_string();
+        self.with_connection(move |connection| {
+            let document: String = connection
+                .query_row("SELECT document FROM transcriptions WHERE id = ?1", params![id], |row| row.get(0))
+                .optional()?
+                .ok_or_else(|| anyhow!("Transcription {} not found", id))?;
+
+            let segments = connection
+                .prepare(&format!(
+                    "SELECT {} FROM segments WHERE transcription_id = ?1 ORDER BY position",
+                    Self::SEGMENT_COLUMNS
+                ))?
+                .query_map(params![id], Self::segment_from_row)?
+                .map(|row| row?.1)
+                .collect::<Result<Vec<_>>>()?;
+
+            let chapters = connection
+                .prepare(&format!(
+                    "SELECT {} FROM chapters WHERE transcription_id = ?1 ORDER BY position",
+                    Self::CHAPTER_COLUMNS
+                ))?
+                .query_map(params![id], Self::chapter_from_row)?
+                .map(|row| row?.1)
+                .collect::<Result<Vec<_>>>()?;
+
+            Self::assemble(&document, segments, chapters)
+        })
+        .await
+    }
+
+    /// Three queries regardless of the number of transcriptions. Rows whose
+    /// document, segments or chapters no longer parse are reported as corrupt.
+    async fn load_all_transcriptions(&self) -> Result<LoadedTranscriptions> {
+        let path = self.path.to_string_lossy().to_string();
+        self.with_connection(move |connection| {
+            let mut invalid: HashMap<String, anyhow::Error> = HashMap::new();
+
+            let mut segments: HashMap<String, Vec<TranscriptionSegment>> = HashMap::new();
+            let mut statement = connection.prepare(&format!(
+                "SELECT {} FROM segments ORDER BY transcription_id, position",
+                Self::SEGMENT_COLUMNS
+            ))?;
+            for row in statement.query_map([], Self::segment_from_row)? {
+                match row? {
+                    (transcription_id, Ok(segment)) => segments.entry(transcription_id).or_default().push(segment),
+                    (transcription_id, Err(e)) => {
+                        invalid.entry(transcription_id).or_insert(e);
+                    }
+                }
+            }
+
+            let mut chapters: HashMap<String, Vec<Chapter>> = HashMap::new();
+            let mut statement = connection.prepare(&format!(
+                "SELECT {} FROM chapters ORDER BY transcription_id, position",
+                Self::CHAPTER_COLUMNS
+            ))?;
+            for row in statement.query_map([], Self::chapter_from_row)? {
+                match row? {
+                    (transcription_id, Ok(chapter)) => chapters.entry(transcription_id).or_default().push(chapter),
+                    (transcription_id, Err(e)) => {
+                        invalid.entry(transcription_id).or_insert(e);
+                    }
+                }
+            }
+
+            let mut loaded = LoadedTranscriptions::default();
+            let mut statement = connection.prepare("SELECT id, document FROM transcriptions")?;
+            let rows = statement.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+
+            for row in rows {
+                let (id, document) = row?;
+                let assembled = match invalid.remove(&id) {
+                    Some(e) => Err(e),
+                    None => Self::assemble(
+                        &document,
+                        segments.remove(&id).unwrap_or_default(),
+                        chapters.remove(&id).unwrap_or_default(),
+                    ),
+                };
+
+                match assembled {
+                    Ok(transcription) => {
+                        loaded.transcriptions.insert(id, transcription);
+                    }
+                    Err(e) => loaded.corrupt.push(CorruptFile {
+                        path: format!("{}#{}", path, id),
+                        error: e.to_string(),
+                    }),
+                }
+            }
+
+            Ok(loaded)
+        })
+        .await
+    }
+
+    async fn delete_transcription(&self, id: &str) -> Result<()> {
+        let id = id.to_string();
+        self.with_connection(move |connection| {
+            connection.execute("DELETE FROM transcriptions WHERE id = ?1", params![id])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn append_revision(&self, revision: &Revision, keep: usize) -> Result<()> {
+        let document = serde_json::to_string(revision)?;
+        let source = serde_json::to_value(revision.source)?.as_str().unwrap_or_default().to_string();
+        let revision = revision.clone();
+
+        self.with_connection(move |connection| {
+            let tx = connection.transaction()?;
+            tx.execute(
+                "INSERT OR IGNORE INTO revisions (id, transcription_id, created_at, source, document)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    revision.id,
+                    revision.transcription_id,
+                    revision.created_at.to_rfc3339(),
+                    source,
+                    document,
+                ],
+            )?;
+            tx.execute(
+                "DELETE FROM revisions WHERE transcription_id = ?1 AND seq NOT IN (
+                     SELECT seq FROM revisions WHERE transcription_id = ?1 ORDER BY seq DESC LIMIT ?2
+                 )",
+                params![revision.transcription_id, keep.max(1) as i64],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn load_revisions(&self, transcription_id: &str) -> Result<Vec<Revision>> {
+        let transcription_id = transcription_id.to_string();
+        self.with_connection(move |connection| {
+            let mut statement =
+                connection.prepare("SELECT document FROM revisions WHERE transcription_id = ?1 ORDER BY seq")?;
+            let documents = statement
+                .query_map(params![transcription_id], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+
+            documents
+                .iter()
+                .map(|document| Ok(serde_json::from_value(Revision::migrate(serde_json::from_str(document)?)?)?))
+                .collect()
+        })
+        .await
+    }
+
+    async fn has_revisions(&self, transcription_id: &str) -> Result<bool> {
+        let transcription_id = transcription_id.to_string();
+        self.with_connection(move |connection| {
+            Ok(connection.query_row(
+                "SELECT EXISTS (SELECT 1 FROM revisions WHERE transcription_id = ?1)",
+                params![transcription_id],
+                |row| row.get(0),
+            )?)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{RevisionSource, TRANSCRIPTION_SCHEMA_VERSION};
+    use serde_json::json;
+
+    fn transcription(id: &str, segments: usize) -> Transcription {
+        let segments: Vec<Value> = (0..segments)
+            .map(|i| json!({
+                "id": format!("{}-s{}", id, i),
+                "text": format!("Segment {}", i),
+                "start_time": i as f64 * 5.0,
+                "end_time": (i + 1) as f64 * 5.0,
+                "confidence": 0.75,
+                "words": [{ "text": "Segment", "confidence": 0.5 }],
+                "profile_id": "default",
+                "speaker": "Anna"
+            }))
+            .collect();
+
+        serde_json::from_value(json!({
+            "schema_version": TRANSCRIPTION_SCHEMA_VERSION,
+            "id": id,
+            "title": format!("Meeting {}", id),
+            "created_at": "2024-05-01T10:00:00Z",
+            "duration": 60,
+            "chapters": [{
+                "id": format!("{}-c0", id),
+                "title": "Intro",
+                "start_time": 0.0,
+                "end_time": 60.0,
+                "content": "Segment 0",
+                "confidence": 0.5,
+                "subsections": [{
+                    "id": format!("{}-c0-s0", id),
+                    "title": "Hello",
+                    "content": "Segment 0",
+                    "start_time": 0.0,
+                    "end_time": 5.0,
+                    "confidence": 0.5,
+                    "segment_ids": [format!("{}-s0", id)]
+                }],
+                "segment_ids": [format!("{}-s0", id)]
+            }],
+            "raw_text": "Segment 0",
+            "status": "Completed",
+            "segments": segments,
+            "tags": ["Team"],
+            "folder": "Projects",
+            "metadata": { "room": "B2" }
+        }))
+        .unwrap()
+    }
+
+    fn open(dir: &tempfile::TempDir) -> SqliteStorage {
+        SqliteStorage::open(&dir.path().join("transcriptions.db")).unwrap()
+    }
+
+    fn rows(storage: &SqliteStorage, table: &str) -> i64 {
+        let connection = storage.connection.lock().unwrap();
+        connection.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0)).unwrap()
+    }
+
+    fn corrupt(storage: &SqliteStorage, sql: &str) {
+        storage.connection.lock().unwrap().execute(sql, []).unwrap();
+    }
+
+    #[tokio::test]
+    async fn transcriptions_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = open(&dir);
+        let original = transcription("a", 3);
+
+        storage.save_transcription(&original).await.unwrap();
+        storage.save_transcription(&transcription("b", 1)).await.unwrap();
+        let loaded = storage.load_transcription("a").await.unwrap();
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&original).unwrap());
+
+        // Saving again replaces the rows instead of adding to them
+        storage.save_transcription(&transcription("a", 2)).await.unwrap();
+        assert_eq!(storage.load_transcription("a").await.unwrap().segments.len(), 2);
+        assert_eq!(rows(&storage, "segments"), 3);
+
+        let all = storage.load_all_transcriptions().await.unwrap();
+        assert_eq!(all.transcriptions.len(), 2);
+        assert!(all.corrupt.is_empty());
+        assert_eq!(all.transcriptions["b"].chapters[0].subsections[0].title, "Hello");
+
+        storage.delete_transcription("a").await.unwrap();
+        assert!(storage.load_transcription("a").await.is_err());
+        assert_eq!(storage.load_all_transcriptions().await.unwrap().transcriptions.len(), 1);
+        assert_eq!((rows(&storage, "segments"), rows(&storage, "chapters")), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn revisions_are_capped_and_deleted_with_the_transcription() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = open(&dir);
+        let mut t = transcription("a", 1);
+        storage.save_transcription(&t).await.unwrap();
+        assert!(!storage.has_revisions("a").await.unwrap());
+
+        let mut last = None;
+        for i in 0..5 {
+            t.title = format!("Version {}", i);
+            let revision = Revision::new(&t, RevisionSource::ManualEdit);
+            storage.append_revision(&revision, 3).await.unwrap();
+            last = Some(revision);
+        }
+        // Appending the same revision again changes nothing
+        storage.append_revision(last.as_ref().unwrap(), 3).await.unwrap();
+
+        let titles: Vec<String> = storage
+            .load_revisions("a")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.transcription.title)
+            .collect();
+        assert_eq!(titles, vec!["Version 2", "Version 3", "Version 4"]);
+        assert!(storage.has_revisions("a").await.unwrap());
+
+        storage.delete_transcription("a").await.unwrap();
+        assert!(!storage.has_revisions("a").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn invalid_json_columns_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = open(&dir);
+        for id in ["a", "b", "c"] {
+            storage.save_transcription(&transcription(id, 1)).await.unwrap();
+        }
+        corrupt(&storage, "UPDATE segments SET words = '[{' WHERE transcription_id = 'a'");
+        corrupt(&storage, "UPDATE chapters SET subsections = 'null' WHERE transcription_id = 'b'");
+
+        let error = storage.load_transcription("a").await.unwrap_err().to_string();
+        assert!(error.starts_with("segment a-s0 has invalid words"), "{}", error);
+        let error = storage.load_transcription("b").await.unwrap_err().to_string();
+        assert!(error.starts_with("chapter b-c0 has invalid subsections"), "{}", error);
+
+        let all = storage.load_all_transcriptions().await.unwrap();
+        assert_eq!(all.transcriptions.keys().collect::<Vec<_>>(), vec!["c"]);
+        let mut corrupt: Vec<&str> = all.corrupt.iter().map(|c| c.path.rsplit('#').next().unwrap()).collect();
+        corrupt.sort();
+        assert_eq!(corrupt, vec!["a", "b"]);
+    }
+}
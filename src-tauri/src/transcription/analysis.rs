@@ -0,0 +1,1466 @@
+use futures::stream::{self, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::ops::Range;
+use super::{TranscriptionService, FALLBACK_CONFIDENCE};
+use crate::models::{AnalysisKind, AnalysisProgress, AnalysisStage, Chapter, MeetingNotes, Subsection, Transcription, TranscriptionSegment};
+
+/// First request plus repair retries for one structured response.
+const MAX_ANALYSIS_ATTEMPTS: usize = 3;
+
+/// Transcript text sent in one analysis prompt. Longer transcripts are
+/// analysed in overlapping windows whose sections are then merged.
+pub const ANALYSIS_WINDOW_CHARS: usize = 48_000;
+/// Text repeated from the end of one window at the start of the next.
+pub const ANALYSIS_WINDOW_OVERLAP_CHARS: usize = 4_000;
+/// Target length of the pseudo-segments cut from transcriptions that have
+/// no stored segments.
+const TEXT_SEGMENT_CHARS: usize = 400;
+
+/// Why a structured analysis produced no usable result.
+#[derive(Debug)]
+pub enum AnalysisError {
+    /// The request itself failed after provider failover.
+    Provider(anyhow::Error),
+    /// The model returned no text, e.g. because the response was blocked.
+    EmptyResponse { finish_reason: Option<String> },
+    /// The text did not parse as JSON matching the schema.
+    InvalidJson(String),
+    /// The JSON parsed but failed validation.
+    Invalid(Vec<String>),
+}
+
+impl std::fmt::Display for AnalysisError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnalysisError::Provider(e) => write!(f, "Analysis request failed: {}", e),
+            AnalysisError::EmptyResponse { finish_reason: Some(reason) } => {
+                write!(f, "Analysis returned no content (finish reason: {})", reason)
+            }
+            AnalysisError::EmptyResponse { finish_reason: None } => write!(f, "Analysis returned no content"),
+            AnalysisError::InvalidJson(e) => write!(f, "Analysis returned malformed JSON: {}", e),
+            AnalysisError::Invalid(issues) => write!(f, "Analysis result is invalid: {}", issues.join("; ")),
+        }
+    }
+}
+
+impl std::error::Error for AnalysisError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AnalysisError::Provider(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// A validated structured response with the confidence of the candidate.
+pub struct StructuredResponse<T> {
+    pub value: T,
+    pub confidence: f32,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChapterDraft {
+    title: String,
+    content: String,
+    start_time: f64,
+}
+
+fn chapter_schema() -> Value {
+    json!({
+        "type": "ARRAY",
+        "items": {
+            "type": "OBJECT",
+            "properties": {
+                "title": { "type": "STRING", "description": "Short chapter title" },
+                "content": { "type": "STRING", "description": "Transcription text belonging to the chapter" },
+                "start_time": { "type": "NUMBER", "description": "Chapter start in seconds from the beginning" }
+            },
+            "required": ["title", "content", "start_time"],
+            "propertyOrdering": ["title", "content", "start_time"]
+        }
+    })
+}
+
+fn validate_chapters(chapters: &[ChapterDraft]) -> Vec<String> {
+    let mut issues = Vec::new();
+
+    if chapters.is_empty() {
+        issues.push("the array must contain at least one chapter".to_string());
+    }
+
+    let mut previous_start = 0.0;
+    for (i, chapter) in chapters.iter().enumerate() {
+        let number = i + 1;
+        if chapter.title.trim().is_empty() {
+            issues.push(format!("chapter {} has an empty title", number));
+        }
+        if chapter.content.trim().is_empty() {
+            issues.push(format!("chapter {} has empty content", number));
+        }
+        if !chapter.start_time.is_finite() || chapter.start_time < 0.0 {
+            issues.push(format!("chapter {} has an invalid start_time {}", number, chapter.start_time));
+        } else if chapter.start_time < previous_start {
+            issues.push(format!(
+                "chapter {} starts at {} which is before the previous chapter ({})",
+                number, chapter.start_time, previous_start
+            ));
+        } else {
+            previous_start = chapter.start_time;
+        }
+    }
+
+    issues
+}
+
+/// A chapter as returned for segment-based analysis. Only start segments are
+/// requested; each chapter runs until the next one starts, so the result
+/// covers the transcript without gaps or overlaps by construction.
+#[derive(Debug, Deserialize)]
+struct SegmentChapterDraft {
+    title: String,
+    first_segment: usize,
+    #[serde(default)]
+    subsections: Vec<SubsectionDraft>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubsectionDraft {
+    title: String,
+    first_segment: usize,
+}
+
+fn segment_chapter_schema() -> Value {
+    json!({
+        "type": "ARRAY",
+        "items": {
+            "type": "OBJECT",
+            "properties": {
+                "title": { "type": "STRING", "description": "Short chapter title" },
+                "first_segment": { "type": "INTEGER", "description": "Number of the segment the chapter starts with" },
+                "subsections": {
+                    "type": "ARRAY",
+                    "items": {
+                        "type": "OBJECT",
+                        "properties": {
+                            "title": { "type": "STRING", "description": "Short subsection title" },
+                            "first_segment": { "type": "INTEGER", "description": "Number of the segment the subsection starts with" }
+                        },
+                        "required": ["title", "first_segment"],
+                        "propertyOrdering": ["title", "first_segment"]
+                    }
+                }
+            },
+            "required": ["title", "first_segment", "subsections"],
+            "propertyOrdering": ["title", "first_segment", "subsections"]
+        }
+    })
+}
+
+fn validate_segment_chapters(chapters: &[SegmentChapterDraft], segment_count: usize) -> Vec<String> {
+    let mut issues = Vec::new();
+
+    match chapters.first() {
+        None => issues.push("the array must contain at least one chapter".to_string()),
+        Some(first) if first.first_segment != 0 => {
+            issues.push(format!("the first chapter must start at segment 0, not {}", first.first_segment))
+        }
+        Some(_) => {}
+    }
+
+    for (i, chapter) in chapters.iter().enumerate() {
+        let number = i + 1;
+        let end = chapters.get(i + 1).map_or(segment_count, |next| next.first_segment);
+
+        if chapter.title.trim().is_empty() {
+            issues.push(format!("chapter {} has an empty title", number));
+        }
+        if chapter.first_segment >= segment_count {
+            issues.push(format!(
+                "chapter {} starts at segment {} but the last segment is {}",
+                number, chapter.first_segment, segment_count.saturating_sub(1)
+            ));
+        }
+        if i + 1 < chapters.len() && end <= chapter.first_segment {
+            issues.push(format!("chapter {} must start before chapter {}", number, number + 1));
+        }
+
+        let mut previous = None;
+        for (j, subsection) in chapter.subsections.iter().enumerate() {
+            if subsection.title.trim().is_empty() {
+                issues.push(format!("subsection {} of chapter {} has an empty title", j + 1, number));
+            }
+            if subsection.first_segment < chapter.first_segment || subsection.first_segment >= end {
+                issues.push(format!(
+                    "subsection {} of chapter {} starts at segment {} outside the chapter's segments {}-{}",
+                    j + 1, number, subsection.first_segment, chapter.first_segment, end.saturating_sub(1)
+                ));
+            }
+            if previous.is_some_and(|p| subsection.first_segment <= p) {
+                issues.push(format!("subsections of chapter {} must be in increasing segment order", number));
+            }
+            previous = Some(subsection.first_segment);
+        }
+    }
+
+    issues
+}
+
+pub fn join_text(segments: &[TranscriptionSegment]) -> String {
+    segments
+        .iter()
+        .map(|s| s.text.trim())
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Turns validated drafts into chapters whose times come from the segments.
+/// A chapter ends where the next one starts; the last one ends with the
+/// last segment.
+fn chapters_from_segments(
+    drafts: Vec<SegmentChapterDraft>,
+    segments: &[TranscriptionSegment],
+    confidence: f32,
+) -> Vec<Chapter> {
+    let starts: Vec<usize> = drafts.iter().map(|d| d.first_segment).collect();
+    let start_time = |index: usize| segments[index].start_time;
+    let end_of = |index: usize| {
+        if index < segments.len() {
+            start_time(index)
+        } else {
+            segments.last().map_or(0.0, |s| s.end_time)
+        }
+    };
+
+    drafts
+        .into_iter()
+        .enumerate()
+        .map(|(i, draft)| {
+            let end = starts.get(i + 1).copied().unwrap_or(segments.len());
+            let range = &segments[draft.first_segment..end];
+
+            // The first subsection always opens the chapter
+            let mut sections = draft.subsections;
+            match sections.first_mut() {
+                Some(first) => first.first_segment = draft.first_segment,
+                None => sections.push(SubsectionDraft {
+                    title: draft.title.clone(),
+                    first_segment: draft.first_segment,
+                }),
+            }
+
+            let section_starts: Vec<usize> = sections.iter().map(|s| s.first_segment).collect();
+            let subsections = sections
+                .into_iter()
+                .enumerate()
+                .map(|(j, section)| {
+                    let section_end = section_starts.get(j + 1).copied().unwrap_or(end);
+                    let section_range = &segments[section.first_segment..section_end];
+                    Subsection {
+                        id: uuid::Uuid::new_v4().to_string(),
+                        title: section.title.trim().to_string(),
+                        content: join_text(section_range),
+                        start_time: start_time(section.first_segment),
+                        end_time: end_of(section_end).max(start_time(section.first_segment)),
+                        confidence,
+                        segment_ids: section_range.iter().map(|s| s.id.clone()).collect(),
+                    }
+                })
+                .collect();
+
+            Chapter {
+                id: uuid::Uuid::new_v4().to_string(),
+                title: draft.title.trim().to_string(),
+                start_time: start_time(draft.first_segment),
+                end_time: end_of(end).max(start_time(draft.first_segment)),
+                content: join_text(range),
+                confidence,
+                subsections,
+                segment_ids: range.iter().map(|s| s.id.clone()).collect(),
+            }
+        })
+        .collect()
+}
+
+/// A section found inside one analysis window, identified by the global
+/// number of the segment it starts with.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LocalSection {
+    pub title: String,
+    pub first_segment: usize,
+}
+
+fn section_schema() -> Value {
+    json!({
+        "type": "ARRAY",
+        "items": {
+            "type": "OBJECT",
+            "properties": {
+                "title": { "type": "STRING", "description": "Short section title" },
+                "first_segment": { "type": "INTEGER", "description": "Number of the segment the section starts with" }
+            },
+            "required": ["title", "first_segment"],
+            "propertyOrdering": ["title", "first_segment"]
+        }
+    })
+}
+
+fn validate_sections(sections: &[LocalSection], window: &Range<usize>) -> Vec<String> {
+    let mut issues = Vec::new();
+
+    match sections.first() {
+        None => issues.push("the array must contain at least one section".to_string()),
+        Some(first) if first.first_segment != window.start => issues.push(format!(
+            "the first section must start at segment {}, not {}",
+            window.start, first.first_segment
+        )),
+        Some(_) => {}
+    }
+
+    let mut previous = None;
+    for (i, section) in sections.iter().enumerate() {
+        if section.title.trim().is_empty() {
+            issues.push(format!("section {} has an empty title", i + 1));
+        }
+        if !window.contains(&section.first_segment) {
+            issues.push(format!(
+                "section {} starts at segment {} outside segments {}-{}",
+                i + 1, section.first_segment, window.start, window.end - 1
+            ));
+        }
+        if previous.is_some_and(|p| section.first_segment <= p) {
+            issues.push("sections must be in increasing segment order".to_string());
+        }
+        previous = Some(section.first_segment);
+    }
+
+    issues
+}
+
+#[derive(Debug, Deserialize)]
+struct ChapterGroupDraft {
+    title: String,
+    first_section: usize,
+}
+
+fn chapter_group_schema() -> Value {
+    json!({
+        "type": "ARRAY",
+        "items": {
+            "type": "OBJECT",
+            "properties": {
+                "title": { "type": "STRING", "description": "Short chapter title" },
+                "first_section": { "type": "INTEGER", "description": "Number of the section the chapter starts with" }
+            },
+            "required": ["title", "first_section"],
+            "propertyOrdering": ["title", "first_section"]
+        }
+    })
+}
+
+fn validate_chapter_groups(chapters: &[ChapterGroupDraft], section_count: usize) -> Vec<String> {
+    let mut issues = Vec::new();
+
+    match chapters.first() {
+        None => issues.push("the array must contain at least one chapter".to_string()),
+        Some(first) if first.first_section != 0 => {
+            issues.push(format!("the first chapter must start at section 0, not {}", first.first_section))
+        }
+        Some(_) => {}
+    }
+
+    let mut previous = None;
+    for (i, chapter) in chapters.iter().enumerate() {
+        if chapter.title.trim().is_empty() {
+            issues.push(format!("chapter {} has an empty title", i + 1));
+        }
+        if chapter.first_section >= section_count {
+            issues.push(format!(
+                "chapter {} starts at section {} but the last section is {}",
+                i + 1, chapter.first_section, section_count.saturating_sub(1)
+            ));
+        }
+        if previous.is_some_and(|p| chapter.first_section <= p) {
+            issues.push("chapters must be in increasing section order".to_string());
+        }
+        previous = Some(chapter.first_section);
+    }
+
+    issues
+}
+
+fn segment_listing(segments: &[TranscriptionSegment], range: Range<usize>) -> String {
+    segments[range.clone()]
+        .iter()
+        .zip(range)
+        .map(|(s, i)| format!("[{}] ({:.1}s-{:.1}s) {}", i, s.start_time, s.end_time, s.text.trim()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Splits the segments into windows of about `window_chars` characters,
+/// each starting `overlap_chars` before the previous one ended. Every
+/// window holds at least one segment and starts after the previous one.
+pub fn analysis_windows(segments: &[TranscriptionSegment], window_chars: usize, overlap_chars: usize) -> Vec<Range<usize>> {
+    let mut windows = Vec::new();
+    let mut start = 0;
+
+    while start < segments.len() {
+        let mut end = start;
+        let mut chars = 0;
+        while end < segments.len() && (end == start || chars + segments[end].text.len() <= window_chars) {
+            chars += segments[end].text.len();
+            end += 1;
+        }
+        windows.push(start..end);
+
+        if end == segments.len() {
+            break;
+        }
+
+        let mut next = end;
+        let mut overlap = 0;
+        while next > start + 1 && overlap < overlap_chars {
+            next -= 1;
+            overlap += segments[next].text.len();
+        }
+        start = next;
+    }
+
+    windows
+}
+
+/// Combines the sections found in each window into one ordered list.
+///
+/// Each window owns the segments from the middle of its overlap with the
+/// previous window to the middle of its overlap with the next one, and only
+/// section starts inside its own range are kept. A section that begins in
+/// one window's range and continues into the next keeps a single entry;
+/// consecutive sections with the same title are treated as one.
+pub fn merge_window_sections(windows: &[Range<usize>], results: Vec<Vec<LocalSection>>) -> Vec<LocalSection> {
+    let owned_starts: Vec<usize> = windows
+        .iter()
+        .enumerate()
+        .map(|(i, window)| match i {
+            0 => 0,
+            _ => (window.start + windows[i - 1].end) / 2,
+        })
+        .collect();
+
+    let mut merged: Vec<LocalSection> = Vec::new();
+    for (i, sections) in results.into_iter().enumerate() {
+        let owned = owned_starts[i]..owned_starts.get(i + 1).copied().unwrap_or(usize::MAX);
+
+        for section in sections.into_iter().filter(|s| owned.contains(&s.first_segment)) {
+            let continues = merged
+                .last()
+                .is_some_and(|last| normalized_title(&last.title) == normalized_title(&section.title));
+            if !continues {
+                merged.push(section);
+            }
+        }
+    }
+
+    if let Some(first) = merged.first_mut() {
+        first.first_segment = 0;
+    }
+
+    merged
+}
+
+fn normalized_title(title: &str) -> String {
+    title
+        .chars()
+        .filter(|c| c.is_alphanumeric() || c.is_whitespace())
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Cuts plain text into sentence-aligned pieces with times interpolated
+/// over the recording, so transcriptions without stored segments can be
+/// analysed in windows too.
+fn text_segments(text: &str, duration: f64) -> Vec<TranscriptionSegment> {
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+
+    for word in text.split_whitespace() {
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+
+        let sentence_end = word.ends_with(['.', '?', '!']);
+        if current.len() >= TEXT_SEGMENT_CHARS * 2 || (sentence_end && current.len() >= TEXT_SEGMENT_CHARS) {
+            pieces.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+
+    let total_chars: usize = pieces.iter().map(|p| p.len()).sum::<usize>().max(1);
+    let mut offset = 0;
+
+    pieces
+        .into_iter()
+        .enumerate()
+        .map(|(i, text)| {
+            let start_time = duration * offset as f64 / total_chars as f64;
+            offset += text.len();
+            TranscriptionSegment {
+                id: format!("text-{}", i),
+                start_time,
+                end_time: duration * offset as f64 / total_chars as f64,
+                confidence: FALLBACK_CONFIDENCE,
+                words: Vec::new(),
+                profile_id: None,
+                speaker: None,
+                text,
+            }
+        })
+        .collect()
+}
+
+/// Non-empty segments in time order, or pseudo-segments cut from the plain
+/// text for transcriptions recorded before segments were stored.
+pub fn analysis_segments(transcription: &Transcription) -> Vec<TranscriptionSegment> {
+    let mut segments: Vec<TranscriptionSegment> = transcription
+        .segments
+        .iter()
+        .filter(|s| !s.text.trim().is_empty())
+        .cloned()
+        .collect();
+
+    if segments.is_empty() {
+        return text_segments(&transcription.raw_text, transcription.duration as f64);
+    }
+
+    segments.sort_by(|a, b| a.start_time.total_cmp(&b.start_time));
+    segments
+}
+
+fn string_list_schema(description: &str) -> Value {
+    json!({ "type": "ARRAY", "items": { "type": "STRING" }, "description": description })
+}
+
+/// Schema with one required property per requested analysis.
+fn notes_schema(kinds: &[AnalysisKind]) -> Value {
+    let mut properties = serde_json::Map::new();
+    let mut required = Vec::new();
+
+    for kind in kinds {
+        let (name, schema) = match kind {
+            AnalysisKind::ExecutiveSummary => (
+                "executive_summary",
+                json!({ "type": "STRING", "description": "One concise paragraph for someone who was not there" }),
+            ),
+            AnalysisKind::BulletSummary => ("bullet_summary", string_list_schema("Key points as short bullet items")),
+            AnalysisKind::ActionItems => (
+                "action_items",
+                json!({
+                    "type": "ARRAY",
+                    "description": "Tasks someone committed to or was assigned",
+                    "items": {
+                        "type": "OBJECT",
+                        "properties": {
+                            "description": { "type": "STRING" },
+                            "owner": { "type": "STRING", "nullable": true, "description": "Person responsible, if stated" },
+                            "due_date": { "type": "STRING", "nullable": true, "description": "Due date as stated, if any" }
+                        },
+                        "required": ["description"],
+                        "propertyOrdering": ["description", "owner", "due_date"]
+                    }
+                }),
+            ),
+            AnalysisKind::Decisions => ("decisions", string_list_schema("Decisions that were made")),
+            AnalysisKind::OpenQuestions => {
+                ("open_questions", string_list_schema("Questions raised but left unresolved"))
+            }
+        };
+        properties.insert(name.to_string(), schema);
+        required.push(name);
+    }
+
+    json!({
+        "type": "OBJECT",
+        "properties": properties,
+        "required": required,
+        "propertyOrdering": required
+    })
+}
+
+fn validate_notes(notes: &MeetingNotes, kinds: &[AnalysisKind]) -> Vec<String> {
+    let mut issues: Vec<String> = kinds
+        .iter()
+        .filter(|kind| !notes.has(**kind))
+        .map(|kind| format!("{:?} is missing", kind))
+        .collect();
+
+    if notes.executive_summary.as_ref().is_some_and(|s| s.trim().is_empty()) {
+        issues.push("executive_summary is empty".to_string());
+    }
+    if let Some(items) = &notes.action_items {
+        if items.iter().any(|item| item.description.trim().is_empty()) {
+            issues.push("every action item needs a description".to_string());
+        }
+    }
+
+    issues
+}
+
+/// Drops blank entries and turns blank owners and due dates into `None`.
+fn tidy_notes(notes: &mut MeetingNotes) {
+    let tidy_list = |list: &mut Option<Vec<String>>| {
+        if let Some(items) = list {
+            items.retain(|item| !item.trim().is_empty());
+        }
+    };
+    tidy_list(&mut notes.bullet_summary);
+    tidy_list(&mut notes.decisions);
+    tidy_list(&mut notes.open_questions);
+
+    let blank_to_none = |value: &mut Option<String>| {
+        if value.as_ref().is_some_and(|v| v.trim().is_empty()) {
+            *value = None;
+        }
+    };
+    if let Some(items) = &mut notes.action_items {
+        for item in items.iter_mut() {
+            blank_to_none(&mut item.owner);
+            blank_to_none(&mut item.due_date);
+        }
+    }
+}
+
+fn notes_instructions(kinds: &[AnalysisKind]) -> String {
+    kinds
+        .iter()
+        .map(|kind| match kind {
+            AnalysisKind::ExecutiveSummary => "- an executive summary",
+            AnalysisKind::BulletSummary => "- a bullet summary of the key points",
+            AnalysisKind::ActionItems => "- action items, with owner and due date when they are stated",
+            AnalysisKind::Decisions => "- decisions that were made",
+            AnalysisKind::OpenQuestions => "- open questions that were not resolved",
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn repair_prompt(problem: &str) -> String {
+    format!(
+        "Your previous response could not be used: {}.
+        Return the complete corrected JSON only, following the same schema.",
+        problem
+    )
+}
+
+/// Makes text-based chapters contiguous: the first starts at zero and each
+/// ends where the next begins, the last at the end of the recording.
+fn close_chapter_ranges(chapters: &mut [Chapter], duration: f64) {
+    if let Some(first) = chapters.first_mut() {
+        first.start_time = 0.0;
+    }
+
+    let starts: Vec<f64> = chapters.iter().map(|c| c.start_time).collect();
+    for (i, chapter) in chapters.iter_mut().enumerate() {
+        let end = starts.get(i + 1).copied().unwrap_or(duration);
+        chapter.end_time = end.max(chapter.start_time);
+    }
+}
+
+impl TranscriptionService {
+    /// Requests JSON constrained to `schema` and checks it with `validate`,
+    /// which returns a list of problems. Rejected responses are sent back to
+    /// the model together with the problems so it can repair them.
+    pub async fn generate_structured<T, F>(
+        &self,
+        prompt: &str,
+        schema: Value,
+        validate: F,
+    ) -> std::result::Result<StructuredResponse<T>, AnalysisError>
+    where
+        T: DeserializeOwned,
+        F: Fn(&T) -> Vec<String>,
+    {
+        let mut contents = vec![json!({ "role": "user", "parts": [{ "text": prompt }] })];
+        let mut last_error = AnalysisError::EmptyResponse { finish_reason: None };
+
+        for attempt in 1..=MAX_ANALYSIS_ATTEMPTS {
+            let request_body = json!({
+                "contents": contents,
+                "generationConfig": {
+                    "responseMimeType": "application/json",
+                    "responseSchema": schema
+                }
+            });
+
+            let response_json = self.generate(&request_body).await.map_err(AnalysisError::Provider)?;
+            let candidate = response_json.get("candidates").and_then(|c| c.get(0));
+
+            let response_text = candidate
+                .and_then(|c| c.get("content"))
+                .and_then(|c| c.get("parts"))
+                .and_then(|p| p.get(0))
+                .and_then(|p| p.get("text"))
+                .and_then(|t| t.as_str())
+                .filter(|t| !t.trim().is_empty());
+
+            let Some(response_text) = response_text else {
+                let finish_reason = candidate
+                    .and_then(|c| c.get("finishReason"))
+                    .and_then(|r| r.as_str())
+                    .map(|r| r.to_string());
+                eprintln!(
+                    "TranscriptionService: Structured response attempt {} of {} was empty ({:?})",
+                    attempt, MAX_ANALYSIS_ATTEMPTS, finish_reason
+                );
+                last_error = AnalysisError::EmptyResponse { finish_reason };
+                continue;
+            };
+
+            let problem = match serde_json::from_str::<T>(response_text) {
+                Ok(value) => {
+                    let issues = validate(&value);
+                    if issues.is_empty() {
+                        let confidence = candidate
+                            .and_then(Self::candidate_confidence)
+                            .unwrap_or(FALLBACK_CONFIDENCE);
+                        return Ok(StructuredResponse { value, confidence });
+                    }
+                    let problem = issues.join("; ");
+                    last_error = AnalysisError::Invalid(issues);
+                    problem
+                }
+                Err(e) => {
+                    let problem = format!("the JSON does not match the schema ({})", e);
+                    last_error = AnalysisError::InvalidJson(e.to_string());
+                    problem
+                }
+            };
+
+            eprintln!(
+                "TranscriptionService: Structured response attempt {} of {} rejected: {}",
+                attempt, MAX_ANALYSIS_ATTEMPTS, problem
+            );
+
+            contents.push(json!({ "role": "model", "parts": [{ "text": response_text }] }));
+            contents.push(json!({ "role": "user", "parts": [{ "text": repair_prompt(&problem) }] }));
+        }
+
+        Err(last_error)
+    }
+
+    /// Splits a transcription into chapters. Transcriptions with stored
+    /// segments are analysed by segment so that chapter and subsection times
+    /// come from the recording; older ones fall back to their plain text.
+    /// Transcripts too long for one prompt go through `analyze_in_windows`.
+    pub async fn analyze_chapters(
+        &self,
+        transcription: &Transcription,
+        on_progress: &(dyn Fn(AnalysisProgress) + Send + Sync),
+    ) -> std::result::Result<Vec<Chapter>, AnalysisError> {
+        let from_text = !transcription.segments.iter().any(|s| !s.text.trim().is_empty());
+
+        if from_text && transcription.raw_text.len() <= ANALYSIS_WINDOW_CHARS {
+            on_progress(AnalysisProgress::new(&transcription.id, AnalysisStage::Chapters, 0, 1));
+            let mut chapters = self.analyze_content_structure(&transcription.raw_text).await?;
+            close_chapter_ranges(&mut chapters, transcription.duration as f64);
+            on_progress(AnalysisProgress::new(&transcription.id, AnalysisStage::Done, 1, 1));
+            return Ok(chapters);
+        }
+
+        let segments = analysis_segments(transcription);
+        let windows = analysis_windows(&segments, ANALYSIS_WINDOW_CHARS, ANALYSIS_WINDOW_OVERLAP_CHARS);
+        let mut chapters = if windows.len() > 1 {
+            self.analyze_in_windows(&transcription.id, &segments, &windows, on_progress).await?
+        } else {
+            on_progress(AnalysisProgress::new(&transcription.id, AnalysisStage::Chapters, 0, 1));
+            let chapters = self.analyze_segments(&segments).await?;
+            on_progress(AnalysisProgress::new(&transcription.id, AnalysisStage::Done, 1, 1));
+            chapters
+        };
+
+        // Pseudo-segments are not stored, so there is nothing to refer to
+        if from_text {
+            for chapter in chapters.iter_mut() {
+                chapter.segment_ids.clear();
+                for subsection in chapter.subsections.iter_mut() {
+                    subsection.segment_ids.clear();
+                }
+            }
+        }
+
+        Ok(chapters)
+    }
+
+    async fn analyze_segments(&self, segments: &[TranscriptionSegment]) -> std::result::Result<Vec<Chapter>, AnalysisError> {
+        let prompt = format!(
+            "Analyze this transcription and break it into logical chapters with titles, and each
+            chapter into subsections with titles. The transcription is given as numbered segments.
+            Identify each chapter and subsection by the number of the segment it starts with.
+            The first chapter starts at segment 0 and chapters are in order.
+
+            Segments:
+            {}",
+            segment_listing(segments, 0..segments.len())
+        );
+
+        let segment_count = segments.len();
+        let response = self
+            .generate_structured(&prompt, segment_chapter_schema(), |chapters: &Vec<SegmentChapterDraft>| {
+                validate_segment_chapters(chapters, segment_count)
+            })
+            .await?;
+
+        Ok(chapters_from_segments(response.value, segments, response.confidence))
+    }
+
+    /// Map-reduce analysis: finds sections in each window, merges them with
+    /// `merge_window_sections`, then groups consecutive sections into
+    /// chapters. The sections become the chapters' subsections.
+    async fn analyze_in_windows(
+        &self,
+        transcription_id: &str,
+        segments: &[TranscriptionSegment],
+        windows: &[Range<usize>],
+        on_progress: &(dyn Fn(AnalysisProgress) + Send + Sync),
+    ) -> std::result::Result<Vec<Chapter>, AnalysisError> {
+        let total = windows.len() + 1;
+        on_progress(AnalysisProgress::new(transcription_id, AnalysisStage::Sections, 0, total));
+
+        let concurrency = self.pool.active_endpoint().rate_limiter.max_concurrent_requests();
+        let mut pending = stream::iter(windows.iter().cloned())
+            .map(|window| self.analyze_window(segments, window))
+            .buffered(concurrency);
+
+        let mut results = Vec::with_capacity(windows.len());
+        while let Some(result) = pending.next().await {
+            results.push(result?);
+            on_progress(AnalysisProgress::new(transcription_id, AnalysisStage::Sections, results.len(), total));
+        }
+        drop(pending);
+
+        let sections = merge_window_sections(windows, results);
+        println!(
+            "TranscriptionService: Merged {} windows into {} sections",
+            windows.len(),
+            sections.len()
+        );
+
+        let listing = sections
+            .iter()
+            .enumerate()
+            .map(|(i, section)| {
+                format!("[{}] ({:.1}s) {}", i, segments[section.first_segment].start_time, section.title.trim())
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let prompt = format!(
+            "These are the consecutive sections of a long transcription, numbered in order.
+            Group consecutive sections into logical chapters and give each chapter a title.
+            Identify each chapter by the number of the section it starts with.
+            The first chapter starts at section 0 and chapters are in order.
+
+            Sections:
+            {}",
+            listing
+        );
+
+        on_progress(AnalysisProgress::new(transcription_id, AnalysisStage::Chapters, windows.len(), total));
+
+        let section_count = sections.len();
+        let response = self
+            .generate_structured(&prompt, chapter_group_schema(), |chapters: &Vec<ChapterGroupDraft>| {
+                validate_chapter_groups(chapters, section_count)
+            })
+            .await?;
+
+        let groups = response.value;
+        let drafts = groups
+            .iter()
+            .enumerate()
+            .map(|(i, group)| {
+                let end = groups.get(i + 1).map_or(section_count, |next| next.first_section);
+                SegmentChapterDraft {
+                    title: group.title.clone(),
+                    first_segment: sections[group.first_section].first_segment,
+                    subsections: sections[group.first_section..end]
+                        .iter()
+                        .map(|section| SubsectionDraft {
+                            title: section.title.clone(),
+                            first_segment: section.first_segment,
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+
+        on_progress(AnalysisProgress::new(transcription_id, AnalysisStage::Done, total, total));
+        Ok(chapters_from_segments(drafts, segments, response.confidence))
+    }
+
+    async fn analyze_window(
+        &self,
+        segments: &[TranscriptionSegment],
+        window: Range<usize>,
+    ) -> std::result::Result<Vec<LocalSection>, AnalysisError> {
+        let prompt = format!(
+            "This is an excerpt of a longer transcription, given as numbered segments.
+            Break it into logical sections with short titles. Identify each section by the
+            number of the segment it starts with. The first section starts at segment {}
+            and sections are in order.
+
+            Segments:
+            {}",
+            window.start,
+            segment_listing(segments, window.clone())
+        );
+
+        let response = self
+            .generate_structured(&prompt, section_schema(), |sections: &Vec<LocalSection>| {
+                validate_sections(sections, &window)
+            })
+            .await?;
+
+        Ok(response.value)
+    }
+
+    /// Extracts the requested meeting notes. Long transcripts are analysed
+    /// per window and the partial notes are then combined in one request.
+    pub async fn analyze_notes(
+        &self,
+        transcription: &Transcription,
+        kinds: &[AnalysisKind],
+    ) -> std::result::Result<MeetingNotes, AnalysisError> {
+        let segments = analysis_segments(transcription);
+        let windows = analysis_windows(&segments, ANALYSIS_WINDOW_CHARS, ANALYSIS_WINDOW_OVERLAP_CHARS);
+        let schema = notes_schema(kinds);
+        let instructions = notes_instructions(kinds);
+
+        let notes = if windows.len() <= 1 {
+            let prompt = format!(
+                "Write meeting notes for this transcription. Provide:
+                {}
+                Only include what is actually said in the transcription; use empty lists when
+                there is nothing to report.
+
+                Transcription:
+                {}",
+                instructions,
+                join_text(&segments)
+            );
+            self.generate_structured(&prompt, schema, |notes: &MeetingNotes| validate_notes(notes, kinds))
+                .await?
+                .value
+        } else {
+            let concurrency = self.pool.active_endpoint().rate_limiter.max_concurrent_requests();
+            let partial: Vec<MeetingNotes> = stream::iter(windows.iter().cloned())
+                .map(|window| {
+                    let prompt = format!(
+                        "Write meeting notes for this excerpt of a longer transcription. Provide:
+                        {}
+                        Only include what is actually said in the excerpt; use empty lists when
+                        there is nothing to report.
+
+                        Excerpt:
+                        {}",
+                        instructions,
+                        join_text(&segments[window])
+                    );
+                    let schema = schema.clone();
+                    async move {
+                        self.generate_structured(&prompt, schema, |notes: &MeetingNotes| validate_notes(notes, kinds))
+                            .await
+                            .map(|response| response.value)
+                    }
+                })
+                .buffered(concurrency)
+                .collect::<Vec<_>>()
+                .await
+                .into_iter()
+                .collect::<std::result::Result<_, _>>()?;
+
+            let partial_json = serde_json::to_string_pretty(&partial).unwrap_or_default();
+            let prompt = format!(
+                "These are meeting notes written for consecutive, slightly overlapping excerpts of
+                one long transcription. Combine them into notes for the whole transcription. Provide:
+                {}
+                Merge duplicates, keep the order in which things came up and do not add anything
+                that is not in the partial notes.
+
+                Partial notes:
+                {}",
+                instructions,
+                partial_json
+            );
+            self.generate_structured(&prompt, schema, |notes: &MeetingNotes| validate_notes(notes, kinds))
+                .await?
+                .value
+        };
+
+        // Only the requested fields are kept even if the model added others
+        let mut requested = MeetingNotes::default();
+        requested.merge(notes, kinds);
+        let mut notes = requested;
+        tidy_notes(&mut notes);
+        notes.generated_at = Some(chrono::Utc::now());
+
+        Ok(notes)
+    }
+
+    pub async fn analyze_content_structure(&self, text: &str) -> std::result::Result<Vec<Chapter>, AnalysisError> {
+        if text.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let prompt = format!(
+            "Analyze this transcription and break it into logical chapters with titles.
+            Chapters must be in order and together cover the whole transcription.
+
+            Transcription:
+            {}",
+            text
+        );
+
+        let response = self
+            .generate_structured(&prompt, chapter_schema(), |chapters: &Vec<ChapterDraft>| validate_chapters(chapters))
+            .await?;
+
+        let chapters = response
+            .value
+            .into_iter()
+            .map(|draft| Chapter {
+                id: uuid::Uuid::new_v4().to_string(),
+                title: draft.title.trim().to_string(),
+                start_time: draft.start_time,
+                end_time: draft.start_time,
+                content: draft.content,
+                confidence: response.confidence,
+                subsections: Vec::new(),
+                segment_ids: Vec::new(),
+            })
+            .collect();
+
+        Ok(chapters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ProviderSettings, RateLimitSettings};
+    use crate::transcription::rate_limit::RateLimiter;
+    use std::sync::Arc;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn segment(index: usize, text: &str) -> TranscriptionSegment {
+        TranscriptionSegment {
+            id: format!("segment-{}", index),
+            text: text.to_string(),
+            start_time: index as f64 * 10.0,
+            end_time: (index + 1) as f64 * 10.0,
+            confidence: 0.9,
+            words: Vec::new(),
+            profile_id: None,
+            speaker: None,
+        }
+    }
+
+    fn segments(count: usize, chars: usize) -> Vec<TranscriptionSegment> {
+        (0..count).map(|i| segment(i, &"x".repeat(chars))).collect()
+    }
+
+    fn section(title: &str, first_segment: usize) -> LocalSection {
+        LocalSection { title: title.to_string(), first_segment }
+    }
+
+    fn text_response(text: &str) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "candidates": [{
+                "content": { "parts": [{ "text": text }] },
+                "finishReason": "STOP"
+            }]
+        }))
+    }
+
+    fn chapters_response(chapters: Value) -> ResponseTemplate {
+        text_response(&chapters.to_string())
+    }
+
+    fn service(server: &MockServer) -> TranscriptionService {
+        let settings = ProviderSettings { base_url: server.uri(), ..Default::default() };
+        let rate_limiter = Arc::new(RateLimiter::new(RateLimitSettings::default()));
+        TranscriptionService::new("key".to_string(), "test-model".to_string(), &settings, rate_limiter).unwrap()
+    }
+
+    fn chapter_draft(title: &str, content: &str, start_time: f64) -> ChapterDraft {
+        ChapterDraft { title: title.to_string(), content: content.to_string(), start_time }
+    }
+
+    fn segment_chapter(title: &str, first_segment: usize, subsections: &[(&str, usize)]) -> SegmentChapterDraft {
+        SegmentChapterDraft {
+            title: title.to_string(),
+            first_segment,
+            subsections: subsections
+                .iter()
+                .map(|(title, first_segment)| SubsectionDraft { title: title.to_string(), first_segment: *first_segment })
+                .collect(),
+        }
+    }
+
+    async fn chapters_from_text(server: &MockServer) -> std::result::Result<Vec<Chapter>, AnalysisError> {
+        service(server).analyze_content_structure("We talked about the budget.").await
+    }
+
+    #[test]
+    fn windows_stay_within_budget_and_overlap() {
+        let segments = segments(10, 10);
+        let windows = analysis_windows(&segments, 30, 10);
+
+        assert_eq!(windows, vec![0..3, 2..5, 4..7, 6..9, 8..10]);
+        for pair in windows.windows(2) {
+            assert!(pair[1].start < pair[0].end, "{:?} does not overlap {:?}", pair[1], pair[0]);
+            assert!(pair[1].start > pair[0].start);
+        }
+    }
+
+    #[test]
+    fn oversized_segment_gets_a_window_of_its_own() {
+        let mut segments = segments(3, 10);
+        segments[1].text = "x".repeat(100);
+
+        assert_eq!(analysis_windows(&segments, 30, 10), vec![0..1, 1..2, 2..3]);
+        assert_eq!(analysis_windows(&segments[..1], 30, 10), vec![0..1]);
+        assert!(analysis_windows(&[], 30, 10).is_empty());
+    }
+
+    #[test]
+    fn merged_sections_keep_one_entry_per_overlap() {
+        // The windows share segments 4 and 5; the second owns 5 onwards
+        let windows = vec![0..6, 4..10];
+        let results = vec![
+            vec![section("Opening", 0), section("Budget", 3), section("Hiring", 5)],
+            vec![section("Budget", 4), section("budget!", 5), section("Wrap-up", 8)],
+        ];
+
+        let merged = merge_window_sections(&windows, results);
+        let merged: Vec<(&str, usize)> = merged.iter().map(|s| (s.title.as_str(), s.first_segment)).collect();
+
+        assert_eq!(merged, vec![("Opening", 0), ("Budget", 3), ("Wrap-up", 8)]);
+    }
+
+    #[test]
+    fn merged_sections_start_at_the_first_segment() {
+        let window = 0..4;
+        let merged = merge_window_sections(std::slice::from_ref(&window), vec![vec![section("Late start", 2)]]);
+        assert_eq!(merged[0].first_segment, 0);
+    }
+
+    #[tokio::test]
+    async fn analyze_chapters_repairs_and_maps_segments() {
+        let server = MockServer::start().await;
+        let endpoint = "/v1beta/models/test-model:generateContent";
+
+        // The first answer does not start at segment 0 and is sent back for repair
+        Mock::given(method("POST"))
+            .and(path(endpoint))
+            .respond_with(chapters_response(json!([
+                { "title": "Budget", "first_segment": 1, "subsections": [] }
+            ])))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(endpoint))
+            .respond_with(chapters_response(json!([
+                { "title": "Opening", "first_segment": 0, "subsections": [] },
+                { "title": "Budget", "first_segment": 2, "subsections": [
+                    { "title": "Costs", "first_segment": 2 },
+                    { "title": "Savings", "first_segment": 3 }
+                ] }
+            ])))
+            .mount(&server)
+            .await;
+
+        let service = service(&server);
+
+        let transcription: Transcription = serde_json::from_value(json!({
+            "id": "t1",
+            "title": "Meeting",
+            "created_at": "2024-05-01T10:00:00Z",
+            "duration": 40,
+            "chapters": [],
+            "raw_text": "",
+            "status": "Completed",
+            "segments": [segment(0, "Hello."), segment(1, "Agenda."), segment(2, "Costs."), segment(3, "Savings.")]
+        }))
+        .unwrap();
+
+        let chapters = service.analyze_chapters(&transcription, &|_| {}).await.unwrap();
+
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[0].title, "Opening");
+        assert_eq!((chapters[0].start_time, chapters[0].end_time), (0.0, 20.0));
+        assert_eq!(chapters[0].segment_ids, vec!["segment-0", "segment-1"]);
+        assert_eq!(chapters[1].content, "Costs. Savings.");
+        assert_eq!((chapters[1].start_time, chapters[1].end_time), (20.0, 40.0));
+
+        let subsections: Vec<(&str, f64, f64)> = chapters[1]
+            .subsections
+            .iter()
+            .map(|s| (s.title.as_str(), s.start_time, s.end_time))
+            .collect();
+        assert_eq!(subsections, vec![("Costs", 20.0, 30.0), ("Savings", 30.0, 40.0)]);
+    }
+
+    #[test]
+    fn text_chapters_are_validated() {
+        assert!(validate_chapters(&[chapter_draft("Intro", "Hello", 0.0), chapter_draft("Budget", "Costs", 30.0)]).is_empty());
+        assert_eq!(validate_chapters(&[]), vec!["the array must contain at least one chapter"]);
+
+        let issues = validate_chapters(&[
+            chapter_draft(" ", "Hello", 0.0),
+            chapter_draft("Budget", "", f64::NAN),
+            chapter_draft("Hiring", "Jobs", 40.0),
+            chapter_draft("Wrap-up", "Bye", 20.0),
+        ]);
+        assert_eq!(issues, vec![
+            "chapter 1 has an empty title",
+            "chapter 2 has empty content",
+            "chapter 2 has an invalid start_time NaN",
+            "chapter 4 starts at 20 which is before the previous chapter (40)",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn text_chapters_run_until_the_next_one_starts() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "candidates": [{
+                    "content": { "parts": [{ "text": json!([
+                        { "title": " Intro ", "content": "We talked", "start_time": 5.0 },
+                        { "title": "Budget", "content": "about the budget.", "start_time": 30.0 }
+                    ]).to_string() }] },
+                    "avgLogprobs": 0.8f64.ln()
+                }]
+            })))
+            .mount(&server)
+            .await;
+
+        let mut chapters = chapters_from_text(&server).await.unwrap();
+        close_chapter_ranges(&mut chapters, 90.0);
+
+        let ranges: Vec<(&str, f64, f64)> = chapters.iter().map(|c| (c.title.as_str(), c.start_time, c.end_time)).collect();
+        assert_eq!(ranges, vec![("Intro", 0.0, 30.0), ("Budget", 30.0, 90.0)]);
+        assert!((chapters[0].confidence - 0.8).abs() < 1e-6);
+    }
+
+    #[tokio::test]
+    async fn invalid_responses_are_repaired_then_given_up() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST")).respond_with(chapters_response(json!([]))).mount(&server).await;
+
+        match chapters_from_text(&server).await {
+            Err(AnalysisError::Invalid(issues)) => assert_eq!(issues, vec!["the array must contain at least one chapter"]),
+            other => panic!("unexpected result {:?}", other.map(|c| c.len())),
+        }
+
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), MAX_ANALYSIS_ATTEMPTS);
+        let last: Value = serde_json::from_slice(&requests[MAX_ANALYSIS_ATTEMPTS - 1].body).unwrap();
+        let contents = last["contents"].as_array().unwrap();
+        assert_eq!(contents.len(), 1 + 2 * (MAX_ANALYSIS_ATTEMPTS - 1));
+        assert!(contents[2]["parts"][0]["text"].as_str().unwrap().contains("at least one chapter"));
+    }
+
+    #[tokio::test]
+    async fn malformed_and_empty_responses_are_reported() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST")).respond_with(text_response("{ not json")).mount(&server).await;
+        assert!(matches!(chapters_from_text(&server).await, Err(AnalysisError::InvalidJson(_))));
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "candidates": [{ "finishReason": "SAFETY" }]
+            })))
+            .mount(&server)
+            .await;
+        let error = chapters_from_text(&server).await.unwrap_err();
+        assert_eq!(error.to_string(), "Analysis returned no content (finish reason: SAFETY)");
+    }
+
+    #[test]
+    fn segment_chapters_must_cover_the_transcript_in_order() {
+        let valid = [segment_chapter("Intro", 0, &[]), segment_chapter("Budget", 2, &[("Costs", 2), ("Savings", 3)])];
+        assert!(validate_segment_chapters(&valid, 4).is_empty());
+
+        let issues = validate_segment_chapters(
+            &[
+                segment_chapter("Intro", 1, &[("Late", 3), ("Early", 2)]),
+                segment_chapter("Budget", 1, &[]),
+                segment_chapter("Hiring", 5, &[]),
+            ],
+            4,
+        );
+        assert_eq!(issues, vec![
+            "the first chapter must start at segment 0, not 1",
+            "chapter 1 must start before chapter 2",
+            "subsection 1 of chapter 1 starts at segment 3 outside the chapter's segments 1-0",
+            "subsection 2 of chapter 1 starts at segment 2 outside the chapter's segments 1-0",
+            "subsections of chapter 1 must be in increasing segment order",
+            "chapter 3 starts at segment 5 but the last segment is 3",
+        ]);
+    }
+
+    #[test]
+    fn chapter_and_subsection_ranges_come_from_segments() {
+        let segments: Vec<TranscriptionSegment> = (0..5).map(|i| segment(i, &format!("Part {}.", i))).collect();
+        let drafts = vec![
+            segment_chapter("Intro", 0, &[]),
+            segment_chapter("Budget", 2, &[("Costs", 3), ("Savings", 4)]),
+        ];
+
+        let chapters = chapters_from_segments(drafts, &segments, 0.7);
+
+        let ranges: Vec<(f64, f64)> = chapters.iter().map(|c| (c.start_time, c.end_time)).collect();
+        assert_eq!(ranges, vec![(0.0, 20.0), (20.0, 50.0)]);
+        assert_eq!(chapters[0].content, "Part 0. Part 1.");
+        assert_eq!(chapters[1].segment_ids, vec!["segment-2", "segment-3", "segment-4"]);
+
+        // Without subsections the chapter gets one; otherwise the first opens the chapter
+        assert_eq!(chapters[0].subsections.len(), 1);
+        assert_eq!(chapters[0].subsections[0].title, "Intro");
+        let subsections: Vec<(&str, f64, f64)> = chapters[1]
+            .subsections
+            .iter()
+            .map(|s| (s.title.as_str(), s.start_time, s.end_time))
+            .collect();
+        assert_eq!(subsections, vec![("Costs", 20.0, 40.0), ("Savings", 40.0, 50.0)]);
+        assert!(chapters.iter().all(|c| c.confidence == 0.7));
+    }
+
+    #[test]
+    fn analysis_uses_sorted_segments_or_falls_back_to_text() {
+        let mut transcription: Transcription = serde_json::from_value(json!({
+            "id": "t1",
+            "title": "Meeting",
+            "created_at": "2024-05-01T10:00:00Z",
+            "duration": 60,
+            "chapters": [],
+            "raw_text": "First sentence. Second sentence.",
+            "status": "Completed",
+            "segments": [segment(2, "Later."), segment(1, "  "), segment(0, "Hello.")]
+        }))
+        .unwrap();
+
+        let ids: Vec<String> = analysis_segments(&transcription).into_iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec!["segment-0", "segment-2"]);
+
+        transcription.segments.clear();
+        let pseudo = analysis_segments(&transcription);
+        assert_eq!(pseudo.len(), 1);
+        assert_eq!(pseudo[0].text, "First sentence. Second sentence.");
+        assert_eq!((pseudo[0].start_time, pseudo[0].end_time), (0.0, 60.0));
+    }
+
+    #[test]
+    fn long_text_is_cut_at_sentence_ends() {
+        let sentence = format!("{}.", "word ".repeat(100).trim_end());
+        let text = [sentence.as_str(); 3].join(" ");
+        let pieces = text_segments(&text, 30.0);
+
+        assert_eq!(pieces.len(), 3);
+        assert!(pieces.iter().all(|p| p.text == sentence));
+        assert_eq!(pieces[0].start_time, 0.0);
+        assert_eq!(pieces[1].start_time, pieces[0].end_time);
+        assert!((pieces[2].end_time - 30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn notes_schema_requires_only_the_requested_analyses() {
+        let schema = notes_schema(&[AnalysisKind::Decisions, AnalysisKind::ExecutiveSummary]);
+
+        assert_eq!(schema["required"], json!(["decisions", "executive_summary"]));
+        let properties: Vec<&String> = schema["properties"].as_object().unwrap().keys().collect();
+        assert_eq!(properties.len(), 2);
+    }
+
+    #[test]
+    fn notes_are_validated_per_requested_analysis() {
+        let notes: MeetingNotes = serde_json::from_value(json!({
+            "executive_summary": "  ",
+            "action_items": [{ "description": "" }]
+        }))
+        .unwrap();
+
+        let issues = validate_notes(&notes, &[AnalysisKind::ExecutiveSummary, AnalysisKind::Decisions]);
+        assert_eq!(issues, vec!["Decisions is missing", "executive_summary is empty", "every action item needs a description"]);
+        assert!(validate_notes(&MeetingNotes::default(), &[]).is_empty());
+    }
+
+    #[test]
+    fn tidying_drops_blank_entries_and_fields() {
+        let mut notes: MeetingNotes = serde_json::from_value(json!({
+            "bullet_summary": ["Budget approved", " "],
+            "decisions": [""],
+            "action_items": [{ "description": "Send slides", "owner": " ", "due_date": "Friday" }]
+        }))
+        .unwrap();
+
+        tidy_notes(&mut notes);
+
+        assert_eq!(notes.bullet_summary, Some(vec!["Budget approved".to_string()]));
+        assert_eq!(notes.decisions, Some(Vec::new()));
+        let item = &notes.action_items.as_ref().unwrap()[0];
+        assert_eq!((item.owner.as_deref(), item.due_date.as_deref()), (None, Some("Friday")));
+    }
+
+    #[tokio::test]
+    async fn analyze_notes_keeps_only_the_requested_fields() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(text_response(&json!({
+                "action_items": [{ "description": "Send slides", "owner": "Anna", "due_date": null }],
+                "decisions": ["Hire two people", ""],
+                "executive_summary": "Not requested"
+            }).to_string()))
+            .mount(&server)
+            .await;
+
+        let transcription: Transcription = serde_json::from_value(json!({
+            "id": "t1",
+            "title": "Meeting",
+            "created_at": "2024-05-01T10:00:00Z",
+            "duration": 20,
+            "chapters": [],
+            "raw_text": "",
+            "status": "Completed",
+            "segments": [segment(0, "Anna sends the slides."), segment(1, "We hire two people.")]
+        }))
+        .unwrap();
+
+        let notes = service(&server)
+            .analyze_notes(&transcription, &[AnalysisKind::ActionItems, AnalysisKind::Decisions])
+            .await
+            .unwrap();
+
+        assert_eq!(notes.executive_summary, None);
+        assert_eq!(notes.decisions, Some(vec!["Hire two people".to_string()]));
+        assert_eq!(notes.action_items.unwrap()[0].owner.as_deref(), Some("Anna"));
+        assert!(notes.generated_at.is_some());
+
+        let request: Value = serde_json::from_slice(&server.received_requests().await.unwrap()[0].body).unwrap();
+        let prompt = request["contents"][0]["parts"][0]["text"].as_str().unwrap();
+        assert!(prompt.contains("Anna sends the slides. We hire two people."));
+    }
+}
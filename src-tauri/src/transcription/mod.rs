@@ -1,100 +1,476 @@
+pub mod analysis;
+pub mod query;
+pub mod rate_limit;
+pub mod title;
+
 use anyhow::{Result, anyhow};
+use futures::future::{BoxFuture, FutureExt};
+use futures::stream::{FuturesOrdered, StreamExt};
+use rate_limit::RateLimiter;
 use reqwest::Client;
 use serde_json::{json, Value};
-use tokio::sync::mpsc;
-use crate::models::{AudioChunk, TranscriptionChunk, Chapter};
+use crate::pipeline::{self, Message, PipelineReceiver, QueueConfig};
+use tokio_util::sync::CancellationToken;
+use crate::models::{self, ApiKeyStatus, ApiKeyValidation, AudioChunk, TranscriptionChunk, GeminiModel, ProviderSettings, TokenUsage, WordConfidence};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Header carrying the API key, so it never appears in URLs, logs or proxies.
+const API_KEY_HEADER: &str = "x-goog-api-key";
+
+/// Profile id of the single key configured through `set_api_key`.
+pub const DEFAULT_PROFILE_ID: &str = "default";
+
+/// Gemini bills audio input at a fixed rate per second.
+const AUDIO_TOKENS_PER_SECOND: u64 = 32;
+const PROMPT_TOKEN_ESTIMATE: u64 = 32;
+
+/// Confidence used when the provider returns no log-probabilities at all.
+pub const FALLBACK_CONFIDENCE: f32 = 0.5;
+
+/// Transcribed text of one audio chunk with the confidence derived from the
+/// provider's log-probabilities.
+pub struct ChunkTranscript {
+    pub text: String,
+    pub confidence: f32,
+    pub words: Vec<WordConfidence>,
+    pub usage: TokenUsage,
+    pub profile_id: String,
+    pub model: String,
+}
 
-pub struct TranscriptionService {
+/// One credential profile resolved into what is needed to call the API.
+#[derive(Debug, Clone)]
+pub struct ProviderEndpoint {
+    pub profile_id: String,
+    pub api_key: String,
+    pub api_root: String,
+    pub model: String,
+    pub rate_limiter: Arc<RateLimiter>,
+}
+
+/// Ordered endpoints sharing one HTTP client. Requests go to the active
+/// endpoint and move on to the next one on quota or authentication errors;
+/// the switch is shared by every clone of the pool.
+#[derive(Clone)]
+pub struct ProviderPool {
     client: Client,
-    api_key: String,
-    base_url: String,
-    model: String,
+    endpoints: Arc<Vec<ProviderEndpoint>>,
+    active: Arc<AtomicUsize>,
+}
+
+impl ProviderPool {
+    pub fn active_endpoint(&self) -> &ProviderEndpoint {
+        let index = self.active.load(Ordering::SeqCst).min(self.endpoints.len() - 1);
+        &self.endpoints[index]
+    }
+
+    /// Whether an error response should trigger failover to the next profile.
+    fn is_failover_error(status: reqwest::StatusCode, body: &str) -> bool {
+        matches!(status.as_u16(), 401 | 403 | 429)
+            || body.contains("RESOURCE_EXHAUSTED")
+            || body.contains("API_KEY_INVALID")
+    }
+
+    /// Posts `request_body` to `generateContent`, failing over through the
+    /// remaining endpoints. Returns the response with the endpoint that
+    /// produced it.
+    pub async fn generate_content(&self, request_body: &Value, estimated_tokens: u64) -> Result<(Value, ProviderEndpoint)> {
+        loop {
+            let index = self.active.load(Ordering::SeqCst);
+            let endpoint = self.endpoints
+                .get(index)
+                .ok_or_else(|| anyhow!("All credential profiles failed"))?;
+            let url = format!("{}/models/{}:generateContent", endpoint.api_root, endpoint.model);
+            let _permit = endpoint.rate_limiter.acquire(estimated_tokens).await;
+
+            let response = self.client
+                .post(&url)
+                .header(API_KEY_HEADER, &endpoint.api_key)
+                .json(request_body)
+                .send()
+                .await?;
+
+            let status = response.status();
+            if status.is_success() {
+                return Ok((response.json().await?, endpoint.clone()));
+            }
+
+            let error_text = response.text().await?;
+            if !Self::is_failover_error(status, &error_text) || index + 1 >= self.endpoints.len() {
+                return Err(anyhow!("API error: {}", error_text));
+            }
+
+            eprintln!(
+                "TranscriptionService: Profile {} failed ({}), failing over to {}",
+                endpoint.profile_id, status, self.endpoints[index + 1].profile_id
+            );
+            // Another request may already have moved past this endpoint
+            let _ = self.active.compare_exchange(index, index + 1, Ordering::SeqCst, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Audio accumulated for the next request, positioned on the recording timeline.
+#[derive(Default)]
+struct AudioBatch {
+    samples: Vec<f32>,
+    sample_rate: u32,
+    start_time: f64,
+}
+
+impl AudioBatch {
+    fn push(&mut self, chunk: &AudioChunk) {
+        if self.samples.is_empty() {
+            self.start_time = chunk.timestamp;
+        }
+        self.sample_rate = chunk.sample_rate;
+        self.samples.extend_from_slice(&chunk.data);
+    }
+
+    /// Starts a request for the accumulated audio, or `None` if it is too
+    /// short to send.
+    fn take(&mut self, pool: &ProviderPool) -> Option<BoxFuture<'static, Option<TranscriptionChunk>>> {
+        let samples = std::mem::take(&mut self.samples);
+        if samples.is_empty() {
+            return None;
+        }
+
+        let start_time = self.start_time;
+        let end_time = start_time + samples.len() as f64 / self.sample_rate as f64;
+
+        // Skip very small audio chunks to avoid API errors
+        if samples.len() < 1000 {
+            println!("TranscriptionService: Skipping small audio chunk ({} samples)", samples.len());
+            return None;
+        }
+
+        println!("TranscriptionService: Sending {} samples to Gemini API", samples.len());
+        Some(TranscriptionService::transcribe_segment(pool.clone(), samples, self.sample_rate, start_time, end_time).boxed())
+    }
+}
+
+pub struct TranscriptionService {
+    pool: ProviderPool,
+    usage: Arc<Mutex<BTreeMap<String, TokenUsage>>>,
 }
 
 impl TranscriptionService {
-    pub fn new(api_key: String, model: String) -> Self {
-        Self {
-            client: Client::new(),
+    pub fn new(
+        api_key: String,
+        model: String,
+        settings: &ProviderSettings,
+        rate_limiter: Arc<RateLimiter>,
+    ) -> Result<Self> {
+        let endpoint = ProviderEndpoint {
+            profile_id: DEFAULT_PROFILE_ID.to_string(),
             api_key,
-            base_url: "https://generativelanguage.googleapis.com/v1beta".to_string(),
+            api_root: settings.api_root(),
             model,
+            rate_limiter,
+        };
+        Self::with_failover(vec![endpoint], settings)
+    }
+
+    /// Service that tries `endpoints` in order, failing over on quota and
+    /// authentication errors.
+    pub fn with_failover(endpoints: Vec<ProviderEndpoint>, settings: &ProviderSettings) -> Result<Self> {
+        if endpoints.is_empty() {
+            return Err(anyhow!("No credential profile configured"));
         }
+
+        Ok(Self {
+            pool: ProviderPool {
+                client: Self::build_client(settings)?,
+                endpoints: Arc::new(endpoints),
+                active: Arc::new(AtomicUsize::new(0)),
+            },
+            usage: Arc::new(Mutex::new(BTreeMap::new())),
+        })
     }
 
+    /// HTTP client honouring the configured timeout, proxy and CA bundle.
+    pub fn build_client(settings: &ProviderSettings) -> Result<Client> {
+        let mut builder = Client::builder()
+            .timeout(std::time::Duration::from_secs(settings.timeout_secs));
+
+        if let Some(proxy_url) = settings.proxy_url.as_deref().filter(|p| !p.trim().is_empty()) {
+            let proxy = reqwest::Proxy::all(proxy_url)
+                .map_err(|e| anyhow!("Invalid proxy URL {}: {}", proxy_url, e))?;
+            builder = builder.proxy(proxy);
+        }
+
+        if let Some(ca_bundle_path) = settings.ca_bundle_path.as_deref().filter(|p| !p.trim().is_empty()) {
+            let pem = std::fs::read(ca_bundle_path)
+                .map_err(|e| anyhow!("Failed to read CA bundle {}: {}", ca_bundle_path, e))?;
+            for certificate in reqwest::Certificate::from_pem_bundle(&pem)? {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        Ok(builder.build()?)
+    }
+
+    /// Returns the tokens consumed by this service's non-streaming calls since
+    /// the last call, keyed by the model that served them, and resets the
+    /// counters. After a failover that is not necessarily `model()`.
+    pub fn take_usage(&self) -> BTreeMap<String, TokenUsage> {
+        std::mem::take(&mut *self.usage.lock().unwrap())
+    }
+
+    /// Performs a cheap authenticated call (listing a single model) to check
+    /// whether the configured key is accepted.
+    pub async fn validate_api_key(&self) -> ApiKeyValidation {
+        let endpoint = self.pool.active_endpoint();
+        let url = format!("{}/models?pageSize=1", endpoint.api_root);
+        let _permit = endpoint.rate_limiter.acquire(0).await;
+
+        let response = match self.pool.client
+            .get(&url)
+            .header(API_KEY_HEADER, &endpoint.api_key)
+            .send()
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => return ApiKeyValidation::new(ApiKeyStatus::NetworkError, Some(e.to_string())),
+        };
+
+        let status = response.status();
+        if status.is_success() {
+            return ApiKeyValidation::new(ApiKeyStatus::Valid, None);
+        }
+
+        let body = response.text().await.unwrap_or_default();
+        let message = serde_json::from_str::<Value>(&body)
+            .ok()
+            .and_then(|v| v.get("error")?.get("message")?.as_str().map(|m| m.to_string()))
+            .unwrap_or(body);
+
+        let key_status = match status.as_u16() {
+            400 | 401 => ApiKeyStatus::Invalid,
+            403 => ApiKeyStatus::PermissionDenied,
+            429 => ApiKeyStatus::QuotaExceeded,
+            _ => ApiKeyStatus::UnexpectedResponse,
+        };
+
+        ApiKeyValidation::new(key_status, Some(message))
+    }
+
+    /// Queries the provider's models endpoint and returns the audio-capable
+    /// models that support `generateContent`.
+    pub async fn list_models(&self) -> Result<Vec<GeminiModel>> {
+        let endpoint = self.pool.active_endpoint();
+        let mut models = Vec::new();
+        let mut page_token: Option<String> = None;
+
+        loop {
+            let mut url = format!("{}/models?pageSize=1000", endpoint.api_root);
+            if let Some(token) = &page_token {
+                url.push_str(&format!("&pageToken={}", token));
+            }
+            let _permit = endpoint.rate_limiter.acquire(0).await;
+
+            let response = self.pool.client
+                .get(&url)
+                .header(API_KEY_HEADER, &endpoint.api_key)
+                .send()
+                .await?;
+            if !response.status().is_success() {
+                let error_text = response.text().await?;
+                return Err(anyhow!("API error: {}", error_text));
+            }
+
+            let response_json: Value = response.json().await?;
+            let entries = response_json
+                .get("models")
+                .and_then(|m| m.as_array())
+                .cloned()
+                .unwrap_or_default();
+
+            models.extend(entries.iter().filter_map(Self::parse_model));
+
+            page_token = response_json
+                .get("nextPageToken")
+                .and_then(|t| t.as_str())
+                .filter(|t| !t.is_empty())
+                .map(|t| t.to_string());
+            if page_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(models)
+    }
+
+    fn parse_model(entry: &Value) -> Option<GeminiModel> {
+        let id = entry.get("name")?.as_str()?.trim_start_matches("models/").to_string();
+
+        let supports_generate = entry
+            .get("supportedGenerationMethods")
+            .and_then(|m| m.as_array())
+            .map(|methods| methods.iter().any(|m| m.as_str() == Some("generateContent")))
+            .unwrap_or(false);
+        if !supports_generate || !models::model_supports_audio(&id) {
+            return None;
+        }
+
+        let context_window = match entry.get("inputTokenLimit").and_then(|l| l.as_u64()) {
+            Some(limit) if limit >= 1_000_000 => format!("{}M tokens", limit / 1_000_000),
+            Some(limit) => format!("{}K tokens", limit / 1_000),
+            None => "Unknown".to_string(),
+        };
+
+        Some(GeminiModel {
+            name: entry
+                .get("displayName")
+                .and_then(|n| n.as_str())
+                .unwrap_or(&id)
+                .to_string(),
+            description: entry
+                .get("description")
+                .and_then(|d| d.as_str())
+                .unwrap_or("")
+                .to_string(),
+            supports_audio: true,
+            context_window,
+            id,
+        })
+    }
+
+    /// Transcribes audio in `TRANSCRIPTION_INTERVAL` batches. Up to the
+    /// rate limiter's concurrency bound, batches are sent while earlier ones
+    /// are still in flight; results are emitted in recording order into a
+    /// queue configured by `output`.
+    ///
+    /// When the audio queue closes, the remaining partial batch is sent and
+    /// in-flight requests are awaited before the output queue closes.
+    /// Cancelling `cancel` abandons all of that immediately.
     pub async fn start_streaming_transcription(
         &self,
-        mut audio_rx: mpsc::UnboundedReceiver<AudioChunk>,
-    ) -> Result<mpsc::UnboundedReceiver<TranscriptionChunk>> {
-        let (tx, rx) = mpsc::unbounded_channel();
-        let client = self.client.clone();
-        let api_key = self.api_key.clone();
-        let base_url = self.base_url.clone();
-        let model = self.model.clone();
+        mut audio_rx: PipelineReceiver<AudioChunk>,
+        output: QueueConfig,
+        cancel: CancellationToken,
+    ) -> Result<PipelineReceiver<TranscriptionChunk>> {
+        let (tx, rx) = pipeline::channel(output);
+        let pool = self.pool.clone();
+        let max_in_flight = pool.active_endpoint().rate_limiter.max_concurrent_requests();
 
         tokio::spawn(async move {
-            let mut accumulated_audio = Vec::new();
+            let mut batch = AudioBatch::default();
             let mut last_transcription_time = std::time::Instant::now();
+            let mut in_flight: FuturesOrdered<BoxFuture<'static, Option<TranscriptionChunk>>> = FuturesOrdered::new();
             const TRANSCRIPTION_INTERVAL: std::time::Duration = std::time::Duration::from_millis(2000);
 
             println!("TranscriptionService: Waiting for audio chunks...");
 
-            while let Some(chunk) = audio_rx.recv().await {
-                println!("TranscriptionService: Received audio chunk with {} samples", chunk.data.len());
-                accumulated_audio.extend_from_slice(&chunk.data);
-
-                if last_transcription_time.elapsed() >= TRANSCRIPTION_INTERVAL && !accumulated_audio.is_empty() {
-                    let audio_data = accumulated_audio.clone();
-                    accumulated_audio.clear();
-                    last_transcription_time = std::time::Instant::now();
-
-                    // Skip very small audio chunks to avoid API errors
-                    if audio_data.len() < 1000 {
-                        println!("TranscriptionService: Skipping small audio chunk ({} samples)", audio_data.len());
-                        continue;
+            loop {
+                tokio::select! {
+                    _ = cancel.cancelled() => {
+                        println!("TranscriptionService: Cancelled with {} requests in flight", in_flight.len());
+                        return;
                     }
-
-                    println!("TranscriptionService: Sending {} samples to Gemini API", audio_data.len());
-                    match Self::transcribe_audio_chunk(&client, &api_key, &base_url, &model, audio_data, chunk.sample_rate).await {
-                        Ok(text) => {
-                            if !text.trim().is_empty() {
-                                let transcription_chunk = TranscriptionChunk {
-                                    text,
-                                    confidence: 0.9, // Placeholder confidence
-                                    start_time: chunk.timestamp,
-                                    end_time: chunk.timestamp + 2.0,
-                                    is_final: false,
-                                };
-
-                                if tx.send(transcription_chunk).is_err() {
-                                    break;
+                    // Stop pulling audio while the in-flight window is full
+                    received = audio_rx.recv(), if in_flight.len() < max_in_flight => {
+                        match received {
+                            Some(Message::Item(chunk)) => {
+                                println!("TranscriptionService: Received audio chunk with {} samples", chunk.data.len());
+                                batch.push(&chunk);
+
+                                if last_transcription_time.elapsed() >= TRANSCRIPTION_INTERVAL {
+                                    last_transcription_time = std::time::Instant::now();
+                                    if let Some(request) = batch.take(&pool) {
+                                        in_flight.push_back(request);
+                                    }
+                                }
+                            }
+                            Some(Message::Gap { dropped }) => {
+                                // Don't splice audio from both sides of the gap into one request
+                                eprintln!("TranscriptionService: {} audio chunks dropped", dropped);
+                                if let Some(request) = batch.take(&pool) {
+                                    in_flight.push_back(request);
                                 }
                             }
+                            None => break,
                         }
-                        Err(e) => {
-                            eprintln!("Transcription error: {}", e);
+                    }
+                    Some(result) = in_flight.next(), if !in_flight.is_empty() => {
+                        if let Some(transcription_chunk) = result {
+                            if tx.send(transcription_chunk).await.is_err() {
+                                return;
+                            }
                         }
                     }
                 }
             }
+
+            // Audio finished: transcribe what is left below the interval
+            if let Some(request) = batch.take(&pool) {
+                in_flight.push_back(request);
+            }
+
+            loop {
+                let result = tokio::select! {
+                    _ = cancel.cancelled() => return,
+                    result = in_flight.next() => result,
+                };
+                let Some(result) = result else { break };
+
+                if let Some(transcription_chunk) = result {
+                    if tx.send(transcription_chunk).await.is_err() {
+                        return;
+                    }
+                }
+            }
+
+            println!("TranscriptionService: Audio stream finished");
         });
 
         Ok(rx)
     }
 
+    /// Transcribes one batch of audio into a chunk covering `start_time..end_time`.
+    /// Errors are logged and yield `None`.
+    async fn transcribe_segment(
+        pool: ProviderPool,
+        audio_data: Vec<f32>,
+        sample_rate: u32,
+        start_time: f64,
+        end_time: f64,
+    ) -> Option<TranscriptionChunk> {
+        match Self::transcribe_audio_chunk(&pool, audio_data, sample_rate).await {
+            // Empty transcripts are still forwarded so their token usage is accounted
+            Ok(transcript) => Some(TranscriptionChunk {
+                id: uuid::Uuid::new_v4().to_string(),
+                text: transcript.text,
+                confidence: transcript.confidence,
+                words: transcript.words,
+                start_time,
+                end_time,
+                is_final: false,
+                usage: transcript.usage,
+                profile_id: transcript.profile_id,
+                model: transcript.model,
+            }),
+            Err(e) => {
+                eprintln!("Transcription error: {}", e);
+                None
+            }
+        }
+    }
+
     async fn transcribe_audio_chunk(
-        client: &Client,
-        api_key: &str,
-        base_url: &str,
-        model: &str,
+        pool: &ProviderPool,
         audio_data: Vec<f32>,
         sample_rate: u32,
-    ) -> Result<String> {
+    ) -> Result<ChunkTranscript> {
+        let estimated_tokens = AUDIO_TOKENS_PER_SECOND * audio_data.len() as u64 / sample_rate.max(1) as u64
+            + PROMPT_TOKEN_ESTIMATE;
+
         // Convert f32 audio data to base64 encoded WAV
         let wav_data = Self::convert_to_wav(&audio_data, sample_rate)?;
         let base64_audio = base64::encode(&wav_data);
 
-        let url = format!("{}/models/{}:generateContent?key={}", base_url, model, api_key);
-
         let request_body = json!({
             "contents": [{
                 "parts": [{
@@ -105,25 +481,16 @@ impl TranscriptionService {
                         "data": base64_audio
                     }
                 }]
-            }]
+            }],
+            "generationConfig": {
+                "responseLogprobs": true
+            }
         });
 
-        let response = client
-            .post(&url)
-            .json(&request_body)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            return Err(anyhow!("API error: {}", error_text));
-        }
-
-        let response_json: Value = response.json().await?;
+        let (response_json, endpoint) = pool.generate_content(&request_body, estimated_tokens).await?;
+        let candidate = response_json.get("candidates").and_then(|c| c.get(0));
 
-        let text = response_json
-            .get("candidates")
-            .and_then(|c| c.get(0))
+        let text = candidate
             .and_then(|c| c.get("content"))
             .and_then(|c| c.get("parts"))
             .and_then(|p| p.get(0))
@@ -132,7 +499,127 @@ impl TranscriptionService {
             .unwrap_or("")
             .to_string();
 
-        Ok(text)
+        let words = candidate.map(Self::word_confidences).unwrap_or_default();
+        let confidence = candidate
+            .and_then(Self::candidate_confidence)
+            .unwrap_or(FALLBACK_CONFIDENCE);
+
+        let usage = Self::parse_usage(&response_json);
+
+        Ok(ChunkTranscript {
+            text,
+            confidence,
+            words,
+            usage,
+            profile_id: endpoint.profile_id,
+            model: endpoint.model,
+        })
+    }
+
+    /// Reads Gemini's `usageMetadata`. Thinking tokens are billed as output.
+    pub fn parse_usage(response: &Value) -> TokenUsage {
+        let metadata = match response.get("usageMetadata") {
+            Some(metadata) => metadata,
+            None => return TokenUsage { requests: 1, ..Default::default() },
+        };
+        let count = |field: &str| metadata.get(field).and_then(|v| v.as_u64()).unwrap_or(0);
+
+        let audio_tokens = metadata
+            .get("promptTokensDetails")
+            .and_then(|d| d.as_array())
+            .map(|details| {
+                details
+                    .iter()
+                    .filter(|d| d.get("modality").and_then(|m| m.as_str()) == Some("AUDIO"))
+                    .filter_map(|d| d.get("tokenCount").and_then(|c| c.as_u64()))
+                    .sum()
+            })
+            .unwrap_or(0);
+
+        TokenUsage {
+            input_tokens: count("promptTokenCount"),
+            audio_tokens,
+            output_tokens: count("candidatesTokenCount") + count("thoughtsTokenCount"),
+            requests: 1,
+        }
+    }
+
+    /// Converts a (natural) log-probability into a confidence in `0.0..=1.0`.
+    /// Works for Gemini `avgLogprobs` as well as Whisper-style `avg_logprob`.
+    pub fn confidence_from_logprob(logprob: f64) -> f32 {
+        (logprob.exp() as f32).clamp(0.0, 1.0)
+    }
+
+    /// Candidate-level confidence: `avgLogprobs` when present, otherwise the
+    /// mean of the chosen token log-probabilities.
+    fn candidate_confidence(candidate: &Value) -> Option<f32> {
+        if let Some(avg) = candidate.get("avgLogprobs").and_then(|a| a.as_f64()) {
+            return Some(Self::confidence_from_logprob(avg));
+        }
+
+        let logprobs: Vec<f64> = Self::chosen_tokens(candidate)
+            .iter()
+            .map(|(_, logprob)| *logprob)
+            .collect();
+
+        if logprobs.is_empty() {
+            return None;
+        }
+
+        let mean = logprobs.iter().sum::<f64>() / logprobs.len() as f64;
+        Some(Self::confidence_from_logprob(mean))
+    }
+
+    fn chosen_tokens(candidate: &Value) -> Vec<(String, f64)> {
+        candidate
+            .get("logprobsResult")
+            .and_then(|r| r.get("chosenCandidates"))
+            .and_then(|c| c.as_array())
+            .map(|tokens| {
+                tokens
+                    .iter()
+                    .filter_map(|t| {
+                        let token = t.get("token")?.as_str()?.to_string();
+                        let logprob = t.get("logProbability")?.as_f64()?;
+                        Some((token, logprob))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Groups the chosen tokens into whitespace-separated words. A word's
+    /// confidence is the joint probability of its tokens.
+    fn word_confidences(candidate: &Value) -> Vec<WordConfidence> {
+        let mut words = Vec::new();
+        let mut current = String::new();
+        let mut current_logprob = 0.0;
+
+        for (token, logprob) in Self::chosen_tokens(candidate) {
+            if token.starts_with(char::is_whitespace) && !current.is_empty() {
+                words.push(WordConfidence {
+                    text: std::mem::take(&mut current),
+                    confidence: Self::confidence_from_logprob(current_logprob),
+                });
+                current_logprob = 0.0;
+            }
+
+            let trimmed = token.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            current.push_str(trimmed);
+            current_logprob += logprob;
+        }
+
+        if !current.is_empty() {
+            words.push(WordConfidence {
+                text: current,
+                confidence: Self::confidence_from_logprob(current_logprob),
+            });
+        }
+
+        words
     }
 
     fn convert_to_wav(audio_data: &[f32], sample_rate: u32) -> Result<Vec<u8>> {
@@ -166,77 +653,18 @@ impl TranscriptionService {
         Ok(wav_data)
     }
 
-    pub async fn analyze_content_structure(&self, text: &str) -> Result<Vec<Chapter>> {
-        let url = format!("{}/models/{}:generateContent?key={}", self.base_url, self.model, self.api_key);
-
-        let prompt = format!(
-            "Analyze this transcription and break it into logical chapters with titles.
-            Return a JSON array with this structure:
-            [{{\"title\": \"Chapter Title\", \"content\": \"Chapter content...\", \"start_time\": 0.0}}]
-
-            Transcription:
-            {}",
-            text
-        );
-
-        let request_body = json!({
-            "contents": [{
-                "parts": [{
-                    "text": prompt
-                }]
-            }]
-        });
-
-        let response = self.client
-            .post(&url)
-            .json(&request_body)
-            .send()
-            .await?;
-
-        let response_json: Value = response.json().await?;
-
-        let response_text = response_json
-            .get("candidates")
-            .and_then(|c| c.get(0))
-            .and_then(|c| c.get("content"))
-            .and_then(|c| c.get("parts"))
-            .and_then(|p| p.get(0))
-            .and_then(|p| p.get("text"))
-            .and_then(|t| t.as_str())
-            .unwrap_or("[]");
-
-        // Extract JSON from the response
-        let json_start = response_text.find('[').unwrap_or(0);
-        let json_end = response_text.rfind(']').unwrap_or(response_text.len());
-        let json_str = &response_text[json_start..=json_end];
-
-        let chapters_data: Value = serde_json::from_str(json_str)
-            .unwrap_or_else(|_| json!([]));
-
-        let mut chapters = Vec::new();
-        if let Some(chapters_array) = chapters_data.as_array() {
-            for (i, chapter_data) in chapters_array.iter().enumerate() {
-                let chapter = Chapter {
-                    id: uuid::Uuid::new_v4().to_string(),
-                    title: chapter_data.get("title")
-                        .and_then(|t| t.as_str())
-                        .unwrap_or(&format!("Chapter {}", i + 1))
-                        .to_string(),
-                    start_time: chapter_data.get("start_time")
-                        .and_then(|t| t.as_f64())
-                        .unwrap_or(0.0),
-                    content: chapter_data.get("content")
-                        .and_then(|c| c.as_str())
-                        .unwrap_or("")
-                        .to_string(),
-                    confidence: 0.9,
-                    subsections: Vec::new(),
-                };
-                chapters.push(chapter);
-            }
-        }
-
-        Ok(chapters)
+    /// `generateContent` through the provider pool, adding the response's
+    /// token usage to the counter of the model that served it.
+    async fn generate(&self, request_body: &Value) -> Result<Value> {
+        let estimated_tokens = request_body.to_string().len() as u64 / 4;
+        let (response_json, endpoint) = self.pool.generate_content(request_body, estimated_tokens).await?;
+        self.usage
+            .lock()
+            .unwrap()
+            .entry(endpoint.model)
+            .or_default()
+            .add(&Self::parse_usage(&response_json));
+        Ok(response_json)
     }
 }
 
@@ -265,4 +693,225 @@ mod base64 {
 
         result
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::RateLimitSettings;
+    use serde_json::json;
+    use wiremock::matchers::{header, method, path, query_param_is_missing};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn service(settings: &ProviderSettings) -> TranscriptionService {
+        let rate_limiter = Arc::new(RateLimiter::new(RateLimitSettings::default()));
+        TranscriptionService::new("secret-key".to_string(), "test-model".to_string(), settings, rate_limiter).unwrap()
+    }
+
+    fn endpoint(server: &MockServer, profile_id: &str, model: &str) -> ProviderEndpoint {
+        ProviderEndpoint {
+            profile_id: profile_id.to_string(),
+            api_key: format!("{}-key", profile_id),
+            api_root: format!("{}/v1beta", server.uri()),
+            model: model.to_string(),
+            rate_limiter: Arc::new(RateLimiter::new(RateLimitSettings::default())),
+        }
+    }
+
+    fn candidate(tokens: &[(&str, f64)]) -> Value {
+        let chosen: Vec<Value> = tokens
+            .iter()
+            .map(|(token, logprob)| json!({ "token": token, "logProbability": logprob }))
+            .collect();
+        json!({ "logprobsResult": { "chosenCandidates": chosen } })
+    }
+
+    #[test]
+    fn logprobs_become_probabilities() {
+        assert_eq!(TranscriptionService::confidence_from_logprob(0.0), 1.0);
+        assert!((TranscriptionService::confidence_from_logprob(0.5f64.ln()) - 0.5).abs() < 1e-6);
+        assert_eq!(TranscriptionService::confidence_from_logprob(f64::NEG_INFINITY), 0.0);
+    }
+
+    #[test]
+    fn candidate_confidence_prefers_the_average_logprob() {
+        let mut value = candidate(&[("Hi", 0.0)]);
+        value["avgLogprobs"] = json!(0.25f64.ln());
+        let confidence = TranscriptionService::candidate_confidence(&value).unwrap();
+        assert!((confidence - 0.25).abs() < 1e-6);
+
+        let from_tokens = TranscriptionService::candidate_confidence(&candidate(&[("a", 0.0), ("b", 0.25f64.ln() * 2.0)]));
+        assert!((from_tokens.unwrap() - 0.25).abs() < 1e-6);
+
+        assert_eq!(TranscriptionService::candidate_confidence(&json!({})), None);
+    }
+
+    #[test]
+    fn word_confidence_is_the_joint_probability_of_its_tokens() {
+        let half = 0.5f64.ln();
+        let words = TranscriptionService::word_confidences(&candidate(&[
+            ("Tras", half),
+            ("crivi", half),
+            (" ora", 0.0),
+            (" ", half),
+            (" fine", half),
+        ]));
+
+        let words: Vec<(&str, f32)> = words.iter().map(|w| (w.text.as_str(), w.confidence)).collect();
+        assert_eq!(words.len(), 3);
+        assert_eq!(words[0].0, "Trascrivi");
+        assert!((words[0].1 - 0.25).abs() < 1e-6);
+        assert_eq!(words[1], ("ora", 1.0));
+        assert_eq!(words[2].0, "fine");
+    }
+
+    #[test]
+    fn client_settings_are_checked() {
+        let settings = |proxy_url: Option<&str>, ca_bundle_path: Option<&str>| ProviderSettings {
+            proxy_url: proxy_url.map(|p| p.to_string()),
+            ca_bundle_path: ca_bundle_path.map(|p| p.to_string()),
+            ..Default::default()
+        };
+
+        assert!(TranscriptionService::build_client(&settings(Some("http://proxy.local:3128"), None)).is_ok());
+        assert!(TranscriptionService::build_client(&settings(Some("  "), Some(""))).is_ok());
+
+        let error = TranscriptionService::build_client(&settings(Some("not a url"), None)).unwrap_err();
+        assert!(error.to_string().starts_with("Invalid proxy URL not a url"));
+
+        let error = TranscriptionService::build_client(&settings(None, Some("/missing/ca.pem"))).unwrap_err();
+        assert!(error.to_string().starts_with("Failed to read CA bundle /missing/ca.pem"));
+    }
+
+    #[tokio::test]
+    async fn api_key_is_sent_in_a_header_only() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1beta/models"))
+            .and(header(API_KEY_HEADER, "secret-key"))
+            .and(query_param_is_missing("key"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "models": [] })))
+            .mount(&server)
+            .await;
+
+        let service = service(&ProviderSettings { base_url: server.uri(), ..Default::default() });
+        let validation = service.validate_api_key().await;
+
+        assert_eq!(validation.status, ApiKeyStatus::Valid);
+        let requests = server.received_requests().await.unwrap();
+        assert!(!requests[0].url.as_str().contains("secret-key"));
+    }
+
+    #[tokio::test]
+    async fn validation_maps_error_responses() {
+        let cases = [
+            (400, ApiKeyStatus::Invalid),
+            (401, ApiKeyStatus::Invalid),
+            (403, ApiKeyStatus::PermissionDenied),
+            (429, ApiKeyStatus::QuotaExceeded),
+            (500, ApiKeyStatus::UnexpectedResponse),
+        ];
+
+        for (code, expected) in cases {
+            let server = MockServer::start().await;
+            Mock::given(method("GET"))
+                .respond_with(ResponseTemplate::new(code).set_body_json(json!({ "error": { "message": "nope" } })))
+                .mount(&server)
+                .await;
+
+            let service = service(&ProviderSettings { base_url: server.uri(), ..Default::default() });
+            let validation = service.validate_api_key().await;
+
+            assert_eq!(validation.status, expected, "status {}", code);
+            assert_eq!(validation.message.as_deref(), Some("nope"));
+        }
+    }
+
+    #[tokio::test]
+    async fn validation_reports_timeouts_as_network_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(3)))
+            .mount(&server)
+            .await;
+
+        let settings = ProviderSettings { base_url: server.uri(), timeout_secs: 1, ..Default::default() };
+        let validation = service(&settings).validate_api_key().await;
+
+        assert_eq!(validation.status, ApiKeyStatus::NetworkError);
+    }
+
+    #[tokio::test]
+    async fn quota_errors_fail_over_and_bill_the_serving_model() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1beta/models/model-a:generateContent"))
+            .respond_with(ResponseTemplate::new(429).set_body_string(r#"{"error":{"status":"RESOURCE_EXHAUSTED"}}"#))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v1beta/models/model-b:generateContent"))
+            .and(header(API_KEY_HEADER, "backup-key"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "candidates": [],
+                "usageMetadata": { "promptTokenCount": 10, "candidatesTokenCount": 5 }
+            })))
+            .mount(&server)
+            .await;
+
+        let endpoints = vec![endpoint(&server, "primary", "model-a"), endpoint(&server, "backup", "model-b")];
+        let service = TranscriptionService::with_failover(endpoints, &ProviderSettings::default()).unwrap();
+
+        let (_, served_by) = service.pool.generate_content(&json!({}), 0).await.unwrap();
+        assert_eq!(served_by.profile_id, "backup");
+        assert_eq!(service.pool.active_endpoint().model, "model-b");
+
+        service.generate(&json!({})).await.unwrap();
+        let usage = service.take_usage();
+        assert_eq!(usage.keys().collect::<Vec<_>>(), vec!["model-b"]);
+        assert_eq!((usage["model-b"].input_tokens, usage["model-b"].output_tokens), (10, 5));
+
+        // The exhausted profile is not retried once the pool moved on
+        let primary_calls = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter(|r| r.url.path().contains("model-a"))
+            .count();
+        assert_eq!(primary_calls, 1);
+    }
+
+    #[tokio::test]
+    async fn failover_stops_after_the_last_profile() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(403).set_body_string("API_KEY_INVALID"))
+            .mount(&server)
+            .await;
+
+        let endpoints = vec![endpoint(&server, "primary", "model-a"), endpoint(&server, "backup", "model-b")];
+        let service = TranscriptionService::with_failover(endpoints, &ProviderSettings::default()).unwrap();
+
+        let error = service.generate(&json!({})).await.unwrap_err();
+        assert_eq!(error.to_string(), "API error: API_KEY_INVALID");
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+        assert!(service.take_usage().is_empty());
+    }
+
+    #[tokio::test]
+    async fn other_errors_do_not_fail_over() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(400).set_body_string("bad request"))
+            .mount(&server)
+            .await;
+
+        let endpoints = vec![endpoint(&server, "primary", "model-a"), endpoint(&server, "backup", "model-b")];
+        let service = TranscriptionService::with_failover(endpoints, &ProviderSettings::default()).unwrap();
+
+        assert!(service.generate(&json!({})).await.is_err());
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+        assert_eq!(service.pool.active_endpoint().model, "model-a");
+    }
+}
@@ -0,0 +1,319 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use super::analysis::{analysis_segments, AnalysisError, ANALYSIS_WINDOW_CHARS};
+use super::TranscriptionService;
+use crate::models::{Citation, QueryAnswer, Transcription, TranscriptionSegment};
+use crate::search::Bm25;
+
+/// Segments on each side of a retrieved segment that are sent along with it.
+const CONTEXT_NEIGHBOURS: usize = 1;
+
+#[derive(Debug, Deserialize)]
+struct AnswerDraft {
+    answer: String,
+    found: bool,
+    citations: Vec<usize>,
+}
+
+fn answer_schema() -> Value {
+    json!({
+        "type": "OBJECT",
+        "properties": {
+            "answer": { "type": "STRING", "description": "Answer based only on the segments" },
+            "found": { "type": "BOOLEAN", "description": "Whether the segments contain the answer" },
+            "citations": {
+                "type": "ARRAY",
+                "items": { "type": "INTEGER" },
+                "description": "Numbers of the segments the answer is based on"
+            }
+        },
+        "required": ["answer", "found", "citations"],
+        "propertyOrdering": ["answer", "found", "citations"]
+    })
+}
+
+fn validate_answer(draft: &AnswerDraft, provided: &BTreeSet<usize>) -> Vec<String> {
+    let mut issues = Vec::new();
+
+    if draft.answer.trim().is_empty() {
+        issues.push("the answer is empty".to_string());
+    }
+    if draft.found && draft.citations.is_empty() {
+        issues.push("an answer that was found must cite at least one segment".to_string());
+    }
+    for citation in &draft.citations {
+        if !provided.contains(citation) {
+            issues.push(format!("segment {} was not provided and cannot be cited", citation));
+        }
+    }
+
+    issues
+}
+
+fn keywords_schema() -> Value {
+    json!({ "type": "ARRAY", "items": { "type": "STRING" } })
+}
+
+/// Picks the best-matching segments and their neighbours until `budget`
+/// characters are used.
+fn retrieve(segments: &[TranscriptionSegment], query: &str, budget: usize) -> BTreeSet<usize> {
+    let passages = (0..segments.len()).map(|i| {
+        let start = i.saturating_sub(CONTEXT_NEIGHBOURS);
+        let end = (i + CONTEXT_NEIGHBOURS + 1).min(segments.len());
+        segments[start..end].iter().map(|s| s.text.as_str()).collect::<Vec<_>>().join(" ")
+    });
+    let index = Bm25::new(passages);
+
+    let mut selected = BTreeSet::new();
+    let mut used = 0;
+
+    for (i, _) in index.rank(query) {
+        let start = i.saturating_sub(CONTEXT_NEIGHBOURS);
+        let end = (i + CONTEXT_NEIGHBOURS + 1).min(segments.len());
+        let added: usize = (start..end)
+            .filter(|j| !selected.contains(j))
+            .map(|j| segments[j].text.len())
+            .sum();
+
+        if used + added > budget {
+            if used > 0 {
+                break;
+            }
+            // A single oversized passage still gets its centre segment
+            selected.insert(i);
+            break;
+        }
+
+        used += added;
+        selected.extend(start..end);
+    }
+
+    selected
+}
+
+impl TranscriptionService {
+    /// Answers `question` from the transcription and cites the segments the
+    /// answer is based on. Transcripts larger than one prompt are narrowed
+    /// down with BM25 retrieval over segments, using search terms suggested
+    /// by the model so that paraphrased questions still match.
+    pub async fn query(
+        &self,
+        transcription: &Transcription,
+        question: &str,
+    ) -> std::result::Result<QueryAnswer, AnalysisError> {
+        let from_text = !transcription.segments.iter().any(|s| !s.text.trim().is_empty());
+        let segments = analysis_segments(transcription);
+        let total_chars: usize = segments.iter().map(|s| s.text.len()).sum();
+
+        let provided: BTreeSet<usize> = if total_chars <= ANALYSIS_WINDOW_CHARS {
+            (0..segments.len()).collect()
+        } else {
+            let prompt = format!(
+                "List search keywords for finding the answer to this question in a transcript of
+                spoken language. Include the question's key terms, synonyms and words a speaker
+                would likely use when talking about it.
+
+                Question: {}",
+                question
+            );
+            let keywords = self
+                .generate_structured(&prompt, keywords_schema(), |_: &Vec<String>| Vec::new())
+                .await?
+                .value;
+
+            let query = format!("{} {}", question, keywords.join(" "));
+            retrieve(&segments, &query, ANALYSIS_WINDOW_CHARS)
+        };
+
+        if provided.is_empty() {
+            return Ok(QueryAnswer {
+                question: question.to_string(),
+                answer: "Nothing in the transcription relates to this question.".to_string(),
+                found: false,
+                citations: Vec::new(),
+            });
+        }
+
+        let listing = provided
+            .iter()
+            .map(|&i| {
+                let s = &segments[i];
+                format!("[{}] ({:.1}s-{:.1}s) {}", i, s.start_time, s.end_time, s.text.trim())
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let prompt = format!(
+            "Answer the question using only the numbered transcription segments below and cite
+            the numbers of the segments the answer is based on. If the segments do not contain
+            the answer, say so, set found to false and cite nothing.
+
+            Question: {}
+
+            Segments:
+            {}",
+            question, listing
+        );
+
+        let draft = self
+            .generate_structured(&prompt, answer_schema(), |draft: &AnswerDraft| validate_answer(draft, &provided))
+            .await?
+            .value;
+
+        let mut cited = draft.citations;
+        cited.sort_unstable();
+        cited.dedup();
+
+        let citations = cited
+            .into_iter()
+            .map(|i| {
+                let segment = &segments[i];
+                Citation {
+                    segment_id: (!from_text).then(|| segment.id.clone()),
+                    start_time: segment.start_time,
+                    end_time: segment.end_time,
+                    text: segment.text.clone(),
+                }
+            })
+            .collect();
+
+        Ok(QueryAnswer {
+            question: question.to_string(),
+            answer: draft.answer.trim().to_string(),
+            found: draft.found,
+            citations,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ProviderSettings, RateLimitSettings};
+    use crate::transcription::rate_limit::RateLimiter;
+    use std::sync::Arc;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn segment(index: usize, text: &str) -> TranscriptionSegment {
+        TranscriptionSegment {
+            id: format!("segment-{}", index),
+            text: text.to_string(),
+            start_time: index as f64 * 10.0,
+            end_time: (index + 1) as f64 * 10.0,
+            confidence: 0.9,
+            words: Vec::new(),
+            profile_id: None,
+            speaker: None,
+        }
+    }
+
+    fn meeting() -> Vec<TranscriptionSegment> {
+        [
+            "Good morning everyone.",
+            "Let us start with the agenda.",
+            "The marketing budget grows by ten percent.",
+            "That budget covers the autumn campaign.",
+            "Next we talk about hiring.",
+            "We need two engineers.",
+            "Thanks, see you next week.",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, text)| segment(i, text))
+        .collect()
+    }
+
+    #[test]
+    fn retrieval_picks_matching_segments_with_their_neighbours() {
+        let segments = meeting();
+
+        let selected: Vec<usize> = retrieve(&segments, "budget", 10_000).into_iter().collect();
+        assert_eq!(selected, vec![0, 1, 2, 3, 4, 5]);
+
+        let selected: Vec<usize> = retrieve(&segments, "engineers", 10_000).into_iter().collect();
+        assert_eq!(selected, vec![3, 4, 5, 6]);
+
+        assert!(retrieve(&segments, "quarterly revenue", 10_000).is_empty());
+    }
+
+    #[test]
+    fn retrieval_stays_within_the_budget() {
+        let segments = meeting();
+        let chars = |selected: &BTreeSet<usize>| selected.iter().map(|&i| segments[i].text.len()).sum::<usize>();
+
+        let budget = segments[1..4].iter().map(|s| s.text.len()).sum();
+        let selected = retrieve(&segments, "budget", budget);
+        assert!(chars(&selected) <= budget);
+        assert!(selected.contains(&2) || selected.contains(&3));
+
+        // Too small for any passage: only the centre of the best one
+        let selected = retrieve(&segments, "budget", 5);
+        assert_eq!(selected.len(), 1);
+        assert!(selected.contains(&2) || selected.contains(&3));
+    }
+
+    #[test]
+    fn answers_may_only_cite_provided_segments() {
+        let provided: BTreeSet<usize> = [1, 2].into_iter().collect();
+        let draft = |answer: &str, found: bool, citations: Vec<usize>| AnswerDraft {
+            answer: answer.to_string(),
+            found,
+            citations,
+        };
+
+        assert!(validate_answer(&draft("Ten percent", true, vec![2]), &provided).is_empty());
+        assert!(validate_answer(&draft("Not mentioned", false, vec![]), &provided).is_empty());
+        assert_eq!(validate_answer(&draft(" ", true, vec![]), &provided), vec![
+            "the answer is empty",
+            "an answer that was found must cite at least one segment",
+        ]);
+        assert_eq!(
+            validate_answer(&draft("Ten percent", true, vec![5]), &provided),
+            vec!["segment 5 was not provided and cannot be cited"]
+        );
+    }
+
+    #[tokio::test]
+    async fn answers_cite_segments_by_id_and_time() {
+        let server = MockServer::start().await;
+        let answer = json!({ "answer": " Ten percent. ", "found": true, "citations": [3, 2, 2] });
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "candidates": [{ "content": { "parts": [{ "text": answer.to_string() }] } }]
+            })))
+            .mount(&server)
+            .await;
+
+        let settings = ProviderSettings { base_url: server.uri(), ..Default::default() };
+        let rate_limiter = Arc::new(RateLimiter::new(RateLimitSettings::default()));
+        let service = TranscriptionService::new("key".to_string(), "test-model".to_string(), &settings, rate_limiter)
+            .unwrap();
+        let transcription: Transcription = serde_json::from_value(json!({
+            "id": "t1",
+            "title": "Meeting",
+            "created_at": "2024-05-01T10:00:00Z",
+            "duration": 70,
+            "chapters": [],
+            "raw_text": "",
+            "status": "Completed",
+            "segments": meeting()
+        }))
+        .unwrap();
+
+        let answer = service.query(&transcription, "How large is the budget?").await.unwrap();
+
+        assert_eq!(answer.answer, "Ten percent.");
+        assert!(answer.found);
+        let citations: Vec<(Option<&str>, f64)> = answer
+            .citations
+            .iter()
+            .map(|c| (c.segment_id.as_deref(), c.start_time))
+            .collect();
+        assert_eq!(citations, vec![(Some("segment-2"), 20.0), (Some("segment-3"), 30.0)]);
+
+        // Short transcripts are sent whole, without a keyword request
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+}
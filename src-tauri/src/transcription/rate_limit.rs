@@ -0,0 +1,178 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+use crate::models::RateLimitSettings;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Sliding one-minute window over requests and estimated tokens, plus a cap
+/// on requests in flight. Shared by every caller using the same credentials.
+pub struct RateLimiter {
+    settings: RateLimitSettings,
+    window: Mutex<VecDeque<(Instant, u64)>>,
+    concurrency: Arc<Semaphore>,
+}
+
+impl std::fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RateLimiter").field("settings", &self.settings).finish()
+    }
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateLimitSettings) -> Self {
+        let clamped = settings.clamped();
+        if clamped != settings {
+            eprintln!("RateLimiter: Raising zero limits in {:?} to 1", settings);
+        }
+        let settings = clamped;
+        let concurrency = Arc::new(Semaphore::new(settings.max_concurrent_requests));
+
+        Self {
+            settings,
+            window: Mutex::new(VecDeque::new()),
+            concurrency,
+        }
+    }
+
+    pub fn max_concurrent_requests(&self) -> usize {
+        self.settings.max_concurrent_requests
+    }
+
+    /// Waits for a concurrency slot and for room in the current window. The
+    /// returned permit releases the slot when dropped. A single request larger
+    /// than the token budget is let through once the window is empty.
+    pub async fn acquire(&self, estimated_tokens: u64) -> OwnedSemaphorePermit {
+        let permit = Arc::clone(&self.concurrency)
+            .acquire_owned()
+            .await
+            .expect("rate limiter semaphore is never closed");
+
+        loop {
+            let wait = {
+                let mut window = self.window.lock().unwrap();
+                let now = Instant::now();
+                while window.front().is_some_and(|(at, _)| now.duration_since(*at) >= WINDOW) {
+                    window.pop_front();
+                }
+
+                let requests = window.len() as u64;
+                let tokens: u64 = window.iter().map(|(_, t)| t).sum();
+                let within_requests = requests < self.settings.requests_per_minute as u64;
+                let within_tokens = window.is_empty() || tokens + estimated_tokens <= self.settings.tokens_per_minute;
+
+                if within_requests && within_tokens {
+                    window.push_back((now, estimated_tokens));
+                    return permit;
+                }
+
+                // Room frees up when the oldest entry leaves the window
+                window
+                    .front()
+                    .map(|(at, _)| WINDOW.saturating_sub(now.duration_since(*at)))
+                    .unwrap_or_default()
+            };
+
+            tokio::time::sleep(wait.max(Duration::from_millis(10))).await;
+        }
+    }
+}
+
+/// One limiter per credential profile, so every service built for the same
+/// key draws from the same budget.
+#[derive(Default)]
+pub struct RateLimiterRegistry {
+    limiters: Mutex<HashMap<String, Arc<RateLimiter>>>,
+}
+
+impl RateLimiterRegistry {
+    /// Returns the limiter for `profile_id`, replacing it when its settings changed.
+    pub fn limiter(&self, profile_id: &str, settings: &RateLimitSettings) -> Arc<RateLimiter> {
+        let mut limiters = self.limiters.lock().unwrap();
+
+        match limiters.get(profile_id) {
+            Some(limiter) if limiter.settings == settings.clamped() => Arc::clone(limiter),
+            _ => {
+                let limiter = Arc::new(RateLimiter::new(settings.clone()));
+                limiters.insert(profile_id.to_string(), Arc::clone(&limiter));
+                limiter
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(requests_per_minute: u32, tokens_per_minute: u64, max_concurrent_requests: usize) -> RateLimiter {
+        RateLimiter::new(RateLimitSettings { requests_per_minute, tokens_per_minute, max_concurrent_requests })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn requests_wait_for_room_in_the_window() {
+        let limiter = limiter(2, 1_000_000, 4);
+        let start = Instant::now();
+
+        drop(limiter.acquire(0).await);
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        drop(limiter.acquire(0).await);
+        assert_eq!(start.elapsed(), Duration::from_secs(10));
+
+        // Room frees up when the first request leaves the window
+        drop(limiter.acquire(0).await);
+        assert_eq!(start.elapsed(), WINDOW);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tokens_wait_for_room_in_the_window() {
+        let limiter = limiter(100, 100, 4);
+        let start = Instant::now();
+
+        drop(limiter.acquire(60).await);
+        drop(limiter.acquire(40).await);
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        drop(limiter.acquire(1).await);
+        assert_eq!(start.elapsed(), WINDOW);
+
+        // Larger than the whole budget: let through once the window is empty
+        drop(limiter.acquire(500).await);
+        assert_eq!(start.elapsed(), WINDOW * 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn permits_bound_requests_in_flight() {
+        let limiter = limiter(100, 1_000_000, 1);
+
+        let first = limiter.acquire(0).await;
+        let blocked = tokio::time::timeout(Duration::from_secs(5), limiter.acquire(0)).await;
+        assert!(blocked.is_err());
+
+        drop(first);
+        let second = tokio::time::timeout(Duration::from_secs(5), limiter.acquire(0)).await;
+        assert!(second.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn zero_limits_still_let_requests_through() {
+        let limiter = limiter(0, 0, 0);
+        assert_eq!(limiter.max_concurrent_requests(), 1);
+
+        let permit = tokio::time::timeout(Duration::from_secs(1), limiter.acquire(10)).await;
+        assert!(permit.is_ok());
+    }
+
+    #[test]
+    fn registry_reuses_limiters_until_settings_change() {
+        let registry = RateLimiterRegistry::default();
+        let zero = RateLimitSettings { requests_per_minute: 0, tokens_per_minute: 0, max_concurrent_requests: 0 };
+
+        let first = registry.limiter("default", &zero);
+        assert!(Arc::ptr_eq(&first, &registry.limiter("default", &zero)));
+        assert!(!Arc::ptr_eq(&first, &registry.limiter("default", &RateLimitSettings::default())));
+        assert!(!Arc::ptr_eq(&first, &registry.limiter("work", &zero)));
+    }
+}
@@ -0,0 +1,66 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use super::analysis::{analysis_segments, join_text, AnalysisError};
+use super::TranscriptionService;
+use crate::editing::{validate_title, MAX_TITLE_CHARS};
+use crate::models::Transcription;
+
+/// Characters from the start of the text sent along with the chapters and
+/// summary, which usually say enough about what the recording is about.
+const TITLE_CONTEXT_CHARS: usize = 8_000;
+
+#[derive(Debug, Deserialize)]
+struct TitleDraft {
+    title: String,
+}
+
+fn title_schema() -> Value {
+    json!({
+        "type": "OBJECT",
+        "properties": {
+            "title": { "type": "STRING", "description": "Short descriptive title" }
+        },
+        "required": ["title"]
+    })
+}
+
+impl TranscriptionService {
+    /// A short title describing what the transcription is about.
+    pub async fn generate_title(&self, transcription: &Transcription) -> std::result::Result<String, AnalysisError> {
+        let text = join_text(&analysis_segments(transcription));
+        let excerpt = match text.char_indices().nth(TITLE_CONTEXT_CHARS) {
+            Some((end, _)) => format!("{} …", &text[..end]),
+            None => text,
+        };
+
+        let mut context = Vec::new();
+        if let Some(summary) = &transcription.notes.executive_summary {
+            context.push(format!("Summary:\n{}", summary));
+        }
+        if !transcription.chapters.is_empty() {
+            let chapters: Vec<&str> = transcription.chapters.iter().map(|c| c.title.as_str()).collect();
+            context.push(format!("Chapters:\n{}", chapters.join("\n")));
+        }
+        context.push(format!("Transcript:\n{}", excerpt));
+
+        let prompt = format!(
+            "Write a title for this transcription of a recording. It should say what the
+            recording is about in a few words, at most {} characters, in the language of the
+            transcript, without quotes and without words like \"transcription\" or \"recording\".
+
+            {}",
+            MAX_TITLE_CHARS / 2,
+            context.join("\n\n")
+        );
+
+        let draft = self
+            .generate_structured(&prompt, title_schema(), |draft: &TitleDraft| {
+                validate_title(&draft.title).err().map(|e| e.to_string()).into_iter().collect()
+            })
+            .await?
+            .value;
+
+        // Already valid; this only drops stray quotes
+        Ok(validate_title(draft.title.trim_matches(|c| c == '"' || c == '\'')).unwrap_or(draft.title))
+    }
+}
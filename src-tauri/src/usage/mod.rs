@@ -0,0 +1,172 @@
+use chrono::{Datelike, NaiveDate};
+use std::collections::{BTreeMap, HashMap};
+use crate::models::{BudgetAlert, DailyUsage, ModelPrice, ModelUsage, TokenUsage, UsageLedger, UsageReport};
+
+/// Share of the monthly budget at which a warning alert is raised.
+pub const BUDGET_WARNING_RATIO: f64 = 0.8;
+
+pub fn record(ledger: &mut UsageLedger, date: NaiveDate, model: &str, usage: &TokenUsage) {
+    ledger
+        .days
+        .entry(date.format("%Y-%m-%d").to_string())
+        .or_default()
+        .entry(model.to_string())
+        .or_default()
+        .add(usage);
+}
+
+/// Looks up the price for `model`, falling back to the longest configured
+/// prefix so that versioned ids like `gemini-2.5-flash-001` are still priced.
+pub fn price_for<'a>(prices: &'a HashMap<String, ModelPrice>, model: &str) -> Option<&'a ModelPrice> {
+    prices.get(model).or_else(|| {
+        prices
+            .iter()
+            .filter(|(id, _)| model.starts_with(id.as_str()))
+            .max_by_key(|(id, _)| id.len())
+            .map(|(_, price)| price)
+    })
+}
+
+pub fn cost(prices: &HashMap<String, ModelPrice>, model: &str, usage: &TokenUsage) -> f64 {
+    price_for(prices, model).map(|price| usage.cost(price)).unwrap_or(0.0)
+}
+
+pub fn budget_alert(cost: f64, budget: Option<f64>) -> Option<BudgetAlert> {
+    let budget = budget.filter(|b| *b > 0.0)?;
+
+    if cost >= budget {
+        Some(BudgetAlert::Exceeded)
+    } else if cost >= budget * BUDGET_WARNING_RATIO {
+        Some(BudgetAlert::Warning)
+    } else {
+        None
+    }
+}
+
+/// Builds the report for `month` (`YYYY-MM`).
+pub fn report(
+    ledger: &UsageLedger,
+    month: &str,
+    prices: &HashMap<String, ModelPrice>,
+    monthly_budget: Option<f64>,
+) -> UsageReport {
+    let mut usage = TokenUsage::default();
+    let mut total_cost = 0.0;
+    let mut models: BTreeMap<String, ModelUsage> = BTreeMap::new();
+    let mut days = Vec::new();
+
+    for (date, per_model) in ledger.days.range(month.to_string()..) {
+        if !date.starts_with(month) {
+            break;
+        }
+
+        let mut day_usage = TokenUsage::default();
+        let mut day_cost = 0.0;
+
+        for (model, model_usage) in per_model {
+            let model_cost = cost(prices, model, model_usage);
+            day_usage.add(model_usage);
+            day_cost += model_cost;
+
+            let entry = models.entry(model.clone()).or_insert_with(|| ModelUsage {
+                model: model.clone(),
+                usage: TokenUsage::default(),
+                cost: 0.0,
+            });
+            entry.usage.add(model_usage);
+            entry.cost += model_cost;
+        }
+
+        usage.add(&day_usage);
+        total_cost += day_cost;
+        days.push(DailyUsage {
+            date: date.clone(),
+            usage: day_usage,
+            cost: day_cost,
+        });
+    }
+
+    UsageReport {
+        month: month.to_string(),
+        usage,
+        cost: total_cost,
+        models: models.into_values().collect(),
+        days,
+        monthly_budget,
+        budget_alert: budget_alert(total_cost, monthly_budget),
+    }
+}
+
+pub fn current_month() -> String {
+    let today = chrono::Utc::now().date_naive();
+    format!("{:04}-{:02}", today.year(), today.month())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(input: u64, audio: u64, output: u64) -> TokenUsage {
+        TokenUsage { input_tokens: input, audio_tokens: audio, output_tokens: output, requests: 1 }
+    }
+
+    fn prices() -> HashMap<String, ModelPrice> {
+        HashMap::from([
+            (
+                "gemini-2.5-flash".to_string(),
+                ModelPrice { input_per_million: 0.30, audio_input_per_million: 1.00, output_per_million: 2.50 },
+            ),
+            (
+                "gemini-2.5".to_string(),
+                ModelPrice { input_per_million: 1.0, audio_input_per_million: 1.0, output_per_million: 1.0 },
+            ),
+        ])
+    }
+
+    fn date(day: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(day, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn audio_tokens_are_priced_separately_from_text_input() {
+        let price = &prices()["gemini-2.5-flash"];
+        let cost = usage(1_000_000, 400_000, 1_000_000).cost(price);
+        assert!((cost - (0.6 * 0.30 + 0.4 * 1.00 + 2.50)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn versioned_models_use_the_longest_matching_prefix() {
+        let prices = prices();
+        assert_eq!(price_for(&prices, "gemini-2.5-flash-001").unwrap().output_per_million, 2.50);
+        assert_eq!(price_for(&prices, "gemini-2.5-pro").unwrap().output_per_million, 1.0);
+        assert!(price_for(&prices, "other-model").is_none());
+        assert_eq!(cost(&prices, "other-model", &usage(1_000_000, 0, 0)), 0.0);
+    }
+
+    #[test]
+    fn budget_alerts_start_at_the_warning_ratio() {
+        assert_eq!(budget_alert(7.9, Some(10.0)), None);
+        assert_eq!(budget_alert(8.0, Some(10.0)), Some(BudgetAlert::Warning));
+        assert_eq!(budget_alert(10.0, Some(10.0)), Some(BudgetAlert::Exceeded));
+        assert_eq!(budget_alert(100.0, None), None);
+        assert_eq!(budget_alert(100.0, Some(0.0)), None);
+    }
+
+    #[test]
+    fn report_covers_only_the_requested_month() {
+        let mut ledger = UsageLedger::default();
+        record(&mut ledger, date("2024-04-30"), "gemini-2.5-flash", &usage(1_000_000, 0, 0));
+        record(&mut ledger, date("2024-05-01"), "gemini-2.5-flash", &usage(1_000_000, 0, 0));
+        record(&mut ledger, date("2024-05-01"), "gemini-2.5-flash", &usage(1_000_000, 0, 0));
+        record(&mut ledger, date("2024-05-02"), "gemini-2.5-pro", &usage(0, 0, 1_000_000));
+        record(&mut ledger, date("2024-06-01"), "gemini-2.5-flash", &usage(1_000_000, 0, 0));
+
+        let report = report(&ledger, "2024-05", &prices(), Some(1.0));
+
+        assert_eq!(report.days.len(), 2);
+        assert_eq!(report.usage.requests, 3);
+        assert_eq!(report.models.len(), 2);
+        assert!((report.cost - (2.0 * 0.30 + 1.0)).abs() < 1e-9);
+        assert_eq!(report.budget_alert, Some(BudgetAlert::Exceeded));
+    }
+}